@@ -1,11 +1,7 @@
 use rspression::{DefaultEnvironment, Environment, RspRunner, Value};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut srcs = Vec::new();
-    srcs.push("x = a + b * c");
-    srcs.push("a = m + n");
-    srcs.push("b = a * 2");
-    srcs.push("c = n + w + b");
+    let srcs = vec!["x = a + b * c", "a = m + n", "b = a * 2", "c = n + w + b"];
 
     let mut runner = RspRunner::new();
     let mut env = DefaultEnvironment::new();
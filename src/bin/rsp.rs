@@ -0,0 +1,62 @@
+//! 命令行工具，用于查看缓存中取出的字节码，从文本清单汇编出字节码，或者去掉字节码中的调试信息
+//!
+//! ```text
+//! rsp disasm <chunk-file>
+//! rsp asm <listing-file> <chunk-file>
+//! rsp strip <chunk-file> <output-file>
+//! ```
+
+use std::process::ExitCode;
+
+use rspression::Chunk;
+use rspression::chunk::{assemble, disassemble};
+
+const USAGE: &str = "usage: rsp disasm <chunk-file>
+       rsp asm <listing-file> <chunk-file>
+       rsp strip <chunk-file> <output-file>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["disasm", path] => disasm(path),
+        ["asm", listing, path] => asm(listing, path),
+        ["strip", input, output] => strip(input, output),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn disasm(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(path)?;
+    let chunk = Chunk::from_bytes(&bytes)?;
+    print!("{}", disassemble(&chunk)?);
+    Ok(())
+}
+
+fn asm(listing: &str, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let source = std::fs::read_to_string(listing)?;
+    let chunk = assemble(&source)?;
+    std::fs::write(path, chunk.to_bytes())?;
+    Ok(())
+}
+
+fn strip(input: &str, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut chunk = Chunk::from_bytes(&std::fs::read(input)?)?;
+    chunk.strip_debug_info();
+    std::fs::write(output, chunk.to_bytes())?;
+    Ok(())
+}
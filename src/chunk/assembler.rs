@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use crate::{ErrorKind, RspError, RspResult, span::Span, values::Value, vm::OpCode};
+
+use super::{Chunk, ChunkWriter};
+
+/// 汇编文本形式的指令清单，生成字节码。
+///
+/// 语法与 [`disassemble`](super::disassemble) 的输出兼容，反汇编的结果可以直接重新汇编：
+///
+/// ```text
+/// ; 注释
+/// .const rate 0.5          ; 命名常量
+/// .code
+///     Begin 0
+///     GetGlobal "price"    ; 字面量操作数会自动加入常量池，变量名会记入变量表
+///     Constant rate
+///     Multiply
+///     JumpIfFalse done     ; 跳转目标可以是标签
+/// done:
+///     End
+///     Exit
+/// ```
+///
+/// - 行首的数字（反汇编输出中的偏移）会被忽略
+/// - 常量类指令的数字操作数是常量下标，跳转指令的数字操作数是相对偏移，`Begin` 的操作数是公式序号
+/// - 合并指令依次写出各个操作数，最后是二元运算的指令名，如 `GlobalConstOp "x" 10 Greater`
+/// - `.constants` 段中的 `<下标> <值>` 按顺序定义常量，`.variables` 段中的 `<下标> <值>` 标记变量名
+/// - `.byte <n>` 写入一个原始字节，用于构造损坏的字节码
+pub fn assemble(source: &str) -> RspResult<Chunk> {
+    Assembler::default().assemble(source)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    Constants,
+    Variables,
+    Code,
+}
+
+enum Operand {
+    Int(i32),
+    Name(String),
+    Value(Value),
+}
+
+enum Item {
+    Op {
+        line: usize,
+        op: OpCode,
+        operands: Vec<Operand>,
+        offset: usize,
+    },
+    Byte(u8),
+}
+
+struct Assembler {
+    writer: ChunkWriter,
+    section: Section,
+    constants: HashMap<String, usize>,
+    labels: HashMap<String, usize>,
+    variables: Vec<String>,
+    items: Vec<Item>,
+    offset: usize,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self {
+            writer: ChunkWriter::new(),
+            section: Section::Code,
+            constants: HashMap::new(),
+            labels: HashMap::new(),
+            variables: Vec::new(),
+            items: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl Assembler {
+    fn assemble(mut self, source: &str) -> RspResult<Chunk> {
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            let tokens = tokenize(text, line)?;
+            self.parse_line(&tokens, line)?;
+        }
+
+        let items = std::mem::take(&mut self.items);
+        for item in items {
+            match item {
+                Item::Byte(b) => self.writer.write_byte(b),
+                Item::Op {
+                    line,
+                    op,
+                    operands,
+                    offset,
+                } => self.emit(line, op, operands, offset)?,
+            }
+        }
+        self.writer.set_variables(&self.variables)?;
+        Ok(self.writer.flush())
+    }
+
+    fn parse_line(&mut self, tokens: &[Token], line: usize) -> RspResult<()> {
+        let mut tokens = tokens;
+        // 反汇编输出中的偏移
+        if let Some(Token::Word(w)) = tokens.first()
+            && self.section == Section::Code
+            && w.chars().all(|c| c.is_ascii_digit())
+        {
+            tokens = &tokens[1..];
+        }
+        // 标签
+        if let Some(Token::Word(w)) = tokens.first()
+            && let Some(label) = w.strip_suffix(':')
+        {
+            if self.labels.insert(label.to_string(), self.offset).is_some() {
+                return Err(error(line, &format!("Duplicate label: {}", label)));
+            }
+            tokens = &tokens[1..];
+        }
+        let Some(first) = tokens.first() else {
+            return Ok(());
+        };
+
+        match first {
+            Token::Word(w) if w.starts_with('.') => self.parse_directive(w, &tokens[1..], line),
+            Token::Word(w) if self.section == Section::Code => {
+                let op = OpCode::from_name(w)
+                    .ok_or_else(|| error(line, &format!("Unknown instruction: {}", w)))?;
+                let operands = tokens[1..]
+                    .iter()
+                    .map(|token| parse_operand(token, line))
+                    .collect::<RspResult<Vec<_>>>()?;
+                let kinds = operand_kinds(op);
+                if operands.len() != kinds.len() {
+                    return Err(error(
+                        line,
+                        &format!("{:?} takes {} operands", op, kinds.len()),
+                    ));
+                }
+                self.items.push(Item::Op {
+                    line,
+                    op,
+                    operands,
+                    offset: self.offset,
+                });
+                self.offset += 1 + op.operand_size();
+                Ok(())
+            }
+            _ => self.parse_entry(tokens, line),
+        }
+    }
+
+    fn parse_directive(&mut self, name: &str, args: &[Token], line: usize) -> RspResult<()> {
+        match (name, args) {
+            (".constants", []) => self.section = Section::Constants,
+            (".variables", []) => self.section = Section::Variables,
+            (".code", []) => self.section = Section::Code,
+            (".const", [Token::Word(name), value]) => {
+                let value = parse_value(value, line)?;
+                let index = self.add_constant(value, line)?;
+                self.constants.insert(name.clone(), index);
+            }
+            (".byte", [Token::Word(w)]) => {
+                let b = parse_int(w)
+                    .and_then(|v| u8::try_from(v).ok())
+                    .ok_or_else(|| error(line, &format!("Invalid byte: {}", w)))?;
+                self.items.push(Item::Byte(b));
+                self.offset += 1;
+            }
+            _ => return Err(error(line, &format!("Invalid directive: {}", name))),
+        }
+        Ok(())
+    }
+
+    /// `.constants` 或 `.variables` 段中的 `<下标> <值>`
+    fn parse_entry(&mut self, tokens: &[Token], line: usize) -> RspResult<()> {
+        let (index, value) = match tokens {
+            [Token::Word(index), value] => match parse_int(index) {
+                Some(i) if i >= 0 => (i as usize, parse_value(value, line)?),
+                _ => return Err(error(line, &format!("Invalid constant index: {}", index))),
+            },
+            _ => return Err(error(line, "Expected <index> <value>")),
+        };
+        match self.section {
+            Section::Constants => {
+                if self.add_constant(value, line)? != index {
+                    return Err(error(
+                        line,
+                        &format!("Constant {} is out of order or duplicated", index),
+                    ));
+                }
+            }
+            _ => match value {
+                Value::String(name) => self.variables.push(name),
+                v => return Err(error(line, &format!("Invalid variable name: {}", v))),
+            },
+        }
+        Ok(())
+    }
+
+    fn emit(
+        &mut self,
+        line: usize,
+        op: OpCode,
+        operands: Vec<Operand>,
+        offset: usize,
+    ) -> RspResult<()> {
+        self.writer.write_code(op);
+        for (kind, operand) in operand_kinds(op).iter().zip(operands) {
+            let value = match (kind, operand) {
+                (Kind::Jump, Operand::Int(v)) | (Kind::Int, Operand::Int(v)) => v,
+                (Kind::Jump, Operand::Name(label)) => {
+                    let target = *self
+                        .labels
+                        .get(&label)
+                        .ok_or_else(|| error(line, &format!("Undefined label: {}", label)))?;
+                    target as i32 - (offset + 1 + op.operand_size()) as i32
+                }
+                (Kind::Jump, _) => {
+                    return Err(error(line, "Jump target must be a label or an offset"));
+                }
+                (Kind::Binary, Operand::Int(v)) => {
+                    let byte = u8::try_from(v)
+                        .map_err(|_| error(line, &format!("Invalid operator: {}", v)))?;
+                    self.writer.write_byte(byte);
+                    continue;
+                }
+                (Kind::Binary, Operand::Name(name)) => {
+                    let binary = OpCode::from_name(&name)
+                        .filter(OpCode::is_binary)
+                        .ok_or_else(|| error(line, &format!("Invalid operator: {}", name)))?;
+                    self.writer.write_code(binary);
+                    continue;
+                }
+                (Kind::Constant | Kind::Global, operand) => {
+                    let index =
+                        match operand {
+                            Operand::Int(v) => v,
+                            Operand::Name(name) => *self.constants.get(&name).ok_or_else(|| {
+                                error(line, &format!("Undefined constant: {}", name))
+                            })? as i32,
+                            Operand::Value(v) => self.add_constant(v, line)? as i32,
+                        };
+                    if let Kind::Global = kind
+                        && let Some(Value::String(name)) = usize::try_from(index)
+                            .ok()
+                            .and_then(|i| self.writer.constant(i))
+                        && !self.variables.contains(name)
+                    {
+                        self.variables.push(name.clone());
+                    }
+                    index
+                }
+                (kind, _) => {
+                    return Err(error(
+                        line,
+                        &format!("{:?} expects {} operand", op, kind.name()),
+                    ));
+                }
+            };
+            self.writer.write_int(value);
+        }
+        Ok(())
+    }
+
+    fn add_constant(&mut self, value: Value, line: usize) -> RspResult<usize> {
+        self.writer
+            .add_constant(value)
+            .map_err(|e| error(line, &e.to_string()))
+    }
+}
+
+/// 指令各个操作数的含义
+#[derive(Clone, Copy)]
+enum Kind {
+    /// 常量下标
+    Constant,
+    /// 变量名的常量下标，名称会记入变量表
+    Global,
+    /// 跳转偏移
+    Jump,
+    /// 整数，如 `Begin` 的公式序号
+    Int,
+    /// 合并指令中的二元运算，占一个字节
+    Binary,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Constant => "a constant",
+            Kind::Global => "a variable name",
+            Kind::Jump => "a jump target",
+            Kind::Int => "an integer",
+            Kind::Binary => "a binary operator",
+        }
+    }
+}
+
+fn operand_kinds(op: OpCode) -> &'static [Kind] {
+    use Kind::*;
+    match op {
+        OpCode::Constant | OpCode::GetProperty | OpCode::SetProperty | OpCode::Call => &[Constant],
+        OpCode::GetGlobal | OpCode::SetGlobal => &[Global],
+        OpCode::Jump | OpCode::JumpIfFalse => &[Jump],
+        OpCode::Begin => &[Int],
+        OpCode::GlobalGlobalOp => &[Global, Global, Binary],
+        OpCode::GlobalConstOp => &[Global, Constant, Binary],
+        OpCode::ConstOp => &[Constant, Binary],
+        _ => &[],
+    }
+}
+
+#[derive(Debug)]
+enum Token {
+    Word(String),
+    Str(String),
+}
+
+/// 切分一行文本，`;` 之后为注释，字符串按 Rust 的转义规则解析
+fn tokenize(text: &str, line: usize) -> RspResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => s.push(unescape(&mut chars, line)?),
+                    Some(c) => s.push(c),
+                    None => return Err(error(line, "Unterminated string")),
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else {
+            let mut w = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' {
+                    break;
+                }
+                w.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(w));
+        }
+    }
+    Ok(tokens)
+}
+
+fn unescape(chars: &mut std::iter::Peekable<std::str::Chars>, line: usize) -> RspResult<char> {
+    let c = match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('0') => '\0',
+        Some('\\') => '\\',
+        Some('"') => '"',
+        Some('\'') => '\'',
+        Some('u') => {
+            let mut hex = String::new();
+            if chars.next() != Some('{') {
+                return Err(error(line, "Invalid unicode escape"));
+            }
+            for c in chars.by_ref() {
+                if c == '}' {
+                    break;
+                }
+                hex.push(c);
+            }
+            u32::from_str_radix(&hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| error(line, &format!("Invalid unicode escape: {}", hex)))?
+        }
+        c => return Err(error(line, &format!("Invalid escape: {:?}", c))),
+    };
+    Ok(c)
+}
+
+fn parse_operand(token: &Token, line: usize) -> RspResult<Operand> {
+    match token {
+        Token::Str(s) => Ok(Operand::Value(Value::String(s.clone()))),
+        Token::Word(w) => {
+            if let Some(v) = parse_int(w) {
+                Ok(Operand::Int(v))
+            } else if w.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+')
+                || matches!(w.as_str(), "true" | "false" | "null")
+            {
+                Ok(Operand::Value(parse_value(token, line)?))
+            } else {
+                Ok(Operand::Name(w.clone()))
+            }
+        }
+    }
+}
+
+/// 解析常量的字面量：字符串、`true`/`false`/`null`、整数和浮点数
+fn parse_value(token: &Token, line: usize) -> RspResult<Value> {
+    let w = match token {
+        Token::Str(s) => return Ok(Value::String(s.clone())),
+        Token::Word(w) => w,
+    };
+    let value = match w.as_str() {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        "null" => Value::Null,
+        _ => match parse_int(w) {
+            Some(v) => Value::Integer(v),
+            None => Value::Double(
+                w.parse::<f64>()
+                    .map_err(|_| error(line, &format!("Invalid value: {}", w)))?,
+            ),
+        },
+    };
+    Ok(value)
+}
+
+fn parse_int(w: &str) -> Option<i32> {
+    match w.strip_prefix("0x") {
+        Some(hex) => i32::from_str_radix(hex, 16).ok(),
+        None => w.parse().ok(),
+    }
+}
+
+fn error(line: usize, message: &str) -> RspError {
+    RspError::ParseError {
+        span: Span::at_line(line),
+        message: message.to_string(),
+        kind: ErrorKind::Other,
+    }
+}
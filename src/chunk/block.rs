@@ -0,0 +1,145 @@
+use std::collections::{BTreeSet, HashSet};
+
+use crate::{ErrorKind, RspError, RspResult, vm::OpCode};
+
+use super::ChunkReader;
+
+/// 字节码中一个 `Begin`/`End` 之间的公式块
+#[derive(Clone, Debug)]
+pub struct CodeBlock {
+    /// 公式在源列表中的序号
+    pub index: i32,
+    /// `Begin` 指令的位置
+    pub start: usize,
+    /// `End` 指令之后的位置
+    pub end: usize,
+    pub reads: HashSet<String>,
+    pub writes: HashSet<String>,
+    /// 访问的变量路径，属性访问记为完整路径，如 `order.customer.tier`
+    pub paths: HashSet<String>,
+}
+
+/// 字节码中读取和赋值的全局变量，均按名称排序
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkVariables {
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+    /// 访问的变量路径，包含属性访问的完整路径
+    pub paths: Vec<String>,
+}
+
+impl ChunkVariables {
+    pub fn from_blocks<'b, I: IntoIterator<Item = &'b CodeBlock>>(blocks: I) -> Self {
+        let mut reads = BTreeSet::new();
+        let mut writes = BTreeSet::new();
+        let mut paths = BTreeSet::new();
+        for block in blocks {
+            reads.extend(block.reads.iter().cloned());
+            writes.extend(block.writes.iter().cloned());
+            paths.extend(block.paths.iter().cloned());
+        }
+        Self {
+            reads: reads.into_iter().collect(),
+            writes: writes.into_iter().collect(),
+            paths: paths.into_iter().collect(),
+        }
+    }
+
+    /// 读取或赋值的所有变量，按名称排序且不重复
+    pub fn names(&self) -> Vec<String> {
+        let names: BTreeSet<&String> = self.reads.iter().chain(self.writes.iter()).collect();
+        names.into_iter().cloned().collect()
+    }
+}
+
+/// 扫描整个字节码，按 `Begin`/`End` 切分出每个公式块及其读写的全局变量
+pub fn scan_blocks(reader: &mut ChunkReader) -> RspResult<Vec<CodeBlock>> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+    // 正在读取的属性路径：`GetGlobal` 之后紧跟的 `GetProperty` 依次追加到路径上
+    let mut path: Option<String> = None;
+    reader.new_position(0);
+    while reader.position() < reader.code_size() {
+        let start = reader.position();
+        let op = reader.read_opcode()?;
+        if !matches!(op, OpCode::GetProperty | OpCode::SetProperty) {
+            flush_path(&mut current, &mut path);
+        }
+        match op {
+            OpCode::Begin => {
+                let index = reader.read_int()?;
+                current = Some(CodeBlock {
+                    index,
+                    start,
+                    end: start,
+                    reads: HashSet::new(),
+                    writes: HashSet::new(),
+                    paths: HashSet::new(),
+                });
+            }
+            OpCode::End => {
+                if let Some(mut block) = current.take() {
+                    block.end = reader.position();
+                    blocks.push(block);
+                }
+            }
+            OpCode::GetGlobal | OpCode::SetGlobal => {
+                let index = reader.read_int()? as usize;
+                let name = reader.read_const(index)?.as_str().to_string();
+                if let Some(block) = current.as_mut() {
+                    if op == OpCode::GetGlobal {
+                        block.reads.insert(name.clone());
+                        path = Some(name);
+                    } else {
+                        block.writes.insert(name.clone());
+                        block.paths.insert(name);
+                    }
+                }
+            }
+            OpCode::GetProperty | OpCode::SetProperty => {
+                let index = reader.read_int()? as usize;
+                let name = reader.read_const(index)?.as_str();
+                if let Some(p) = path.as_mut() {
+                    p.push('.');
+                    p.push_str(name);
+                }
+                if op == OpCode::SetProperty {
+                    flush_path(&mut current, &mut path);
+                }
+            }
+            OpCode::GlobalGlobalOp | OpCode::GlobalConstOp => {
+                let end = reader.position() + op.operand_size();
+                let count = if op == OpCode::GlobalGlobalOp { 2 } else { 1 };
+                for _ in 0..count {
+                    let index = reader.read_int()? as usize;
+                    let name = reader.read_const(index)?.as_str();
+                    if let Some(block) = current.as_mut() {
+                        block.reads.insert(name.to_string());
+                        block.paths.insert(name.to_string());
+                    }
+                }
+                reader.new_position(end);
+            }
+            OpCode::Exit => break,
+            OpCode::Unknown => {
+                return Err(RspError::RuntimeError {
+                    message: format!("Unknown instruction at offset: {}", start),
+                    span: None,
+                    kind: ErrorKind::Other,
+                });
+            }
+            _ => {
+                let pos = reader.position();
+                reader.new_position(pos + op.operand_size());
+            }
+        }
+    }
+    reader.new_position(0);
+    Ok(blocks)
+}
+
+fn flush_path(current: &mut Option<CodeBlock>, path: &mut Option<String>) {
+    if let (Some(block), Some(p)) = (current.as_mut(), path.take()) {
+        block.paths.insert(p);
+    }
+}
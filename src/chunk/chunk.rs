@@ -1,44 +1,175 @@
+use crate::{RspError, RspResult};
+
+use super::crc32::crc32;
+use super::cursor::ByteCursor;
+use super::{ChunkReader, ChunkVariables, ConstantPool, SourceMap, scan_blocks};
+
+/// 字节码的二进制格式：
+///
+/// ```text
+/// magic "RSPC" | 格式版本 u16 | 指令集版本 u16 | flags u32 | 数据长度 u32 | CRC32 u32 | 数据
+/// ```
+///
+/// 数据部分依次为以 u32 长度为前缀的 codes、constants、vars 三段，flags 带有
+/// [`Chunk::FLAG_DEBUG`] 时还有第四段调试信息，内容为 [`SourceMap`]。
+/// 没有文件头的旧格式（版本 0）只包含数据部分，仍然可以读取。
 #[derive(Clone, Debug)]
 pub struct Chunk {
     pub codes: Vec<u8>,
     pub constants: Vec<u8>,
     pub vars: Vec<u8>,
+    /// 调试信息，为空表示没有调试信息
+    pub debug: Vec<u8>,
 }
 
 impl Chunk {
+    pub const MAGIC: [u8; 4] = *b"RSPC";
+    /// 当前的二进制格式版本
+    pub const FORMAT_VERSION: u16 = 1;
+    /// 当前的指令集版本，指令的编码或语义发生变化时递增。
+    /// 版本 2 增加了合并指令 `GlobalGlobalOp`、`GlobalConstOp` 和 `ConstOp`
+    pub const OPCODE_VERSION: u16 = 2;
+    /// 数据部分带有调试信息
+    pub const FLAG_DEBUG: u32 = 1;
+    /// 当前版本能够识别的 flags
+    pub const KNOWN_FLAGS: u32 = Self::FLAG_DEBUG;
+
+    const HEADER_SIZE: usize = 4 + 2 + 2 + 4 + 4 + 4;
+
+    /// 字节码中读取和赋值的变量，可以在执行前一次性准备好所需的数据
+    pub fn variables(&self) -> RspResult<ChunkVariables> {
+        let pool = ConstantPool::from_bytes(&self.constants)?;
+        let blocks = scan_blocks(&mut ChunkReader::new(&self.codes, &pool))?;
+        Ok(ChunkVariables::from_blocks(&blocks))
+    }
+
+    pub fn has_debug_info(&self) -> bool {
+        !self.debug.is_empty()
+    }
+
+    /// 字节码偏移到源码位置的映射，没有调试信息时返回 `None`
+    pub fn source_map(&self) -> RspResult<Option<SourceMap>> {
+        if !self.has_debug_info() {
+            return Ok(None);
+        }
+        SourceMap::from_bytes(&self.debug, self.codes.len()).map(Some)
+    }
+
+    /// 去掉调试信息，执行结果不变，只是运行时错误不再带有源码位置
+    pub fn strip_debug_info(&mut self) {
+        self.debug = Vec::new();
+    }
+
+    fn flags(&self) -> u32 {
+        if self.has_debug_info() {
+            Self::FLAG_DEBUG
+        } else {
+            0
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut out = Vec::new();
+        let payload = self.payload_bytes();
+        let mut out = Vec::with_capacity(Self::HEADER_SIZE + payload.len());
+        out.extend_from_slice(&Self::MAGIC);
+        out.extend_from_slice(&Self::FORMAT_VERSION.to_be_bytes());
+        out.extend_from_slice(&Self::OPCODE_VERSION.to_be_bytes());
+        out.extend_from_slice(&self.flags().to_be_bytes());
+        out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        out.extend_from_slice(&crc32(&payload).to_be_bytes());
+        out.extend_from_slice(&payload);
+        out
+    }
+
+    /// 读取字节码，数据损坏、不完整或者版本高于当前实现时返回错误
+    pub fn from_bytes(bytes: &[u8]) -> RspResult<Self> {
+        if !bytes.starts_with(&Self::MAGIC) {
+            return Self::from_payload(bytes, 0);
+        }
+
+        let mut cursor = ByteCursor::new(bytes);
+        cursor.read_bytes(Self::MAGIC.len())?;
+        let version = cursor.read_u16()?;
+        if version > Self::FORMAT_VERSION {
+            return Err(RspError::ChunkError {
+                message: format!(
+                    "Unsupported chunk format version: {}, max supported: {}",
+                    version,
+                    Self::FORMAT_VERSION
+                ),
+            });
+        }
+        let opcode_version = cursor.read_u16()?;
+        if opcode_version > Self::OPCODE_VERSION {
+            return Err(RspError::ChunkError {
+                message: format!(
+                    "Unsupported opcode version: {}, max supported: {}",
+                    opcode_version,
+                    Self::OPCODE_VERSION
+                ),
+            });
+        }
+        let flags = cursor.read_u32()?;
+        if flags & !Self::KNOWN_FLAGS != 0 {
+            return Err(RspError::ChunkError {
+                message: format!("Unknown chunk flags: {:#x}", flags),
+            });
+        }
+        let len = cursor.read_u32()? as usize;
+        let checksum = cursor.read_u32()?;
+        let payload = cursor.read_bytes(len)?;
+        if !cursor.is_empty() {
+            return Err(RspError::ChunkError {
+                message: format!("Trailing bytes after chunk: {}", cursor.remaining()),
+            });
+        }
+        if crc32(payload) != checksum {
+            return Err(RspError::ChunkError {
+                message: "Chunk checksum mismatch".to_string(),
+            });
+        }
+        Self::from_payload(payload, flags)
+    }
+
+    pub fn get_byte_size(&self) -> usize {
+        self.codes.len() + self.constants.len() + self.vars.len() + self.debug.len()
+    }
+
+    fn payload_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16 + self.get_byte_size());
         out.extend_from_slice(&(self.codes.len() as u32).to_be_bytes());
         out.extend_from_slice(&self.codes);
         out.extend_from_slice(&(self.constants.len() as u32).to_be_bytes());
         out.extend_from_slice(&self.constants);
         out.extend_from_slice(&(self.vars.len() as u32).to_be_bytes());
         out.extend_from_slice(&self.vars);
+        if self.has_debug_info() {
+            out.extend_from_slice(&(self.debug.len() as u32).to_be_bytes());
+            out.extend_from_slice(&self.debug);
+        }
         out
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        let mut i = 0usize;
-        let read_u32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize;
-        let code_sz = read_u32(&bytes[i..i + 4]);
-        i += 4;
-        let codes = bytes[i..i + code_sz].to_vec();
-        i += code_sz;
-        let const_sz = read_u32(&bytes[i..i + 4]);
-        i += 4;
-        let constants = bytes[i..i + const_sz].to_vec();
-        i += const_sz;
-        let var_sz = read_u32(&bytes[i..i + 4]);
-        i += 4;
-        let vars = bytes[i..i + var_sz].to_vec();
-        Self {
+    fn from_payload(bytes: &[u8], flags: u32) -> RspResult<Self> {
+        let mut cursor = ByteCursor::new(bytes);
+        let codes = cursor.read_section()?.to_vec();
+        let constants = cursor.read_section()?.to_vec();
+        let vars = cursor.read_section()?.to_vec();
+        let debug = if flags & Self::FLAG_DEBUG != 0 {
+            cursor.read_section()?.to_vec()
+        } else {
+            Vec::new()
+        };
+        if !cursor.is_empty() {
+            return Err(RspError::ChunkError {
+                message: format!("Trailing bytes after chunk: {}", cursor.remaining()),
+            });
+        }
+        Ok(Self {
             codes,
             constants,
             vars,
-        }
-    }
-
-    pub fn get_byte_size(&self) -> usize {
-        self.codes.len() + self.constants.len() + self.vars.len()
+            debug,
+        })
     }
 }
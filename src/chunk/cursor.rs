@@ -0,0 +1,64 @@
+use crate::{RspError, RspResult};
+
+/// 带边界检查的字节读取器，数据不完整时返回错误而不是越界 panic
+pub(crate) struct ByteCursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn read_bytes(&mut self, n: usize) -> RspResult<&'a [u8]> {
+        if n > self.remaining() {
+            return Err(RspError::ChunkError {
+                message: format!(
+                    "Unexpected end of data at offset {}: need {} bytes, {} left",
+                    self.pos,
+                    n,
+                    self.remaining()
+                ),
+            });
+        }
+        let b = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(b)
+    }
+
+    pub fn read_u8(&mut self) -> RspResult<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> RspResult<u16> {
+        let b = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> RspResult<u32> {
+        let b = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> RspResult<u64> {
+        let b = self.read_bytes(8)?;
+        Ok(u64::from_be_bytes([
+            b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+        ]))
+    }
+
+    /// 读取以 u32 长度为前缀的一段数据
+    pub fn read_section(&mut self) -> RspResult<&'a [u8]> {
+        let len = self.read_u32()? as usize;
+        self.read_bytes(len)
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt::Write;
+
+use crate::{RspResult, values::Value, vm::OpCode};
+
+use super::{Chunk, ChunkReader, ConstantPool, writer::is_var_const};
+
+/// 反汇编字节码，输出常量池、变量表以及逐条指令的文本清单。
+///
+/// 每条指令一行：偏移、指令名、操作数，注释部分给出常量的值、变量名、跳转目标或公式序号。
+/// 字节码损坏时（非法指令、操作数不完整、常量下标越界）在对应位置标注出来，不会中断输出，
+/// 只有常量池本身无法解码时才返回错误。
+pub fn disassemble(chunk: &Chunk) -> RspResult<String> {
+    let pool = ConstantPool::from_bytes(&chunk.constants)?;
+    let mut out = String::new();
+
+    let constants = pool.all();
+    let _ = writeln!(out, ".constants");
+    for (i, v) in constants.iter().enumerate() {
+        let _ = writeln!(out, "{:>6}  {}", i, format_constant(v));
+    }
+
+    let _ = writeln!(out, ".variables");
+    for (i, v) in constants.iter().enumerate() {
+        if is_var_const(&chunk.vars, i) {
+            let _ = writeln!(out, "{:>6}  {}", i, format_constant(v));
+        }
+    }
+
+    let _ = writeln!(out, ".code");
+    let mut reader = ChunkReader::new(&chunk.codes, &pool);
+    let mut order: Option<i32> = None;
+    while reader.position() < reader.code_size() {
+        let offset = reader.position();
+        let byte = reader.read_byte()?;
+        let op = OpCode::from(byte);
+        if op == OpCode::Unknown {
+            let _ = writeln!(out, "{:04}  ; <invalid instruction {:#04x}>", offset, byte);
+            continue;
+        }
+
+        let size = op.operand_size();
+        if reader.position() + size > reader.code_size() {
+            let _ = writeln!(out, "{:04}  {:?}  ; <truncated operand>", offset, op);
+            break;
+        }
+        if size == 0 {
+            match op {
+                OpCode::End => {
+                    let index = order.take().map_or("?".to_string(), |i| i.to_string());
+                    let _ = writeln!(out, "{:04}  {:<14} {:<6}  ; #{}", offset, "End", "", index);
+                }
+                _ => {
+                    let _ = writeln!(out, "{:04}  {:?}", offset, op);
+                }
+            }
+            continue;
+        }
+
+        if matches!(
+            op,
+            OpCode::GlobalGlobalOp | OpCode::GlobalConstOp | OpCode::ConstOp
+        ) {
+            let (operands, comment) = fused_operands(&mut reader, op, constants)?;
+            let _ = writeln!(
+                out,
+                "{:04}  {:<14} {:<6}  ; {}",
+                offset,
+                format!("{:?}", op),
+                operands,
+                comment
+            );
+            continue;
+        }
+
+        let operand = reader.read_int()?;
+        let comment = match op {
+            OpCode::Begin => {
+                order = Some(operand);
+                format!("#{}", operand)
+            }
+            OpCode::Jump | OpCode::JumpIfFalse => {
+                format!("-> {:04}", reader.position() as i64 + operand as i64)
+            }
+            _ => constant_comment(constants, operand, op != OpCode::Constant),
+        };
+        let _ = writeln!(
+            out,
+            "{:04}  {:<14} {:<6}  ; {}",
+            offset,
+            format!("{:?}", op),
+            operand,
+            comment
+        );
+    }
+    Ok(out)
+}
+
+/// 合并指令的操作数和注释，如 `0 1 Add` 和 `a Add b`，左操作数取自栈顶时注释中记为 `_`
+fn fused_operands(
+    reader: &mut ChunkReader,
+    op: OpCode,
+    constants: &[Value],
+) -> RspResult<(String, String)> {
+    let mut operands = Vec::new();
+    let mut values = Vec::new();
+    if op == OpCode::ConstOp {
+        values.push("_".to_string());
+    } else {
+        let a = reader.read_int()?;
+        operands.push(a.to_string());
+        values.push(constant_comment(constants, a, true));
+    }
+    let b = reader.read_int()?;
+    operands.push(b.to_string());
+    values.push(constant_comment(constants, b, op == OpCode::GlobalGlobalOp));
+    let byte = reader.read_byte()?;
+    let binary = OpCode::from(byte);
+    let binary = if binary.is_binary() {
+        format!("{:?}", binary)
+    } else {
+        format!("<invalid operator {:#04x}>", byte)
+    };
+    let comment = format!("{} {} {}", values[0], binary, values[1]);
+    operands.push(binary);
+    Ok((operands.join(" "), comment))
+}
+
+/// 常量下标的注释：变量名、属性名等名称不加引号，其余常量按 [`format_constant`] 输出
+fn constant_comment(constants: &[Value], index: i32, is_name: bool) -> String {
+    match usize::try_from(index).ok().and_then(|i| constants.get(i)) {
+        Some(Value::String(s)) if is_name => s.clone(),
+        Some(v) => format_constant(v),
+        None => "<invalid constant>".to_string(),
+    }
+}
+
+/// 常量的文本形式：字符串带引号并转义，浮点数总带有小数点，以便与整数区分
+pub(crate) fn format_constant(v: &Value) -> String {
+    match v {
+        Value::String(s) => format!("{:?}", s),
+        Value::Double(d) => format!("{:?}", d),
+        _ => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RspRunner;
+
+    #[test]
+    fn test_disassemble() {
+        let mut runner = RspRunner::new();
+        let chunk = runner
+            .compile_source(&["x = -a * 2.0", "a > 1 && b"])
+            .unwrap();
+        let text = disassemble(&chunk).unwrap();
+        let expected = r#".constants
+     0  "a"
+     1  2.0
+     2  "x"
+     3  1
+     4  "b"
+.variables
+     0  "a"
+     2  "x"
+     4  "b"
+.code
+0000  Begin          0       ; #0
+0005  GetGlobal      0       ; a
+0010  Negate
+0011  ConstOp        1 Multiply  ; _ Multiply 2.0
+0017  SetGlobal      2       ; x
+0022  End                    ; #0
+0023  Begin          1       ; #1
+0028  GlobalConstOp  0 3 Greater  ; a Greater 1
+0038  JumpIfFalse    6       ; -> 0049
+0043  Pop
+0044  GetGlobal      4       ; b
+0049  End                    ; #1
+0050  Exit
+"#;
+        assert_eq!(expected, text);
+    }
+
+    #[test]
+    fn test_disassemble_corrupted() {
+        let mut chunk = RspRunner::new().compile_source(&["x = 1"]).unwrap();
+        chunk.codes[0] = 200;
+        chunk.codes.truncate(chunk.codes.len() - 3);
+        let text = disassemble(&chunk).unwrap();
+        assert!(text.contains("<invalid instruction 0xc8>"));
+        assert!(text.contains("<truncated operand>"));
+    }
+}
@@ -1,9 +1,22 @@
+mod assembler;
+mod block;
+#[allow(clippy::module_inception)]
 mod chunk;
+mod crc32;
+mod cursor;
+mod disassembler;
 mod pool;
+mod program;
 mod reader;
+mod source_map;
 mod writer;
 
+pub use assembler::assemble;
+pub use block::{ChunkVariables, CodeBlock, scan_blocks};
 pub use chunk::Chunk;
+pub use disassembler::disassemble;
 pub use pool::ConstantPool;
+pub use program::Program;
 pub use reader::ChunkReader;
+pub use source_map::{SourceEntry, SourceMap};
 pub use writer::ChunkWriter;
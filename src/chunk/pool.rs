@@ -1,11 +1,53 @@
 use std::collections::HashMap;
 
 use crate::values::Value;
+use crate::{ErrorKind, RspError, RspResult};
+
+use super::cursor::ByteCursor;
+
+/// 常量在二进制格式中的类型标记，与 [`Value::type_code`] 保持一致
+const TAG_INTEGER: u8 = 1;
+const TAG_DOUBLE: u8 = 4;
+/// 以 u16 为长度前缀的字符串
+const TAG_STRING: u8 = 5;
+const TAG_BOOLEAN: u8 = 6;
+const TAG_NULL: u8 = 8;
+/// 以 u32 为长度前缀的字符串，用于超过 `u16::MAX` 字节的长文本
+const TAG_LONG_STRING: u8 = 9;
+
+/// 常量去重使用的键，区分类型，避免整数 `1` 与字符串 `"1"` 被当作同一个常量
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum ConstKey {
+    Integer(i32),
+    Double(u64),
+    String(String),
+    Boolean(bool),
+    Null,
+}
+
+impl ConstKey {
+    fn of(v: &Value) -> Option<Self> {
+        match v {
+            Value::Integer(i) => Some(ConstKey::Integer(*i)),
+            Value::Double(d) => Some(ConstKey::Double(d.to_bits())),
+            Value::String(s) => Some(ConstKey::String(s.clone())),
+            Value::Boolean(b) => Some(ConstKey::Boolean(*b)),
+            Value::Null => Some(ConstKey::Null),
+            Value::Instance(_) => None,
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct ConstantPool {
     constants: Vec<Value>,
-    index_map: HashMap<String, usize>,
+    index_map: HashMap<ConstKey, usize>,
+}
+
+impl Default for ConstantPool {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConstantPool {
@@ -16,47 +58,55 @@ impl ConstantPool {
         }
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Self {
+    /// 解码常量池，遇到未知的类型标记、数据不完整或字符串不是合法的 UTF-8 时返回错误
+    pub fn from_bytes(bytes: &[u8]) -> RspResult<Self> {
         let mut constants = Vec::new();
-        let mut i = 0usize;
-        while i < bytes.len() {
-            let tag = bytes[i];
-            i += 1;
+        let mut cursor = ByteCursor::new(bytes);
+        while !cursor.is_empty() {
+            let tag = cursor.read_u8()?;
             let value = match tag {
-                1 => {
-                    // Integer
-                    let b = &bytes[i..i + 4];
-                    i += 4;
-                    let v = i32::from_be_bytes([b[0], b[1], b[2], b[3]]);
-                    Value::Integer(v)
+                TAG_INTEGER => {
+                    let b = cursor.read_bytes(4)?;
+                    Value::Integer(i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
                 }
-                4 => {
-                    // Double
-                    let b = &bytes[i..i + 8];
-                    i += 8;
-                    let v = f64::from_bits(u64::from_be_bytes([
-                        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
-                    ]));
-                    Value::Double(v)
+                TAG_DOUBLE => Value::Double(f64::from_bits(cursor.read_u64()?)),
+                TAG_STRING => {
+                    let len = cursor.read_u16()? as usize;
+                    Value::String(Self::read_string(&mut cursor, len)?)
                 }
-                5 => {
-                    // String
-                    let len_b = &bytes[i..i + 2];
-                    i += 2;
-                    let len = u16::from_be_bytes([len_b[0], len_b[1]]) as usize;
-                    let s_b = &bytes[i..i + len];
-                    i += len;
-                    let s = String::from_utf8(s_b.to_vec()).expect("utf8");
-                    Value::String(s)
+                TAG_LONG_STRING => {
+                    let len = cursor.read_u32()? as usize;
+                    Value::String(Self::read_string(&mut cursor, len)?)
+                }
+                TAG_BOOLEAN => match cursor.read_u8()? {
+                    0 => Value::Boolean(false),
+                    1 => Value::Boolean(true),
+                    b => {
+                        return Err(RspError::ChunkError {
+                            message: format!("Invalid boolean constant: {}", b),
+                        });
+                    }
+                },
+                TAG_NULL => Value::Null,
+                _ => {
+                    return Err(RspError::ChunkError {
+                        message: format!("Unsupported constant tag: {}", tag),
+                    });
                 }
-                _ => panic!("unsupported constant tag: {}", tag),
             };
             constants.push(value);
         }
-        Self {
+        Ok(Self {
             constants,
             index_map: HashMap::new(),
-        }
+        })
+    }
+
+    fn read_string(cursor: &mut ByteCursor, len: usize) -> RspResult<String> {
+        let b = cursor.read_bytes(len)?;
+        String::from_utf8(b.to_vec()).map_err(|e| RspError::ChunkError {
+            message: format!("Invalid UTF-8 in string constant: {}", e),
+        })
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -65,46 +115,117 @@ impl ConstantPool {
         for v in &self.constants {
             match v {
                 Value::Integer(i) => {
-                    out.push(v.type_code());
+                    out.push(TAG_INTEGER);
                     out.extend_from_slice(&i.to_be_bytes());
                 }
                 Value::Double(d) => {
-                    out.push(v.type_code());
+                    out.push(TAG_DOUBLE);
                     out.extend_from_slice(&d.to_bits().to_be_bytes());
                 }
                 Value::String(s) => {
-                    out.push(v.type_code());
                     let b = s.as_bytes();
-                    assert!(b.len() <= u16::MAX as usize);
-                    out.extend_from_slice(&(b.len() as u16).to_be_bytes());
+                    // 短字符串保持原有的 u16 长度格式
+                    if b.len() <= u16::MAX as usize {
+                        out.push(TAG_STRING);
+                        out.extend_from_slice(&(b.len() as u16).to_be_bytes());
+                    } else {
+                        out.push(TAG_LONG_STRING);
+                        out.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                    }
                     out.extend_from_slice(b);
                 }
-                _ => panic!("unsupported constant type in pool"),
+                Value::Boolean(b) => {
+                    out.push(TAG_BOOLEAN);
+                    out.push(*b as u8);
+                }
+                Value::Null => out.push(TAG_NULL),
+                // add_const 不会接受其它类型
+                Value::Instance(_) => {}
             }
         }
         out
     }
 
-    pub fn add_const(&mut self, v: Value) -> usize {
-        let key = v.to_string();
-        if let Some(idx) = self.index_map.get(&key).copied() {
-            return idx;
+    /// 添加一个常量并返回它的下标，相同类型且相同值的常量只保存一份。
+    /// 不能序列化的值（如对象实例）以及超过 `u32::MAX` 字节的字符串返回错误
+    pub fn add_const(&mut self, v: Value) -> RspResult<usize> {
+        let key = ConstKey::of(&v).ok_or_else(|| RspError::CompileError {
+            message: format!("Unsupported constant type: {}", v.type_code()),
+            span: None,
+            kind: ErrorKind::Other,
+        })?;
+        if let Value::String(s) = &v
+            && s.len() > u32::MAX as usize
+        {
+            return Err(RspError::CompileError {
+                message: format!("String constant too long: {} bytes", s.len()),
+                span: None,
+                kind: ErrorKind::Other,
+            });
         }
-        match v {
-            Value::Integer(_) | Value::Double(_) | Value::String(_) | Value::Boolean(_) => {}
-            _ => panic!("unsupported constant value type: {:?}", v.type_code()),
+        if let Some(idx) = self.index_map.get(&key).copied() {
+            return Ok(idx);
         }
         self.constants.push(v);
         let idx = self.constants.len() - 1;
         self.index_map.insert(key, idx);
-        idx
+        Ok(idx)
     }
 
-    pub fn read_const(&self, index: usize) -> &Value {
-        &self.constants[index]
+    pub fn read_const(&self, index: usize) -> RspResult<&Value> {
+        self.constants
+            .get(index)
+            .ok_or_else(|| RspError::ChunkError {
+                message: format!("Constant index {} out of range", index),
+            })
     }
 
     pub fn all(&self) -> &Vec<Value> {
         &self.constants
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let long = "x".repeat(u16::MAX as usize + 10);
+        let values = vec![
+            Value::Integer(1),
+            Value::String("1".to_string()),
+            Value::Double(1.0),
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::Null,
+            Value::String(long.clone()),
+            Value::String("价格".to_string()),
+        ];
+        let mut pool = ConstantPool::new();
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(i, pool.add_const(v.clone()).unwrap());
+        }
+        assert_eq!(1, pool.add_const(Value::String("1".to_string())).unwrap());
+        assert_eq!(6, pool.add_const(Value::String(long)).unwrap());
+
+        let decoded = ConstantPool::from_bytes(&pool.to_bytes()).unwrap();
+        assert_eq!(&values, decoded.all());
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        for bytes in [
+            vec![200],
+            vec![TAG_INTEGER, 0, 0],
+            vec![TAG_BOOLEAN, 2],
+            vec![TAG_STRING, 0, 2, 0xff, 0xfe],
+            vec![TAG_LONG_STRING, 0xff, 0xff, 0xff, 0xff],
+        ] {
+            assert!(matches!(
+                ConstantPool::from_bytes(&bytes),
+                Err(RspError::ChunkError { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{levels::split_levels, targets::select_for_targets};
+use crate::{RspError, RspResult, Value};
+
+use super::writer::is_var_const;
+use super::{Chunk, ChunkReader, ChunkVariables, CodeBlock, ConstantPool, SourceMap, scan_blocks};
+
+/// 加载后的字节码。
+///
+/// 常量池只在加载时解码一次，公式块的划分和分层也一并计算好。`Program` 本身只读，
+/// 可以放在 `Arc` 中被多个线程共享，每次执行只需要一个 [`ChunkReader`] 和一个 VM。
+#[derive(Clone, Debug)]
+pub struct Program {
+    codes: Vec<u8>,
+    pool: ConstantPool,
+    vars: Vec<u8>,
+    blocks: Vec<CodeBlock>,
+    levels: Vec<Vec<usize>>,
+    /// 常量下标 -> 变量槽位，只有变量名常量才有槽位
+    slots: Vec<Option<usize>>,
+    slot_names: Vec<String>,
+    variables: ChunkVariables,
+    source_map: Option<SourceMap>,
+}
+
+impl Program {
+    /// 公式序号的上限，避免损坏的序号导致为执行结果分配过大的内存
+    pub const MAX_FORMULAS: usize = 1 << 20;
+
+    pub fn new(chunk: &Chunk) -> RspResult<Self> {
+        Self::from_chunk(chunk.clone())
+    }
+
+    /// 直接使用 `chunk` 中的数据，避免复制字节码
+    pub fn from_chunk(chunk: Chunk) -> RspResult<Self> {
+        let pool = ConstantPool::from_bytes(&chunk.constants)?;
+        let blocks = scan_blocks(&mut ChunkReader::new(&chunk.codes, &pool))?;
+        Self::check_blocks(&blocks)?;
+        let levels = split_levels(blocks.iter().map(|block| (&block.reads, &block.writes)));
+        let (slots, slot_names) = Self::resolve_slots(&pool, &chunk.vars, &blocks);
+        let variables = ChunkVariables::from_blocks(&blocks);
+        let source_map = chunk.source_map()?;
+        Ok(Self {
+            codes: chunk.codes,
+            pool,
+            vars: chunk.vars,
+            blocks,
+            levels,
+            slots,
+            slot_names,
+            variables,
+            source_map,
+        })
+    }
+
+    /// 检查公式序号并返回公式个数。
+    /// 执行结果按公式序号存放，按目标变量编译的字节码只包含部分公式，序号可以不连续
+    pub(crate) fn check_blocks(blocks: &[CodeBlock]) -> RspResult<usize> {
+        if let Some(block) = blocks
+            .iter()
+            .find(|block| block.index < 0 || block.index as usize >= Self::MAX_FORMULAS)
+        {
+            return Err(RspError::ChunkError {
+                message: format!(
+                    "Formula index {} out of range, max: {}",
+                    block.index,
+                    Self::MAX_FORMULAS - 1
+                ),
+            });
+        }
+        Ok(blocks
+            .iter()
+            .map(|block| block.index as usize + 1)
+            .max()
+            .unwrap_or(0))
+    }
+
+    /// 为每个变量名常量分配一个连续的槽位。变量名常量由 `vars` 位图标记，
+    /// 位图中缺失但被 `GetGlobal`/`SetGlobal` 使用的名称也会分配槽位
+    fn resolve_slots(
+        pool: &ConstantPool,
+        vars: &[u8],
+        blocks: &[CodeBlock],
+    ) -> (Vec<Option<usize>>, Vec<String>) {
+        let used: HashSet<&str> = blocks
+            .iter()
+            .flat_map(|block| block.reads.iter().chain(block.writes.iter()))
+            .map(String::as_str)
+            .collect();
+        let mut slots = vec![None; pool.all().len()];
+        let mut slot_names = Vec::new();
+        let mut by_name: HashMap<&str, usize> = HashMap::new();
+        for (i, v) in pool.all().iter().enumerate() {
+            if let Value::String(name) = v
+                && (is_var_const(vars, i) || used.contains(name.as_str()))
+            {
+                let slot = *by_name.entry(name).or_insert_with(|| {
+                    slot_names.push(name.clone());
+                    slot_names.len() - 1
+                });
+                slots[i] = Some(slot);
+            }
+        }
+        (slots, slot_names)
+    }
+
+    /// 创建一个从头开始读取的读取器，不会复制常量池
+    pub fn reader(&self) -> ChunkReader<'_> {
+        let reader = ChunkReader::new(&self.codes, &self.pool);
+        match &self.source_map {
+            Some(source_map) => reader.with_source_map(source_map),
+            None => reader,
+        }
+    }
+
+    pub fn codes(&self) -> &[u8] {
+        &self.codes
+    }
+
+    pub fn constants(&self) -> &ConstantPool {
+        &self.pool
+    }
+
+    pub fn vars(&self) -> &[u8] {
+        &self.vars
+    }
+
+    /// 变量对应的槽位，不是该字节码中的变量时返回 `None`
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slot_names.iter().position(|n| n == name)
+    }
+
+    /// 所有变量名，下标即槽位
+    pub fn slot_names(&self) -> &[String] {
+        &self.slot_names
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slot_names.len()
+    }
+
+    /// 常量下标对应的槽位
+    pub(crate) fn slots(&self) -> &[Option<usize>] {
+        &self.slots
+    }
+
+    pub fn variables(&self) -> &ChunkVariables {
+        &self.variables
+    }
+
+    /// 字节码中的调试信息，编译时关闭了调试信息或者已经去掉时返回 `None`
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.source_map.as_ref()
+    }
+
+    pub fn blocks(&self) -> &[CodeBlock] {
+        &self.blocks
+    }
+
+    /// 公式个数，即最大的公式序号加一。执行结果按公式序号存放，长度总是等于它
+    pub fn size(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| block.index as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 按依赖关系分层后的公式块，同一层内的公式块可以并行执行
+    pub fn levels(&self) -> Vec<Vec<&CodeBlock>> {
+        self.levels
+            .iter()
+            .map(|level| level.iter().map(|&pos| &self.blocks[pos]).collect())
+            .collect()
+    }
+
+    /// 计算 `targets` 中变量所需的公式块，保持在字节码中的顺序
+    pub fn target_blocks(&self, targets: &[&str]) -> Vec<&CodeBlock> {
+        let items: Vec<_> = self
+            .blocks
+            .iter()
+            .map(|block| (&block.reads, &block.writes))
+            .collect();
+        select_for_targets(&items, targets)
+            .into_iter()
+            .map(|pos| &self.blocks[pos])
+            .collect()
+    }
+}
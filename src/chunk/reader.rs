@@ -1,50 +1,69 @@
-use crate::{values::Value, vm::OpCode};
+use crate::{RspError, RspResult, values::Value, vm::OpCode};
 
+use super::SourceMap;
 use super::pool::ConstantPool;
 
+#[derive(Clone)]
 pub struct ChunkReader<'a> {
     code: &'a [u8],
     ip: usize,
-    const_pool: ConstantPool,
-    _vars_bits: Vec<u8>,
+    const_pool: &'a ConstantPool,
+    source_map: Option<&'a SourceMap>,
 }
 
 impl<'a> ChunkReader<'a> {
-    pub fn new(code: &'a [u8], constants: &'a [u8], vars: &'a [u8]) -> Self {
+    pub fn new(code: &'a [u8], const_pool: &'a ConstantPool) -> Self {
         Self {
             code,
             ip: 0,
-            const_pool: ConstantPool::from_bytes(constants),
-            _vars_bits: vars.to_vec(),
+            const_pool,
+            source_map: None,
         }
     }
 
-    pub fn read_byte(&mut self) -> u8 {
-        let b = self.code[self.ip];
-        self.ip += 1;
-        b
+    /// 附带源码映射，VM 出错时用它找到出错的子表达式
+    pub fn with_source_map(mut self, source_map: &'a SourceMap) -> Self {
+        self.source_map = Some(source_map);
+        self
     }
 
-    pub fn read_short(&mut self) -> i16 {
-        let b = &self.code[self.ip..self.ip + 2];
-        self.ip += 2;
-        i16::from_be_bytes([b[0], b[1]])
+    pub fn source_map(&self) -> Option<&'a SourceMap> {
+        self.source_map
     }
 
-    pub fn read_int(&mut self) -> i32 {
-        let b = &self.code[self.ip..self.ip + 4];
-        self.ip += 4;
-        i32::from_be_bytes([b[0], b[1], b[2], b[3]])
+    /// 读取指令和操作数，超出字节码末尾时返回错误，未经校验的字节码也不会越界
+    pub fn read_byte(&mut self) -> RspResult<u8> {
+        Ok(self.read_bytes::<1>()?[0])
     }
 
-    pub fn read_opcode(&mut self) -> OpCode {
-        OpCode::from(self.read_byte())
+    pub fn read_short(&mut self) -> RspResult<i16> {
+        Ok(i16::from_be_bytes(self.read_bytes()?))
     }
 
-    pub fn read_const(&self, index: usize) -> &Value {
+    pub fn read_int(&mut self) -> RspResult<i32> {
+        Ok(i32::from_be_bytes(self.read_bytes()?))
+    }
+
+    pub fn read_opcode(&mut self) -> RspResult<OpCode> {
+        Ok(OpCode::from(self.read_byte()?))
+    }
+
+    pub fn read_const(&self, index: usize) -> RspResult<&'a Value> {
         self.const_pool.read_const(index)
     }
 
+    fn read_bytes<const N: usize>(&mut self) -> RspResult<[u8; N]> {
+        let bytes = self
+            .code
+            .get(self.ip..self.ip.saturating_add(N))
+            .and_then(|b| <[u8; N]>::try_from(b).ok())
+            .ok_or_else(|| RspError::ChunkError {
+                message: format!("Unexpected end of code at offset {}", self.ip),
+            })?;
+        self.ip += N;
+        Ok(bytes)
+    }
+
     pub fn position(&self) -> usize {
         self.ip
     }
@@ -0,0 +1,134 @@
+use crate::span::Span;
+use crate::{RspError, RspResult};
+
+use super::cursor::ByteCursor;
+
+/// 一条可能出错的指令对应的源码位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceEntry {
+    /// 指令在字节码中的偏移
+    pub offset: u32,
+    /// 指令所在公式的序号
+    pub formula: u32,
+    /// 指令对应的表达式
+    pub span: Span,
+    /// 二元运算和赋值的左右操作数，出错的是某个操作数时指向它，其它指令为 `None`
+    pub operands: Option<(Span, Span)>,
+}
+
+/// 字节码偏移到公式序号和源码位置的映射，保存在字节码的调试段中。
+///
+/// 只记录可能在运行时出错的指令，条目按偏移递增排列。二进制格式为 u32 条目数，
+/// 之后每个条目依次为偏移、公式序号、位置的四个字段，一个字节标记是否有操作数，
+/// 有操作数时再跟左右两个位置，所有整数都是大端序
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    entries: Vec<SourceEntry>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 追加一条记录，偏移必须大于已有的记录
+    pub fn push(&mut self, entry: SourceEntry) {
+        debug_assert!(self.entries.last().is_none_or(|e| e.offset < entry.offset));
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[SourceEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 从 `offset` 处开始的指令的记录
+    pub fn find(&self, offset: usize) -> Option<&SourceEntry> {
+        let pos = self
+            .entries
+            .binary_search_by_key(&offset, |e| e.offset as usize)
+            .ok()?;
+        self.entries.get(pos)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(4 + self.entries.len() * 25);
+        out.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            out.extend_from_slice(&entry.offset.to_be_bytes());
+            out.extend_from_slice(&entry.formula.to_be_bytes());
+            write_span(&mut out, &entry.span);
+            match &entry.operands {
+                Some((left, right)) => {
+                    out.push(1);
+                    write_span(&mut out, left);
+                    write_span(&mut out, right);
+                }
+                None => out.push(0),
+            }
+        }
+        out
+    }
+
+    /// 读取调试段，条目的偏移必须递增且不超过 `code_size`
+    pub fn from_bytes(bytes: &[u8], code_size: usize) -> RspResult<Self> {
+        let mut cursor = ByteCursor::new(bytes);
+        let count = cursor.read_u32()? as usize;
+        // 每个条目至少 25 个字节，数量不可信时不预先分配
+        let mut entries = Vec::with_capacity(count.min(cursor.remaining() / 25));
+        for _ in 0..count {
+            let offset = cursor.read_u32()?;
+            let formula = cursor.read_u32()?;
+            let span = read_span(&mut cursor)?;
+            let operands = match cursor.read_u8()? {
+                0 => None,
+                1 => Some((read_span(&mut cursor)?, read_span(&mut cursor)?)),
+                flag => return Err(invalid(format!("unknown operand flag {}", flag))),
+            };
+            if offset as usize >= code_size {
+                return Err(invalid(format!("offset {} out of code", offset)));
+            }
+            let entry = SourceEntry {
+                offset,
+                formula,
+                span,
+                operands,
+            };
+            if entries
+                .last()
+                .is_some_and(|e: &SourceEntry| e.offset >= offset)
+            {
+                return Err(invalid(format!("offset {} out of order", offset)));
+            }
+            entries.push(entry);
+        }
+        if !cursor.is_empty() {
+            return Err(invalid(format!("{} trailing bytes", cursor.remaining())));
+        }
+        Ok(Self { entries })
+    }
+}
+
+fn invalid(message: String) -> RspError {
+    RspError::ChunkError {
+        message: format!("Invalid source map: {}", message),
+    }
+}
+
+fn write_span(out: &mut Vec<u8>, span: &Span) {
+    for v in [span.offset, span.len, span.line, span.column] {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+}
+
+fn read_span(cursor: &mut ByteCursor) -> RspResult<Span> {
+    Ok(Span {
+        offset: cursor.read_u32()?,
+        len: cursor.read_u32()?,
+        line: cursor.read_u32()?,
+        column: cursor.read_u32()?,
+    })
+}
@@ -1,16 +1,28 @@
 use bitvec::prelude::*;
 
-use crate::{values::Value, vm::OpCode};
+use crate::{RspResult, values::Value, vm::OpCode};
 
 use super::Chunk;
 use super::pool::ConstantPool;
 
+/// 常量是否被 `vars` 位图标记为变量名，位图与 [`ChunkWriter::set_variables`] 写入的格式一致
+pub(crate) fn is_var_const(vars: &[u8], index: usize) -> bool {
+    vars.get(index / 8)
+        .is_some_and(|bits| bits & (0x80 >> (index % 8)) != 0)
+}
+
 pub struct ChunkWriter {
     code: Vec<u8>,
     pool: ConstantPool,
     is_var_const: BitVec<u8, Msb0>,
 }
 
+impl Default for ChunkWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ChunkWriter {
     pub fn new() -> Self {
         Self {
@@ -34,6 +46,7 @@ impl ChunkWriter {
             codes,
             constants,
             vars,
+            debug: Vec::new(),
         }
     }
 
@@ -52,19 +65,23 @@ impl ChunkWriter {
     pub fn write_code(&mut self, op: OpCode) {
         self.write_byte(op as u8);
     }
-    pub fn add_constant(&mut self, v: Value) -> usize {
+    pub fn add_constant(&mut self, v: Value) -> RspResult<usize> {
         self.pool.add_const(v)
     }
-    pub fn set_variables(&mut self, vars: &[String]) {
+    pub fn constant(&self, index: usize) -> Option<&Value> {
+        self.pool.all().get(index)
+    }
+    pub fn set_variables(&mut self, vars: &[String]) -> RspResult<()> {
         let n = vars.len();
         self.is_var_const.resize(n, false);
         for var in vars {
-            let idx = self.pool.add_const(Value::String(var.clone()));
+            let idx = self.pool.add_const(Value::String(var.clone()))?;
             if idx >= self.is_var_const.len() {
                 self.is_var_const.resize(idx + 1, false);
             }
             self.is_var_const.set(idx, true);
         }
+        Ok(())
     }
     pub fn position(&self) -> usize {
         self.code.len()
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use crate::{ErrorKind, RspError, RspResult, Value};
+
+/// 一列数据，类型一致时按原生类型存放，否则按 [`Value`] 存放
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Integer(Vec<i32>),
+    Double(Vec<f64>),
+    Boolean(Vec<bool>),
+    Values(Vec<Value>),
+}
+
+impl Column {
+    /// 由任意值组成一列，所有值同为整数、浮点数或布尔值时转为对应的原生类型
+    pub fn from_values(values: Vec<Value>) -> Self {
+        match values.first() {
+            Some(Value::Integer(_)) if values.iter().all(Value::is_integer) => {
+                Column::Integer(values.iter().map(Value::as_integer).collect())
+            }
+            Some(Value::Double(_)) if values.iter().all(Value::is_double) => {
+                Column::Double(values.iter().map(Value::as_double).collect())
+            }
+            Some(Value::Boolean(_)) if values.iter().all(Value::is_boolean) => {
+                Column::Boolean(values.iter().map(Value::is_truthy).collect())
+            }
+            _ => Column::Values(values),
+        }
+    }
+
+    /// 同一个值重复 `rows` 次组成一列
+    pub fn repeat(value: &Value, rows: usize) -> Self {
+        match value {
+            Value::Integer(i) => Column::Integer(vec![*i; rows]),
+            Value::Double(d) => Column::Double(vec![*d; rows]),
+            Value::Boolean(b) => Column::Boolean(vec![*b; rows]),
+            v => Column::Values(vec![v.clone(); rows]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Integer(v) => v.len(),
+            Column::Double(v) => v.len(),
+            Column::Boolean(v) => v.len(),
+            Column::Values(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 第 `row` 行的值，越界时为 `Value::Null`
+    pub fn get(&self, row: usize) -> Value {
+        let value = match self {
+            Column::Integer(v) => v.get(row).map(|i| Value::Integer(*i)),
+            Column::Double(v) => v.get(row).map(|d| Value::Double(*d)),
+            Column::Boolean(v) => v.get(row).map(|b| Value::Boolean(*b)),
+            Column::Values(v) => v.get(row).cloned(),
+        };
+        value.unwrap_or(Value::Null)
+    }
+
+    pub fn to_values(&self) -> Vec<Value> {
+        (0..self.len()).map(|row| self.get(row)).collect()
+    }
+}
+
+impl From<Vec<i32>> for Column {
+    fn from(v: Vec<i32>) -> Self {
+        Column::Integer(v)
+    }
+}
+
+impl From<Vec<f64>> for Column {
+    fn from(v: Vec<f64>) -> Self {
+        Column::Double(v)
+    }
+}
+
+impl From<Vec<bool>> for Column {
+    fn from(v: Vec<bool>) -> Self {
+        Column::Boolean(v)
+    }
+}
+
+impl From<Vec<Value>> for Column {
+    fn from(v: Vec<Value>) -> Self {
+        Column::Values(v)
+    }
+}
+
+/// 按列存放的一批数据，每个变量一列，所有列的行数相同。
+///
+/// 所有行共用的参数可以用 [`Batch::set_scalar`] 设置，不需要重复成一列。
+/// 执行后公式赋值的变量也以列的形式写回。
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    rows: usize,
+    pub(crate) columns: HashMap<String, Column>,
+    pub(crate) scalars: HashMap<String, Value>,
+}
+
+impl Batch {
+    pub fn new(rows: usize) -> Self {
+        Self {
+            rows,
+            columns: HashMap::new(),
+            scalars: HashMap::new(),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// 设置一个变量的整列数据，行数必须与 `rows` 一致
+    pub fn set_column<C: Into<Column>>(&mut self, name: &str, column: C) -> RspResult<()> {
+        let column = column.into();
+        if column.len() != self.rows {
+            return Err(RspError::RuntimeError {
+                message: format!(
+                    "Column {} has {} rows, expected {}",
+                    name,
+                    column.len(),
+                    self.rows
+                ),
+                span: None,
+                kind: ErrorKind::Other,
+            });
+        }
+        self.scalars.remove(name);
+        self.columns.insert(name.to_string(), column);
+        Ok(())
+    }
+
+    /// 设置所有行共用的变量值
+    pub fn set_scalar(&mut self, name: &str, value: Value) {
+        self.columns.remove(name);
+        self.scalars.insert(name.to_string(), value);
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.get(name)
+    }
+
+    pub fn scalar(&self, name: &str) -> Option<&Value> {
+        self.scalars.get(name)
+    }
+}
@@ -0,0 +1,501 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::environment::Environment;
+use crate::error::{ErrorKind, RspError};
+use crate::functions::FunctionManager;
+use crate::span::Span;
+use crate::visitors::VarsQuery;
+
+/// 最多给出的拼写建议数
+const MAX_SUGGESTIONS: usize = 3;
+
+/// 错误码。`E00xx` 为词法和语法错误，`E01xx` 为编译和运行时错误，`E02xx` 为分析错误，
+/// `E03xx` 为字节码错误，`E04xx` 为超出执行限制，`E05xx` 为其它错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Syntax,
+    UnexpectedCharacter,
+    UnterminatedString,
+    InvalidNumber,
+    NestingTooDeep,
+    TooManyArguments,
+    Runtime,
+    TypeMismatch,
+    UndefinedVariable,
+    UndefinedFunction,
+    UndefinedProperty,
+    DivisionByZero,
+    ArityMismatch,
+    NotAnInstance,
+    InvalidCall,
+    InvalidAssignment,
+    CircularReference,
+    InvalidChunk,
+    VerifyFailed,
+    InstructionLimit,
+    StackOverflow,
+    StringTooLong,
+    Timeout,
+    Cancelled,
+    Aborted,
+    Io,
+}
+
+impl ErrorCode {
+    /// 根据错误的类型归类。解析、编译和运行时错误按其 [`ErrorKind`] 进一步区分
+    pub fn of(error: &RspError) -> Self {
+        match error {
+            RspError::ParseError { kind, .. } => Self::of_kind(kind, ErrorCode::Syntax),
+            RspError::RuntimeError { kind, .. } | RspError::CompileError { kind, .. } => {
+                Self::of_kind(kind, ErrorCode::Runtime)
+            }
+            RspError::AnalyzeError { .. } => ErrorCode::CircularReference,
+            RspError::ChunkError { .. } => ErrorCode::InvalidChunk,
+            RspError::VerifyError { .. } => ErrorCode::VerifyFailed,
+            RspError::InstructionLimit { .. } => ErrorCode::InstructionLimit,
+            RspError::StackOverflow { .. } => ErrorCode::StackOverflow,
+            RspError::StringTooLong { .. } => ErrorCode::StringTooLong,
+            RspError::Timeout { .. } => ErrorCode::Timeout,
+            RspError::Cancelled => ErrorCode::Cancelled,
+            RspError::Aborted { .. } => ErrorCode::Aborted,
+            RspError::IoError(_) => ErrorCode::Io,
+        }
+    }
+
+    /// `other` 为没有更具体类别时使用的错误码
+    fn of_kind(kind: &ErrorKind, other: ErrorCode) -> Self {
+        match kind {
+            ErrorKind::Other => other,
+            ErrorKind::UnexpectedCharacter => ErrorCode::UnexpectedCharacter,
+            ErrorKind::UnterminatedString => ErrorCode::UnterminatedString,
+            ErrorKind::InvalidNumber => ErrorCode::InvalidNumber,
+            ErrorKind::NestingTooDeep => ErrorCode::NestingTooDeep,
+            ErrorKind::TooManyArguments => ErrorCode::TooManyArguments,
+            ErrorKind::TypeMismatch => ErrorCode::TypeMismatch,
+            ErrorKind::UndefinedVariable { .. } => ErrorCode::UndefinedVariable,
+            ErrorKind::UndefinedFunction { .. } => ErrorCode::UndefinedFunction,
+            ErrorKind::UndefinedProperty { .. } => ErrorCode::UndefinedProperty,
+            ErrorKind::DivisionByZero => ErrorCode::DivisionByZero,
+            ErrorKind::ArityMismatch => ErrorCode::ArityMismatch,
+            ErrorKind::NotAnInstance => ErrorCode::NotAnInstance,
+            ErrorKind::InvalidCall => ErrorCode::InvalidCall,
+            ErrorKind::InvalidAssignment => ErrorCode::InvalidAssignment,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => "E0001",
+            ErrorCode::UnexpectedCharacter => "E0002",
+            ErrorCode::UnterminatedString => "E0003",
+            ErrorCode::InvalidNumber => "E0004",
+            ErrorCode::NestingTooDeep => "E0005",
+            ErrorCode::TooManyArguments => "E0006",
+            ErrorCode::Runtime => "E0100",
+            ErrorCode::TypeMismatch => "E0101",
+            ErrorCode::UndefinedVariable => "E0102",
+            ErrorCode::UndefinedFunction => "E0103",
+            ErrorCode::UndefinedProperty => "E0104",
+            ErrorCode::DivisionByZero => "E0105",
+            ErrorCode::ArityMismatch => "E0106",
+            ErrorCode::NotAnInstance => "E0107",
+            ErrorCode::InvalidCall => "E0108",
+            ErrorCode::InvalidAssignment => "E0109",
+            ErrorCode::CircularReference => "E0201",
+            ErrorCode::InvalidChunk => "E0301",
+            ErrorCode::VerifyFailed => "E0302",
+            ErrorCode::InstructionLimit => "E0401",
+            ErrorCode::StackOverflow => "E0402",
+            ErrorCode::StringTooLong => "E0403",
+            ErrorCode::Timeout => "E0404",
+            ErrorCode::Cancelled => "E0405",
+            ErrorCode::Aborted => "E0406",
+            ErrorCode::Io => "E0501",
+        }
+    }
+
+    /// 简短的标题
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => "syntax error",
+            ErrorCode::UnexpectedCharacter => "unexpected character",
+            ErrorCode::UnterminatedString => "unterminated string",
+            ErrorCode::InvalidNumber => "invalid number",
+            ErrorCode::NestingTooDeep => "expression nested too deeply",
+            ErrorCode::TooManyArguments => "too many arguments",
+            ErrorCode::Runtime => "runtime error",
+            ErrorCode::TypeMismatch => "mismatched types",
+            ErrorCode::UndefinedVariable => "undefined variable",
+            ErrorCode::UndefinedFunction => "undefined function",
+            ErrorCode::UndefinedProperty => "undefined property",
+            ErrorCode::DivisionByZero => "division by zero",
+            ErrorCode::ArityMismatch => "wrong number of arguments",
+            ErrorCode::NotAnInstance => "not an instance",
+            ErrorCode::InvalidCall => "invalid call",
+            ErrorCode::InvalidAssignment => "invalid assignment target",
+            ErrorCode::CircularReference => "circular reference",
+            ErrorCode::InvalidChunk => "invalid chunk",
+            ErrorCode::VerifyFailed => "chunk verification failed",
+            ErrorCode::InstructionLimit => "instruction limit exceeded",
+            ErrorCode::StackOverflow => "stack overflow",
+            ErrorCode::StringTooLong => "string too long",
+            ErrorCode::Timeout => "timed out",
+            ErrorCode::Cancelled => "cancelled",
+            ErrorCode::Aborted => "aborted",
+            ErrorCode::Io => "io error",
+        }
+    }
+
+    /// 对这一类错误的解释和常见的修改方法
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => {
+                "The formula does not follow the expression grammar. Check for a missing operand, \
+                 an unbalanced parenthesis or a missing comma between arguments."
+            }
+            ErrorCode::UnexpectedCharacter => {
+                "This character is not part of any operator, number, string or identifier."
+            }
+            ErrorCode::UnterminatedString => {
+                "A string literal was opened with `\"` but never closed."
+            }
+            ErrorCode::InvalidNumber => {
+                "The number literal is malformed or does not fit in the numeric range."
+            }
+            ErrorCode::NestingTooDeep => {
+                "The expression is nested deeper than the parser allows. Split it into several \
+                 formulas that assign intermediate variables."
+            }
+            ErrorCode::TooManyArguments => "A function call can take at most 255 arguments.",
+            ErrorCode::Runtime => "The formula failed while it was being evaluated.",
+            ErrorCode::TypeMismatch => {
+                "The operator does not accept values of these types. Arithmetic needs numbers, \
+                 and `+` also accepts strings."
+            }
+            ErrorCode::UndefinedVariable => {
+                "The variable is not set in the environment and no formula assigns it. \
+                 Check the spelling or provide a value before execution."
+            }
+            ErrorCode::UndefinedFunction => {
+                "No function with this name is registered. Check the spelling or register it \
+                 with the `FunctionManager`."
+            }
+            ErrorCode::UndefinedProperty => "The object does not have a property with this name.",
+            ErrorCode::DivisionByZero => {
+                "An integer was divided by zero, or the remainder of a division by zero was \
+                 taken. Guard the divisor, for example `if(b != 0, a / b, 0)`."
+            }
+            ErrorCode::ArityMismatch => {
+                "The function was called with a different number of arguments than it declares."
+            }
+            ErrorCode::NotAnInstance => "Only instances have properties that can be read or set.",
+            ErrorCode::InvalidCall => "Only a function name can be called, as in `abs(x)`.",
+            ErrorCode::InvalidAssignment => {
+                "The left side of `=` must be a variable or a property."
+            }
+            ErrorCode::CircularReference => {
+                "The formulas depend on each other in a cycle, so there is no order in which \
+                 they can be evaluated."
+            }
+            ErrorCode::InvalidChunk => {
+                "The bytecode is truncated, corrupted or was written by an incompatible version. \
+                 Compile the formulas again."
+            }
+            ErrorCode::VerifyFailed => {
+                "The bytecode failed verification and was not executed. It was probably \
+                 modified after compilation."
+            }
+            ErrorCode::InstructionLimit => {
+                "The execution ran more instructions than `Limits::max_instructions` allows."
+            }
+            ErrorCode::StackOverflow => {
+                "The execution needed a deeper stack than `Limits::max_stack` allows."
+            }
+            ErrorCode::StringTooLong => {
+                "A string produced during execution is longer than `Limits::max_string_len`."
+            }
+            ErrorCode::Timeout => "The execution ran longer than `Limits::timeout`.",
+            ErrorCode::Cancelled => "The execution was cancelled through its `CancelToken`.",
+            ErrorCode::Aborted => {
+                "The environment refused to run the formulas, `before_execute` returned false."
+            }
+            ErrorCode::Io => "Reading or writing a file failed.",
+        }
+    }
+}
+
+/// 便于阅读的错误诊断信息，可以输出为带有源码片段的纯文本，或者供网页界面使用的 JSON
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    /// 原始错误信息，不带错误类型前缀
+    pub message: String,
+    pub span: Option<Span>,
+    /// 拼写相近的已知名称，按相近程度排序
+    pub suggestions: Vec<String>,
+    /// 解析、编译和运行时错误的类别，其它错误为 `None`
+    pub kind: Option<ErrorKind>,
+}
+
+impl Diagnostic {
+    pub fn new(error: &RspError) -> Self {
+        let message = match error {
+            RspError::ParseError { message, .. }
+            | RspError::AnalyzeError { message }
+            | RspError::RuntimeError { message, .. }
+            | RspError::CompileError { message, .. }
+            | RspError::ChunkError { message }
+            | RspError::Aborted { message }
+            | RspError::VerifyError { message, .. } => message.clone(),
+            e => e.to_string(),
+        };
+        Self {
+            code: ErrorCode::of(error),
+            message,
+            span: error.span(),
+            suggestions: Vec::new(),
+            kind: error.kind().cloned(),
+        }
+    }
+
+    /// 未定义的变量按环境中的变量名和 `formulas` 中赋值的变量名，未定义的函数按已注册的函数名
+    /// 给出拼写建议，其它错误不变。`formulas` 为一起执行的公式源码，无法解析的公式被忽略
+    pub fn with_suggestions<E: Environment, S: AsRef<str>>(
+        mut self,
+        env: &E,
+        functions: &FunctionManager,
+        formulas: &[S],
+    ) -> Self {
+        let Some(kind) = &self.kind else {
+            return self;
+        };
+        self.suggestions = match kind {
+            ErrorKind::UndefinedVariable { name } => {
+                let mut query = VarsQuery::new();
+                let mut assigns = HashSet::new();
+                for formula in formulas {
+                    if let Some(vars) = query.execute_src(formula.as_ref().to_string()) {
+                        // 属性赋值不产生新的变量
+                        assigns.extend(
+                            vars.get_assigns()
+                                .iter()
+                                .filter(|name| !name.contains('.'))
+                                .cloned(),
+                        );
+                    }
+                }
+                let mut candidates = env.names();
+                candidates.extend(assigns.iter().map(String::as_str));
+                suggest(name, candidates)
+            }
+            ErrorKind::UndefinedFunction { name } => suggest(name, functions.names()),
+            _ => return self,
+        };
+        self
+    }
+
+    /// 纯文本格式，`source` 为出错公式的源码，错误位置超出源码时不显示源码片段
+    pub fn render(&self, source: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "error[{}]: {}", self.code.code(), self.code.title());
+        match self.span.filter(Span::is_known) {
+            Some(span) => {
+                let _ = writeln!(out, " --> {}", span);
+                if let Some(snippet) = Snippet::new(source, span) {
+                    let line = span.line.to_string();
+                    let pad = " ".repeat(line.len());
+                    let _ = writeln!(out, "{} |", pad);
+                    let _ = writeln!(out, "{} | {}", line, snippet.text);
+                    let _ = writeln!(
+                        out,
+                        "{} | {}{} {}",
+                        pad, snippet.indent, snippet.marker, self.message
+                    );
+                    let _ = writeln!(out, "{} |", pad);
+                } else {
+                    let _ = writeln!(out, "  = {}", self.message);
+                }
+            }
+            None => {
+                let _ = writeln!(out, "  = {}", self.message);
+            }
+        }
+        if !self.suggestions.is_empty() {
+            let names: Vec<String> = self
+                .suggestions
+                .iter()
+                .map(|s| format!("`{}`", s))
+                .collect();
+            let _ = writeln!(out, "  = help: did you mean {}?", names.join(" or "));
+        }
+        let _ = writeln!(out, "  = note: {}", self.code.explanation());
+        out
+    }
+
+    /// JSON 格式，包含纯文本格式中的所有信息。位置按字节和字符两种方式给出，源码行不含换行符
+    pub fn render_json(&self, source: &str) -> String {
+        let mut out = String::from("{");
+        let _ = write!(
+            out,
+            "\"code\":\"{}\",\"title\":\"{}\",\"message\":",
+            self.code.code(),
+            self.code.title()
+        );
+        write_json_string(&mut out, &self.message);
+        out.push_str(",\"explanation\":");
+        write_json_string(&mut out, self.code.explanation());
+        out.push_str(",\"span\":");
+        match self.span.filter(Span::is_known) {
+            Some(span) => {
+                let _ = write!(
+                    out,
+                    "{{\"offset\":{},\"len\":{},\"line\":{},\"column\":{}}}",
+                    span.offset, span.len, span.line, span.column
+                );
+            }
+            None => out.push_str("null"),
+        }
+        out.push_str(",\"source_line\":");
+        match self
+            .span
+            .filter(Span::is_known)
+            .and_then(|span| Snippet::new(source, span))
+        {
+            Some(snippet) => write_json_string(&mut out, snippet.text),
+            None => out.push_str("null"),
+        }
+        out.push_str(",\"suggestions\":[");
+        for (i, name) in self.suggestions.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_json_string(&mut out, name);
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+/// 出错位置所在的源码行，以及下一行中对齐到出错位置的标记
+struct Snippet<'s> {
+    text: &'s str,
+    indent: String,
+    marker: String,
+}
+
+impl<'s> Snippet<'s> {
+    fn new(source: &'s str, span: Span) -> Option<Self> {
+        let start = span.start();
+        let prefix = source.get(..start)?;
+        let line_start = prefix.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        // 制表符原样保留，其它字符按显示宽度换成空格，这样标记在终端中能对齐
+        let indent = source[line_start..start]
+            .chars()
+            .map(|c| match c {
+                '\t' => "\t".to_string(),
+                c => " ".repeat(char_width(c)),
+            })
+            .collect();
+        // 跨行的位置只标记第一行，长度为 0 的位置（例如缺少的右括号）标记一个字符
+        let end = span.end().min(line_start + text.len()).max(start);
+        let width: usize = source.get(start..end)?.chars().map(char_width).sum();
+        Some(Self {
+            text,
+            indent,
+            marker: "^".repeat(width.max(1)),
+        })
+    }
+}
+
+/// 字符在等宽终端中占的列数，中日韩文字和全角符号占两列
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// 从 `candidates` 中选出与 `name` 拼写相近的名称。
+///
+/// 允许的编辑距离随名称长度增加，每三个字符允许一处错误，相邻字符互换算一处。
+/// 只是大小写不同的名称总是会给出
+pub fn suggest<'n>(name: &str, candidates: impl IntoIterator<Item = &'n str>) -> Vec<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    let lower = name.to_lowercase();
+    let mut found: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|c| *c != name)
+        .filter_map(|c| {
+            let distance = if c.to_lowercase() == lower {
+                0
+            } else {
+                edit_distance(name, c)
+            };
+            (distance <= limit).then_some((distance, c))
+        })
+        .collect();
+    found.sort_unstable();
+    found.dedup();
+    found
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)| c.to_string())
+        .collect()
+}
+
+/// 按字符计算的编辑距离，相邻字符互换计为一次编辑
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // 只保留最近的三行
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        cur[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            cur[j] = (prev[j] + 1).min(cur[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                cur[j] = cur[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut prev);
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
@@ -1,14 +1,24 @@
+use crate::field::Field;
 use crate::values::Value;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub trait Environment {
-    fn before_execute(&mut self, _vars: &[String]) -> bool {
+    /// 每批公式执行前调用一次。`vars` 为将要读写的变量名，属性访问只取第一段（如 `order`），
+    /// `fields` 为对应的访问路径，属性访问（如 `order.customer.tier`）给出完整路径，
+    /// 两者均按名称排序，各种执行方式传入的内容相同。
+    /// 可以在这里一次性准备好所需的数据，返回 `false` 时中止执行并返回 `RspError::Aborted`
+    fn before_execute(&mut self, _vars: &[String], _fields: &[Arc<Field>]) -> bool {
         true
     }
     fn get(&self, name: &str) -> Option<&Value>;
     fn put(&mut self, name: String, value: Value) -> bool;
     fn extend<T: IntoIterator<Item = (String, Value)>>(&mut self, iter: T);
     fn size(&self) -> usize;
+    /// 环境中已有的变量名，诊断信息据此给出拼写相近的建议。无法列出时返回空列表
+    fn names(&self) -> Vec<&str> {
+        Vec::new()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +57,10 @@ impl Environment for DefaultEnvironment {
     fn size(&self) -> usize {
         self.values.len()
     }
+
+    fn names(&self) -> Vec<&str> {
+        self.values.keys().map(String::as_str).collect()
+    }
 }
 
 impl Default for DefaultEnvironment {
@@ -54,3 +68,110 @@ impl Default for DefaultEnvironment {
         Self::new()
     }
 }
+
+/// 分层并行执行时每个工作线程使用的环境：读取时优先读取本线程写入的值，
+/// 否则读取共享环境；写入只记录在本地，待整层执行完毕后再统一合并到共享环境。
+pub struct OverlayEnvironment<'a, E: Environment> {
+    base: &'a E,
+    writes: HashMap<String, Value>,
+}
+
+impl<'a, E: Environment> OverlayEnvironment<'a, E> {
+    pub fn new(base: &'a E) -> Self {
+        Self {
+            base,
+            writes: HashMap::new(),
+        }
+    }
+
+    pub fn into_writes(self) -> HashMap<String, Value> {
+        self.writes
+    }
+}
+
+impl<'a, E: Environment> Environment for OverlayEnvironment<'a, E> {
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.writes.get(name).or_else(|| self.base.get(name))
+    }
+
+    fn put(&mut self, name: String, value: Value) -> bool {
+        self.writes.insert(name, value);
+        true
+    }
+
+    fn extend<T: IntoIterator<Item = (String, Value)>>(&mut self, iter: T) {
+        self.writes.extend(iter);
+    }
+
+    fn size(&self) -> usize {
+        let added = self
+            .writes
+            .keys()
+            .filter(|name| self.base.get(name).is_none())
+            .count();
+        self.base.size() + added
+    }
+
+    fn names(&self) -> Vec<&str> {
+        let mut names = self.base.names();
+        names.extend(
+            self.writes
+                .keys()
+                .map(String::as_str)
+                .filter(|name| self.base.get(name).is_none()),
+        );
+        names
+    }
+}
+
+/// 按槽位访问全局变量的环境。
+///
+/// 槽位在加载字节码时分配（见 [`Program::slot`](crate::Program::slot)），执行时直接按下标读写，
+/// 不需要对变量名做哈希。宿主程序先查询一次变量对应的槽位，之后每次执行前按槽位填入数据即可。
+pub trait SlotEnvironment {
+    fn get_slot(&self, slot: usize) -> Option<&Value>;
+    fn put_slot(&mut self, slot: usize, value: Value) -> bool;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DefaultSlotEnvironment {
+    values: Vec<Option<Value>>,
+}
+
+impl DefaultSlotEnvironment {
+    pub fn new(size: usize) -> Self {
+        Self {
+            values: vec![None; size],
+        }
+    }
+
+    /// 设置槽位的值，与 `put_slot` 相同，槽位超出范围时返回 `false`
+    pub fn set(&mut self, slot: usize, value: Value) -> bool {
+        self.put_slot(slot, value)
+    }
+
+    /// 清空所有槽位的值，保留已分配的空间，便于下一次执行复用
+    pub fn clear(&mut self) {
+        self.values.iter_mut().for_each(|v| *v = None);
+    }
+
+    pub fn size(&self) -> usize {
+        self.values.len()
+    }
+}
+
+impl SlotEnvironment for DefaultSlotEnvironment {
+    fn get_slot(&self, slot: usize) -> Option<&Value> {
+        self.values.get(slot)?.as_ref()
+    }
+
+    fn put_slot(&mut self, slot: usize, value: Value) -> bool {
+        match self.values.get_mut(slot) {
+            Some(v) => {
+                *v = Some(value);
+                true
+            }
+            None => false,
+        }
+    }
+}
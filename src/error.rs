@@ -1,21 +1,131 @@
+use crate::span::Span;
 use thiserror::Error;
 
+/// 解析、编译和运行时错误的具体类别，诊断信息据此给出错误码
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// 没有更具体的类别
+    Other,
+    UnexpectedCharacter,
+    UnterminatedString,
+    InvalidNumber,
+    NestingTooDeep,
+    TooManyArguments,
+    TypeMismatch,
+    UndefinedVariable {
+        name: String,
+    },
+    UndefinedFunction {
+        name: String,
+    },
+    UndefinedProperty {
+        name: String,
+    },
+    DivisionByZero,
+    ArityMismatch,
+    NotAnInstance,
+    InvalidCall,
+    InvalidAssignment,
+}
+
 #[derive(Error, Debug)]
 pub enum RspError {
-    #[error("Parse error at line {line}: {message}")]
-    ParseError { line: usize, message: String },
+    #[error("Parse error at {span}: {message}")]
+    ParseError {
+        span: Span,
+        message: String,
+        kind: ErrorKind,
+    },
 
     #[error("Analyze error: {message}")]
     AnalyzeError { message: String },
 
     #[error("Runtime error: {message}")]
-    RuntimeError { message: String },
+    RuntimeError {
+        message: String,
+        span: Option<Span>,
+        kind: ErrorKind,
+    },
 
     #[error("Compile error: {message}")]
-    CompileError { message: String },
+    CompileError {
+        message: String,
+        span: Option<Span>,
+        kind: ErrorKind,
+    },
+
+    #[error("Chunk error: {message}")]
+    ChunkError { message: String },
+
+    #[error("Execution aborted: {message}")]
+    Aborted { message: String },
+
+    #[error("Verify error at offset {offset}: {message}")]
+    VerifyError { offset: usize, message: String },
+
+    #[error("Instruction limit exceeded: {limit}")]
+    InstructionLimit { limit: u64 },
+
+    #[error("Stack overflow: depth exceeds {limit}")]
+    StackOverflow { limit: usize },
+
+    #[error("String too long: {length} bytes, limit: {limit}")]
+    StringTooLong { length: usize, limit: usize },
+
+    #[error("Execution timed out after {timeout:?}")]
+    Timeout { timeout: std::time::Duration },
+
+    #[error("Execution cancelled")]
+    Cancelled,
 
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 }
 
+impl RspError {
+    /// 出错的源码位置，只有解析、编译和运行时错误可能带有位置
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            RspError::ParseError { span, .. } => Some(*span),
+            RspError::RuntimeError { span, .. } | RspError::CompileError { span, .. } => *span,
+            _ => None,
+        }
+    }
+
+    /// 解析、编译和运行时错误的具体类别，其它错误返回 `None`
+    pub fn kind(&self) -> Option<&ErrorKind> {
+        match self {
+            RspError::ParseError { kind, .. }
+            | RspError::RuntimeError { kind, .. }
+            | RspError::CompileError { kind, .. } => Some(kind),
+            _ => None,
+        }
+    }
+
+    /// 给还没有位置的编译或运行时错误补上位置。内层已经补上的位置更精确，保持不变
+    pub fn with_span(self, span: Span) -> Self {
+        match self {
+            RspError::RuntimeError {
+                message,
+                span: None,
+                kind,
+            } => RspError::RuntimeError {
+                message,
+                span: Some(span),
+                kind,
+            },
+            RspError::CompileError {
+                message,
+                span: None,
+                kind,
+            } => RspError::CompileError {
+                message,
+                span: Some(span),
+                kind,
+            },
+            e => e,
+        }
+    }
+}
+
 pub type RspResult<T> = Result<T, RspError>;
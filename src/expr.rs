@@ -1,7 +1,14 @@
 use crate::Token;
+use crate::error::{ErrorKind, RspError};
+use crate::parser::Interner;
+use crate::span::Span;
 use crate::values::Value;
-use std::rc::Rc;
+use std::sync::Arc;
 
+/// 不借用源字符串的语法树
+pub type OwnedExpr = Expr<'static>;
+
+#[derive(Clone)]
 pub enum Expr<'a> {
     Binary(BinaryExpr<'a>),
     Logic(LogicExpr<'a>),
@@ -13,6 +20,8 @@ pub enum Expr<'a> {
     If(IfExpr<'a>),
     Get(GetExpr<'a>),
     Set(SetExpr<'a>),
+    /// 容错解析时代替出错部分的节点，见 [`Parser::parse_recover`](crate::Parser::parse_recover)
+    Error(ErrorExpr),
 }
 
 pub trait Visitor<R> {
@@ -26,6 +35,7 @@ pub trait Visitor<R> {
     fn visit_if(&mut self, expr: &IfExpr) -> R;
     fn visit_get(&mut self, expr: &GetExpr) -> R;
     fn visit_set(&mut self, expr: &SetExpr) -> R;
+    fn visit_error(&mut self, expr: &ErrorExpr) -> R;
 }
 
 impl<'a> Expr<'a> {
@@ -41,19 +51,144 @@ impl<'a> Expr<'a> {
             Expr::If(expr) => visitor.visit_if(expr),
             Expr::Get(expr) => visitor.visit_get(expr),
             Expr::Set(expr) => visitor.visit_set(expr),
+            Expr::Error(expr) => visitor.visit_error(expr),
+        }
+    }
+
+    /// 节点在源码中的位置
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary(expr) => expr.span,
+            Expr::Logic(expr) => expr.span,
+            Expr::Literal(expr) => expr.span,
+            Expr::Unary(expr) => expr.span,
+            Expr::Id(expr) => expr.span,
+            Expr::Assign(expr) => expr.span,
+            Expr::Call(expr) => expr.span,
+            Expr::If(expr) => expr.span,
+            Expr::Get(expr) => expr.span,
+            Expr::Set(expr) => expr.span,
+            Expr::Error(expr) => expr.span,
+        }
+    }
+
+    /// 替换节点的位置。构造函数按子节点和 token 推算位置，
+    /// 字面量和 `if` 等没有足够信息推算的节点由解析器补上
+    pub fn with_span(mut self, span: Span) -> Self {
+        match &mut self {
+            Expr::Binary(expr) => expr.span = span,
+            Expr::Logic(expr) => expr.span = span,
+            Expr::Literal(expr) => expr.span = span,
+            Expr::Unary(expr) => expr.span = span,
+            Expr::Id(expr) => expr.span = span,
+            Expr::Assign(expr) => expr.span = span,
+            Expr::Call(expr) => expr.span = span,
+            Expr::If(expr) => expr.span = span,
+            Expr::Get(expr) => expr.span = span,
+            Expr::Set(expr) => expr.span = span,
+            Expr::Error(expr) => expr.span = span,
         }
+        self
+    }
+
+    /// 转换为不再借用源字符串的语法树，可以脱离源字符串缓存或跨线程传递
+    pub fn into_owned(self) -> Expr<'static> {
+        self.into_owned_with(&mut Interner::new())
     }
 
-    pub fn binary(left: Expr<'a>, operator: Rc<Token<'a>>, right: Expr<'a>) -> Expr<'a> {
+    /// 与 `into_owned` 相同，但变量名等文本放入驻留池中，多个语法树之间共享同一份字符串
+    pub fn into_owned_with(self, interner: &mut Interner) -> Expr<'static> {
+        let it = interner;
+        let span = self.span();
+        let expr = match self {
+            Expr::Binary(BinaryExpr {
+                left,
+                operator,
+                right,
+                ..
+            }) => Expr::binary(
+                left.into_owned_with(it),
+                own_token(operator, it),
+                right.into_owned_with(it),
+            ),
+            Expr::Logic(LogicExpr {
+                left,
+                operator,
+                right,
+                ..
+            }) => Expr::logic(
+                left.into_owned_with(it),
+                own_token(operator, it),
+                right.into_owned_with(it),
+            ),
+            Expr::Literal(expr) => Expr::Literal(expr),
+            Expr::Error(expr) => Expr::Error(expr),
+            Expr::Unary(UnaryExpr {
+                operator, right, ..
+            }) => Expr::unary(own_token(operator, it), right.into_owned_with(it)),
+            Expr::Id(IdExpr { name, .. }) => Expr::id(own_token(name, it)),
+            Expr::Assign(AssignExpr {
+                left,
+                operator,
+                right,
+                ..
+            }) => Expr::assign(
+                left.into_owned_with(it),
+                own_token(operator, it),
+                right.into_owned_with(it),
+            ),
+            Expr::Call(CallExpr {
+                callee,
+                arguments,
+                r_paren,
+                ..
+            }) => Expr::call(
+                callee.into_owned_with(it),
+                arguments
+                    .into_iter()
+                    .map(|arg| arg.into_owned_with(it))
+                    .collect(),
+                own_token(r_paren, it),
+            ),
+            Expr::If(IfExpr {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            }) => Expr::if_expr(
+                condition.into_owned_with(it),
+                then_branch.into_owned_with(it),
+                else_branch.map(|expr| expr.into_owned_with(it)),
+            ),
+            Expr::Get(GetExpr { object, name, .. }) => {
+                Expr::get(object.into_owned_with(it), own_token(name, it))
+            }
+            Expr::Set(SetExpr {
+                object,
+                name,
+                value,
+                ..
+            }) => Expr::set(
+                object.into_owned_with(it),
+                own_token(name, it),
+                value.into_owned_with(it),
+            ),
+        };
+        expr.with_span(span)
+    }
+
+    pub fn binary(left: Expr<'a>, operator: Arc<Token<'a>>, right: Expr<'a>) -> Expr<'a> {
         Expr::Binary(BinaryExpr {
+            span: left.span().to(right.span()),
             left: Box::new(left),
             operator,
             right: Box::new(right),
         })
     }
 
-    pub fn logic(left: Expr<'a>, operator: Rc<Token<'a>>, right: Expr<'a>) -> Self {
+    pub fn logic(left: Expr<'a>, operator: Arc<Token<'a>>, right: Expr<'a>) -> Self {
         Expr::Logic(LogicExpr {
+            span: left.span().to(right.span()),
             left: Box::new(left),
             operator,
             right: Box::new(right),
@@ -61,30 +196,39 @@ impl<'a> Expr<'a> {
     }
 
     pub fn literal(value: Value) -> Self {
-        Expr::Literal(LiteralExpr { value })
+        Expr::Literal(LiteralExpr {
+            value,
+            span: Span::default(),
+        })
     }
 
-    pub fn unary(operator: Rc<Token<'a>>, right: Expr<'a>) -> Self {
+    pub fn unary(operator: Arc<Token<'a>>, right: Expr<'a>) -> Self {
         Expr::Unary(UnaryExpr {
+            span: operator.span.to(right.span()),
             operator,
             right: Box::new(right),
         })
     }
 
-    pub fn id(token: Rc<Token<'a>>) -> Self {
-        Expr::Id(IdExpr { name: token })
+    pub fn id(token: Arc<Token<'a>>) -> Self {
+        Expr::Id(IdExpr {
+            span: token.span,
+            name: token,
+        })
     }
 
-    pub fn assign(left: Expr<'a>, operator: Rc<Token<'a>>, right: Expr<'a>) -> Self {
+    pub fn assign(left: Expr<'a>, operator: Arc<Token<'a>>, right: Expr<'a>) -> Self {
         Expr::Assign(AssignExpr {
+            span: left.span().to(right.span()),
             left: Box::new(left),
             operator,
             right: Box::new(right),
         })
     }
 
-    pub fn call(callee: Expr<'a>, arguments: Vec<Expr<'a>>, r_paren: Rc<Token<'a>>) -> Self {
+    pub fn call(callee: Expr<'a>, arguments: Vec<Expr<'a>>, r_paren: Arc<Token<'a>>) -> Self {
         Expr::Call(CallExpr {
+            span: callee.span().to(r_paren.span),
             callee: Box::new(callee),
             arguments,
             r_paren,
@@ -96,79 +240,156 @@ impl<'a> Expr<'a> {
         then_branch: Expr<'a>,
         else_branch: Option<Expr<'a>>,
     ) -> Self {
+        let end = else_branch.as_ref().unwrap_or(&then_branch).span();
         Expr::If(IfExpr {
+            span: condition.span().to(end),
             condition: Box::new(condition),
             then_branch: Box::new(then_branch),
             else_branch: else_branch.map(Box::new),
         })
     }
 
-    pub fn get(object: Expr<'a>, name: Rc<Token<'a>>) -> Self {
+    pub fn get(object: Expr<'a>, name: Arc<Token<'a>>) -> Self {
         Expr::Get(GetExpr {
+            span: object.span().to(name.span),
             object: Box::new(object),
             name,
         })
     }
 
-    pub fn set(object: Expr<'a>, name: Rc<Token<'a>>, value: Expr<'a>) -> Self {
+    pub fn set(object: Expr<'a>, name: Arc<Token<'a>>, value: Expr<'a>) -> Self {
         Expr::Set(SetExpr {
+            span: object.span().to(value.span()),
             object: Box::new(object),
             name,
             value: Box::new(value),
         })
     }
+
+    pub fn error(span: Span) -> Self {
+        Expr::Error(ErrorExpr { span })
+    }
+
+    /// 语法树中是否含有 `Expr::Error` 节点
+    pub fn has_error(&self) -> bool {
+        match self {
+            Expr::Binary(BinaryExpr { left, right, .. })
+            | Expr::Logic(LogicExpr { left, right, .. })
+            | Expr::Assign(AssignExpr { left, right, .. }) => left.has_error() || right.has_error(),
+            Expr::Unary(UnaryExpr { right, .. }) => right.has_error(),
+            Expr::Call(CallExpr {
+                callee, arguments, ..
+            }) => callee.has_error() || arguments.iter().any(Expr::has_error),
+            Expr::If(IfExpr {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            }) => {
+                condition.has_error()
+                    || then_branch.has_error()
+                    || else_branch.as_ref().is_some_and(|e| e.has_error())
+            }
+            Expr::Get(GetExpr { object, .. }) => object.has_error(),
+            Expr::Set(SetExpr { object, value, .. }) => object.has_error() || value.has_error(),
+            Expr::Literal(_) | Expr::Id(_) => false,
+            Expr::Error(_) => true,
+        }
+    }
+}
+
+fn own_token(token: Arc<Token>, interner: &mut Interner) -> Arc<Token<'static>> {
+    let token = Arc::try_unwrap(token).unwrap_or_else(|token| (*token).clone());
+    Arc::new(token.into_owned_with(interner))
 }
 
+#[derive(Clone)]
 pub struct BinaryExpr<'a> {
     pub left: Box<Expr<'a>>,
-    pub operator: Rc<Token<'a>>,
+    pub operator: Arc<Token<'a>>,
     pub right: Box<Expr<'a>>,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct LogicExpr<'a> {
     pub left: Box<Expr<'a>>,
-    pub operator: Rc<Token<'a>>,
+    pub operator: Arc<Token<'a>>,
     pub right: Box<Expr<'a>>,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct LiteralExpr {
     pub value: Value,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct UnaryExpr<'a> {
-    pub operator: Rc<Token<'a>>,
+    pub operator: Arc<Token<'a>>,
     pub right: Box<Expr<'a>>,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct IdExpr<'a> {
-    pub name: Rc<Token<'a>>,
+    pub name: Arc<Token<'a>>,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct AssignExpr<'a> {
     pub left: Box<Expr<'a>>,
-    pub operator: Rc<Token<'a>>,
+    pub operator: Arc<Token<'a>>,
     pub right: Box<Expr<'a>>,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct CallExpr<'a> {
     pub callee: Box<Expr<'a>>,
     pub arguments: Vec<Expr<'a>>,
-    pub r_paren: Rc<Token<'a>>,
+    pub r_paren: Arc<Token<'a>>,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct IfExpr<'a> {
     pub condition: Box<Expr<'a>>,
     pub then_branch: Box<Expr<'a>>,
     pub else_branch: Option<Box<Expr<'a>>>,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct GetExpr<'a> {
     pub object: Box<Expr<'a>>,
-    pub name: Rc<Token<'a>>,
+    pub name: Arc<Token<'a>>,
+    pub span: Span,
 }
 
+#[derive(Clone)]
 pub struct SetExpr<'a> {
     pub object: Box<Expr<'a>>,
-    pub name: Rc<Token<'a>>,
+    pub name: Arc<Token<'a>>,
     pub value: Box<Expr<'a>>,
+    pub span: Span,
+}
+
+/// 出错部分在源码中的位置，错误本身由解析器另外返回
+#[derive(Clone)]
+pub struct ErrorExpr {
+    pub span: Span,
+}
+
+impl ErrorExpr {
+    /// 执行或编译到出错节点时返回的错误
+    pub fn to_error(&self) -> RspError {
+        RspError::ParseError {
+            span: self.span,
+            message: "Expression contains syntax errors".to_string(),
+            kind: ErrorKind::Other,
+        }
+    }
 }
@@ -1,9 +1,9 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct Field {
     name: String,
-    owner: Option<Rc<Field>>,
+    owner: Option<Arc<Field>>,
     src: Option<String>,
 }
 
@@ -16,7 +16,7 @@ impl Field {
         }
     }
 
-    pub fn with_owner(name: &str, owner: Rc<Field>) -> Self {
+    pub fn with_owner(name: &str, owner: Arc<Field>) -> Self {
         Field {
             name: name.to_string(),
             owner: Some(owner),
@@ -24,27 +24,23 @@ impl Field {
         }
     }
 
-    pub fn with_str(src: &str) -> Rc<Field> {
-        let mut cur: Option<Rc<Field>> = None;
-        for name in src.split('.') {
-            let field = match cur {
-                Some(ref owner) => Field::with_owner(name, owner.clone()),
-                None => Field::new(name),
-            };
-            cur = Some(Rc::new(field));
-        }
-        cur.unwrap()
+    pub fn with_str(src: &str) -> Arc<Field> {
+        let mut names = src.split('.');
+        let first = Arc::new(Field::new(names.next().unwrap_or_default()));
+        names.fold(first, |owner, name| {
+            Arc::new(Field::with_owner(name, owner))
+        })
     }
 
     pub fn get_name(&self) -> &str {
         &self.name
     }
 
-    pub fn get_owner(&self) -> Option<Rc<Field>> {
+    pub fn get_owner(&self) -> Option<Arc<Field>> {
         self.owner.clone()
     }
 
-    fn search(field: Option<Rc<Field>>, path: &mut Vec<String>) {
+    fn search(field: Option<Arc<Field>>, path: &mut Vec<String>) {
         if let Some(f) = field {
             Field::search(f.owner.clone(), path);
             path.push(f.name.clone());
@@ -58,7 +54,7 @@ impl std::fmt::Display for Field {
             Some(s) => s,
             None => {
                 let mut path = Vec::new();
-                Field::search(Some(Rc::new(self.clone())), &mut path);
+                Field::search(Some(Arc::new(self.clone())), &mut path);
                 &path.join(".")
             }
         };
@@ -1,9 +1,15 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use crate::values::Value;
+use crate::{ErrorKind, RspError, RspResult};
 
-pub trait Callable {
-    fn call(&self, arguments: Vec<Value>) -> Value;
+pub trait Callable: Send + Sync {
+    fn call(&self, arguments: Vec<Value>) -> RspResult<Value>;
     fn arity(&self) -> usize;
+    /// 相同参数总是返回相同结果且没有副作用，参数都是常量时调用可以在编译前预先计算
+    fn is_pure(&self) -> bool {
+        false
+    }
 }
 
 pub struct Function {
@@ -13,11 +19,19 @@ pub struct Function {
 }
 
 impl Callable for Function {
-    fn call(&self, arguments: Vec<Value>) -> Value {
+    fn call(&self, arguments: Vec<Value>) -> RspResult<Value> {
         if arguments.len() != self.arity {
-            panic!("Expected {} arguments but got {}", self.arity, arguments.len());
+            return Err(RspError::RuntimeError {
+                message: format!(
+                    "Expected {} arguments but got {}",
+                    self.arity,
+                    arguments.len()
+                ),
+                span: None,
+                kind: ErrorKind::ArityMismatch,
+            });
         }
-        (self.body)(arguments)
+        Ok((self.body)(arguments))
     }
 
     fn arity(&self) -> usize {
@@ -26,7 +40,13 @@ impl Callable for Function {
 }
 
 pub struct FunctionManager {
-    functions: HashMap<String, Box<dyn Callable>>,
+    functions: HashMap<String, Arc<Box<dyn Callable>>>,
+}
+
+impl Default for FunctionManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FunctionManager {
@@ -39,11 +59,22 @@ impl FunctionManager {
     }
 
     pub fn register(&mut self, name: String, callable: Box<dyn Callable>) {
-        self.functions.insert(name, callable);
+        self.functions.insert(name, Arc::new(callable));
     }
 
+    #[allow(clippy::borrowed_box)]
     pub fn get(&self, name: &str) -> Option<&Box<dyn Callable>> {
-        self.functions.get(name)
+        self.functions.get(name).map(Arc::as_ref)
+    }
+
+    /// 取得可以脱离 `FunctionManager` 单独持有的函数，供编译期预先解析函数调用
+    pub fn get_shared(&self, name: &str) -> Option<Arc<Box<dyn Callable>>> {
+        self.functions.get(name).cloned()
+    }
+
+    /// 已注册的函数名
+    pub fn names(&self) -> Vec<&str> {
+        self.functions.keys().map(String::as_str).collect()
     }
 
     fn register_builtins(&mut self) {
@@ -57,13 +88,17 @@ impl FunctionManager {
 pub struct ClockFunction;
 
 impl Callable for ClockFunction {
-    fn call(&self, _arguments: Vec<Value>) -> Value {
+    fn call(&self, _arguments: Vec<Value>) -> RspResult<Value> {
         use std::time::{SystemTime, UNIX_EPOCH};
         let duration = SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .unwrap()
+            .map_err(|e| RspError::RuntimeError {
+                message: format!("System clock is before UNIX epoch: {}", e),
+                span: None,
+                kind: ErrorKind::Other,
+            })?
             .as_secs_f64();
-        Value::Double(duration)
+        Ok(Value::Double(duration))
     }
 
     fn arity(&self) -> usize {
@@ -74,19 +109,20 @@ impl Callable for ClockFunction {
 pub struct AbsFunction;
 
 impl Callable for AbsFunction {
-    fn call(&self, arguments: Vec<Value>) -> Value {
-        if let Some(value) = arguments.get(0) {
-            match value {
-                Value::Integer(i) => Value::Integer(i.abs()),
-                Value::Double(d) => Value::Double(d.abs()),
-                _ => Value::Null,
-            }
-        } else {
-            Value::Null
-        }
+    fn call(&self, arguments: Vec<Value>) -> RspResult<Value> {
+        let value = match arguments.first() {
+            Some(Value::Integer(i)) => Value::Integer(i.wrapping_abs()),
+            Some(Value::Double(d)) => Value::Double(d.abs()),
+            _ => Value::Null,
+        };
+        Ok(value)
     }
 
     fn arity(&self) -> usize {
         1
     }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
 }
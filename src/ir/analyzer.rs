@@ -1,13 +1,17 @@
 use super::digraph::{Digraph, TopologicalSort};
+use super::levels::split_levels;
+use super::targets::select_for_targets;
 use crate::expr::Expr;
-use crate::ir::ExprInfo;
 use crate::ir::node_set::{Node, NodeSet};
+use crate::ir::{ExecutionPlan, ExprInfo};
+use crate::parser::Interner;
 use crate::{RspError, RspResult};
 
 pub struct Analyzer<'a> {
     expr_infos: Vec<ExprInfo<'a>>,
     node_set: NodeSet<usize>,
-    graph: Digraph,
+    /// 构建依赖图失败时保存错误信息，在排序时报告
+    graph: Result<Digraph, String>,
     need_sort: bool,
 }
 
@@ -34,77 +38,139 @@ impl<'a> Analyzer<'a> {
         expr_infos
     }
 
+    /// 每个公式和每个变量各对应图中的一个节点。公式节点排在前面，
+    /// 节点序号与公式序号相同，变量节点排在所有公式节点之后。
     fn init_node_set(expr_infos: &Vec<ExprInfo>) -> NodeSet<usize> {
         let mut node_set = NodeSet::new();
         for expr_info in expr_infos {
-            if !expr_info.is_assign() {
-                continue;
-            }
-
+            let index = expr_info.get_index();
+            node_set.add_node_with_info(&Self::expr_node_name(index), Some(index));
+        }
+        for expr_info in expr_infos {
             for name in expr_info.get_reads() {
                 node_set.add_node(name);
             }
-
-            let mut flag = true;
             for name in expr_info.get_writes() {
-                let node = node_set.add_node(name);
-                if flag {
-                    node.info = Some(expr_info.get_index());
-                    flag = false;
-                }
+                node_set.add_node(name);
             }
         }
         node_set
     }
 
-    fn init_graph(node_set: &NodeSet<usize>, expr_infos: &Vec<ExprInfo>) -> Digraph {
+    /// 边的方向：被读取的变量 -> 公式 -> 被赋值的变量
+    fn init_graph(
+        node_set: &NodeSet<usize>,
+        expr_infos: &Vec<ExprInfo>,
+    ) -> Result<Digraph, String> {
+        let index_of = |name: &str| {
+            node_set
+                .get_node(name)
+                .map(|node| node.index)
+                .ok_or_else(|| format!("Node not found: {}", name))
+        };
         let mut graph = Digraph::new(node_set.size());
-        if node_set.size() == 0 {
-            return graph;
-        }
         for info in expr_infos {
-            if !info.is_assign() {
-                continue;
-            }
+            let e = index_of(&Self::expr_node_name(info.get_index()))?;
             for prec in info.get_reads() {
-                let pre_node = node_set.get_node(prec).expect("pre node not found");
-                let u = pre_node.index;
-                for succ in info.get_writes() {
-                    let succ_node = node_set.get_node(succ).expect("succ node not found");
-                    let v = succ_node.index;
-                    graph.add_edge(u, v);
-                }
+                graph.add_edge(index_of(prec)?, e)?;
+            }
+            for succ in info.get_writes() {
+                graph.add_edge(e, index_of(succ)?)?;
             }
         }
-        graph
+        Ok(graph)
+    }
+
+    /// 公式节点的名称，以 `#` 开头，不会与变量名冲突
+    fn expr_node_name(index: usize) -> String {
+        format!("#{}", index)
     }
 
     pub fn analyze(&self) -> RspResult<Vec<&ExprInfo<'a>>> {
         if self.need_sort && !self.expr_infos.is_empty() && self.has_assign() {
             return self.sort();
         }
-        return Ok(self
+        Ok(self
             .expr_infos
             .iter()
             .map(|item| -> &ExprInfo { item })
-            .collect());
+            .collect())
+    }
+
+    /// 在排序结果的基础上按依赖关系分层，同一层内的公式互不依赖，可以并行执行
+    pub fn analyze_levels(&self) -> RspResult<Vec<Vec<&ExprInfo<'a>>>> {
+        let expr_infos = self.analyze()?;
+        let levels = split_levels(
+            expr_infos
+                .iter()
+                .map(|info| (info.get_reads(), info.get_writes())),
+        );
+        Ok(levels
+            .into_iter()
+            .map(|level| level.into_iter().map(|pos| expr_infos[pos]).collect())
+            .collect())
+    }
+
+    /// 分析并生成执行计划，执行计划不再借用源字符串
+    pub fn into_plan(self) -> RspResult<ExecutionPlan> {
+        let size = self.expr_infos.len();
+        let orders: Vec<usize> = self
+            .analyze()?
+            .iter()
+            .map(|info| info.get_index())
+            .collect();
+        let mut infos: Vec<Option<ExprInfo<'a>>> = self.expr_infos.into_iter().map(Some).collect();
+        // 所有公式共用一个驻留池，同名变量只保存一份字符串
+        let mut interner = Interner::new();
+        let expr_infos: Vec<ExprInfo<'static>> = orders
+            .into_iter()
+            .filter_map(|index| infos[index].take())
+            .map(|info| info.into_owned_with(&mut interner))
+            .collect();
+        let levels = split_levels(
+            expr_infos
+                .iter()
+                .map(|info| (info.get_reads(), info.get_writes())),
+        );
+        Ok(ExecutionPlan::new(expr_infos, levels, size))
+    }
+
+    /// 只返回计算 `targets` 中变量所需的公式（包括间接依赖的公式），保持排序后的顺序
+    pub fn analyze_targets(&self, targets: &[&str]) -> RspResult<Vec<&ExprInfo<'a>>> {
+        let expr_infos = self.analyze()?;
+        let items: Vec<_> = expr_infos
+            .iter()
+            .map(|info| (info.get_reads(), info.get_writes()))
+            .collect();
+        Ok(select_for_targets(&items, targets)
+            .into_iter()
+            .map(|pos| expr_infos[pos])
+            .collect())
     }
 
     fn has_assign(&self) -> bool {
-        self.graph.v() > 0
+        self.expr_infos
+            .iter()
+            .any(|info| !info.get_writes().is_empty())
     }
 
     fn sort(&self) -> RspResult<Vec<&ExprInfo<'a>>> {
+        let graph = self
+            .graph
+            .as_ref()
+            .map_err(|message| RspError::AnalyzeError {
+                message: message.clone(),
+            })?;
         let mut result = Vec::new();
-        if self.graph.v() == 0 {
+        if graph.v() == 0 {
             return Ok(result);
         }
 
-        let mut top_sorter = TopologicalSort::new(&self.graph);
+        let mut top_sorter = TopologicalSort::new(graph);
 
         if !top_sorter.sort() {
             return Err(RspError::AnalyzeError {
-                message: format!("{}", "公式列表存在循环引用！"),
+                message: "公式列表存在循环引用！".to_string(),
             });
         }
 
@@ -114,17 +180,11 @@ impl<'a> Analyzer<'a> {
         };
 
         for &node_index in node_orders {
-            if let Some(Node { info, .. }) = self.node_set.get_node_by_index(node_index) {
-                if let Some(index) = info {
-                    let expr_info = &self.expr_infos[*index];
-                    result.push(expr_info);
-                }
-            }
-        }
-
-        for expr in &self.expr_infos {
-            if !expr.is_assign() {
-                result.push(expr);
+            if let Some(Node { info, .. }) = self.node_set.get_node_by_index(node_index)
+                && let Some(index) = info
+            {
+                let expr_info = &self.expr_infos[*index];
+                result.push(expr_info);
             }
         }
         Ok(result)
@@ -203,10 +263,10 @@ mod tests {
         assert!(expr_infos.len() == 6);
         assert_eq!("a = m + n", srcs[expr_infos[0].get_index()]);
         assert_eq!("b = a * 2", srcs[expr_infos[1].get_index()]);
-        assert_eq!("c = n + w + b", srcs[expr_infos[2].get_index()]);
-        assert_eq!("x = y = a + b * c", srcs[expr_infos[3].get_index()]);
-        assert_eq!("b * 2 + 1", srcs[expr_infos[4].get_index()]);
-        assert_eq!("a * b + c", srcs[expr_infos[5].get_index()]);
+        assert_eq!("b * 2 + 1", srcs[expr_infos[2].get_index()]);
+        assert_eq!("c = n + w + b", srcs[expr_infos[3].get_index()]);
+        assert_eq!("a * b + c", srcs[expr_infos[4].get_index()]);
+        assert_eq!("x = y = a + b * c", srcs[expr_infos[5].get_index()]);
 
         runner = RspRunner::new();
         let mut env = DefaultEnvironment::new();
@@ -228,4 +288,56 @@ mod tests {
         assert_eq!(22, result[5].as_integer());
         println!("==========");
     }
+
+    #[test]
+    fn test_levels() {
+        let srcs = vec![
+            "x = y = a + b * c",
+            "a = m + n",
+            "b = a * 2",
+            "c = n + w + b",
+            "d = m * 2",
+        ];
+        let mut runner = RspRunner::new();
+        let exprs = runner.parse(&srcs).unwrap();
+        let ana = Analyzer::new(exprs, true);
+        let levels = ana.analyze_levels().unwrap();
+        let levels: Vec<Vec<&str>> = levels
+            .iter()
+            .map(|level| level.iter().map(|info| srcs[info.get_index()]).collect())
+            .collect();
+        assert_eq!(4, levels.len());
+        assert_eq!(2, levels[0].len());
+        assert!(levels[0].contains(&"a = m + n"));
+        assert!(levels[0].contains(&"d = m * 2"));
+        assert_eq!(vec!["b = a * 2"], levels[1]);
+        assert_eq!(vec!["c = n + w + b"], levels[2]);
+        assert_eq!(vec!["x = y = a + b * c"], levels[3]);
+    }
+
+    #[test]
+    fn test_plain_expressions() {
+        let srcs = vec!["a + 1", "1 + (a = m * 2)", "a * 2 > 10", "m + 1"];
+        let mut runner = RspRunner::new();
+        let exprs = runner.parse(&srcs).unwrap();
+        let ana = Analyzer::new(exprs, true);
+        let expr_infos = ana.analyze().unwrap();
+        let orders: Vec<&str> = expr_infos
+            .iter()
+            .map(|info| srcs[info.get_index()])
+            .collect();
+        assert_eq!(
+            vec!["1 + (a = m * 2)", "m + 1", "a + 1", "a * 2 > 10"],
+            orders
+        );
+    }
+
+    #[test]
+    fn test_cycle() {
+        let srcs = vec!["a + b", "a = b + 1", "b = a * 2"];
+        let mut runner = RspRunner::new();
+        let exprs = runner.parse(&srcs).unwrap();
+        let ana = Analyzer::new(exprs, true);
+        assert!(matches!(ana.analyze(), Err(RspError::AnalyzeError { .. })));
+    }
 }
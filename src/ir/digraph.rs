@@ -1,4 +1,5 @@
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fmt;
 
 pub struct Digraph {
@@ -26,40 +27,42 @@ impl Digraph {
         self.e
     }
 
-    fn validate_vertex(&self, v: usize) {
+    fn validate_vertex(&self, v: usize) -> Result<(), String> {
         if v >= self.v {
-            panic!("vertex {} is not between 0 and {}", v, self.v - 1);
+            return Err(format!("vertex {} is not between 0 and {}", v, self.v));
         }
+        Ok(())
     }
 
-    pub fn add_edge(&mut self, v: usize, w: usize) {
-        self.validate_vertex(v);
-        self.validate_vertex(w);
+    pub fn add_edge(&mut self, v: usize, w: usize) -> Result<(), String> {
+        self.validate_vertex(v)?;
+        self.validate_vertex(w)?;
         self.adj[v].push(w);
         self.indegree[w] += 1;
         self.e += 1;
+        Ok(())
     }
 
+    /// 不存在的节点没有相邻节点
     pub fn adj(&self, v: usize) -> &[usize] {
-        self.validate_vertex(v);
-        &self.adj[v]
+        self.adj.get(v).map_or(&[], Vec::as_slice)
     }
 
     pub fn _outdegree(&self, v: usize) -> usize {
-        self.validate_vertex(v);
-        self.adj[v].len()
+        self.adj(v).len()
     }
 
     pub fn indegree(&self, v: usize) -> usize {
-        self.validate_vertex(v);
-        self.indegree[v]
+        self.indegree.get(v).copied().unwrap_or(0)
     }
 
     pub fn _reverse(&self) -> Digraph {
         let mut reverse = Digraph::new(self.v);
         for v in 0..self.v {
             for &w in &self.adj[v] {
-                reverse.add_edge(w, v);
+                reverse.adj[w].push(v);
+                reverse.indegree[v] += 1;
+                reverse.e += 1;
             }
         }
         reverse
@@ -102,22 +105,24 @@ impl<'a> TopologicalSort<'a> {
             self.indegree[v] = self.g.indegree(v);
         }
 
-        let mut queue = VecDeque::new();
+        // 每次取出入度为0的节点中序号最小的一个，使得排序结果稳定，
+        // 且在依赖关系允许的情况下尽量保持节点原有的先后顺序
+        let mut queue = BinaryHeap::new();
         for v in 0..v_count {
             if self.indegree[v] == 0 {
-                queue.push_back(v);
+                queue.push(Reverse(v));
             }
         }
 
         let mut count = 0;
         let mut order = vec![0; v_count];
-        while let Some(u) = queue.pop_front() {
+        while let Some(Reverse(u)) = queue.pop() {
             order[count] = u;
             count += 1;
             for &v in self.g.adj(u) {
                 self.indegree[v] -= 1;
                 if self.indegree[v] == 0 {
-                    queue.push_back(v);
+                    queue.push(Reverse(v));
                 }
             }
         }
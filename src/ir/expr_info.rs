@@ -1,7 +1,9 @@
 use crate::expr::Expr;
+use crate::parser::Interner;
 use crate::visitors::VarsQuery;
 use std::collections::HashSet;
 
+#[derive(Clone)]
 pub struct ExprInfo<'a> {
     reads: HashSet<String>,  // 依赖的变量 read
     writes: HashSet<String>, // 被赋值的变量 write
@@ -21,6 +23,20 @@ impl<'a> ExprInfo<'a> {
         info
     }
 
+    /// 转换为不再借用源字符串的中间结果
+    pub fn into_owned(self) -> ExprInfo<'static> {
+        self.into_owned_with(&mut Interner::new())
+    }
+
+    pub fn into_owned_with(self, interner: &mut Interner) -> ExprInfo<'static> {
+        ExprInfo {
+            reads: self.reads,
+            writes: self.writes,
+            expr: self.expr.into_owned_with(interner),
+            index: self.index,
+        }
+    }
+
     fn init_variables(&mut self) {
         let mut var_query = VarsQuery::new();
         if let Some(var_set) = var_query.execute(&self.expr) {
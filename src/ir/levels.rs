@@ -0,0 +1,80 @@
+use std::collections::{HashMap, HashSet};
+
+/// 按读写依赖把排好序的公式分层，返回每层公式在 `items` 中的位置。
+/// 同一层的公式互不读写对方写入的变量，写入在整层执行完后再合并时可以并行执行
+pub fn split_levels<'s, I>(items: I) -> Vec<Vec<usize>>
+where
+    I: IntoIterator<Item = (&'s HashSet<String>, &'s HashSet<String>)>,
+{
+    let mut levels: Vec<Vec<usize>> = Vec::new();
+    let mut write_levels: HashMap<&str, usize> = HashMap::new();
+    let mut read_levels: HashMap<&str, usize> = HashMap::new();
+
+    for (pos, (reads, writes)) in items.into_iter().enumerate() {
+        let mut level = 0;
+        for name in reads {
+            // 读后于写：必须在写入该变量的公式之后
+            if let Some(&l) = write_levels.get(name.as_str()) {
+                level = level.max(l + 1);
+            }
+        }
+        for name in writes {
+            // 写后于写：保持原有的先后顺序
+            if let Some(&l) = write_levels.get(name.as_str()) {
+                level = level.max(l + 1);
+            }
+            // 写后于读：同一层的读取看到的是旧值，因此不能早于读取所在的层
+            if let Some(&l) = read_levels.get(name.as_str()) {
+                level = level.max(l);
+            }
+        }
+
+        for name in reads {
+            let entry = read_levels.entry(name.as_str()).or_insert(level);
+            *entry = (*entry).max(level);
+        }
+        for name in writes {
+            write_levels.insert(name.as_str(), level);
+        }
+
+        if level == levels.len() {
+            levels.push(Vec::new());
+        }
+        levels[level].push(pos);
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_levels() {
+        // a = m + n; b = a * 2; c = n + w; x = a + b * c; a + c
+        let items = [
+            (set(&["m", "n"]), set(&["a"])),
+            (set(&["a"]), set(&["b"])),
+            (set(&["n", "w"]), set(&["c"])),
+            (set(&["a", "b", "c"]), set(&["x"])),
+            (set(&["a", "c"]), set(&[])),
+        ];
+        let levels = split_levels(items.iter().map(|(r, w)| (r, w)));
+        assert_eq!(vec![vec![0, 2], vec![1, 4], vec![3]], levels);
+    }
+
+    #[test]
+    fn test_write_after_write() {
+        let items = [
+            (set(&[]), set(&["a"])),
+            (set(&[]), set(&["a"])),
+            (set(&["b"]), set(&[])),
+        ];
+        let levels = split_levels(items.iter().map(|(r, w)| (r, w)));
+        assert_eq!(vec![vec![0, 2], vec![1]], levels);
+    }
+}
@@ -1,7 +1,11 @@
 pub mod analyzer;
 mod digraph;
 pub mod expr_info;
+pub mod levels;
 mod node_set;
+pub mod plan;
+pub mod targets;
 
 pub use analyzer::Analyzer;
 pub use expr_info::ExprInfo;
+pub use plan::ExecutionPlan;
@@ -92,7 +92,7 @@ impl<T> NodeSet<T> {
     }
 }
 
-impl<'a, T> fmt::Display for NodeSet<T> {
+impl<T> fmt::Display for NodeSet<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for i in 0..self.cnt {
             if let Some(node) = self.get_node_by_index(i) {
@@ -0,0 +1,70 @@
+use std::collections::BTreeSet;
+
+use crate::ir::ExprInfo;
+
+/// 分析器的完整输出：排好序的公式及其读写变量、分层结构和输入输出变量列表。
+///
+/// 执行计划不借用源字符串，可以克隆、缓存或在线程间传递，
+/// 同时可以用于解释执行（`RspRunner::run_plan`）和编译（`RspRunner::compile_plan`）。
+#[derive(Clone)]
+pub struct ExecutionPlan {
+    expr_infos: Vec<ExprInfo<'static>>,
+    levels: Vec<Vec<usize>>,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    size: usize,
+}
+
+impl ExecutionPlan {
+    /// `expr_infos` 为排好序的公式，`levels` 为各层公式在 `expr_infos` 中的位置，
+    /// `size` 为分析前的公式总数
+    pub fn new(expr_infos: Vec<ExprInfo<'static>>, levels: Vec<Vec<usize>>, size: usize) -> Self {
+        let mut reads = BTreeSet::new();
+        let mut writes = BTreeSet::new();
+        for info in &expr_infos {
+            reads.extend(info.get_reads().iter().cloned());
+            writes.extend(info.get_writes().iter().cloned());
+        }
+        let inputs = reads.difference(&writes).cloned().collect();
+        let outputs = writes.into_iter().collect();
+        Self {
+            expr_infos,
+            levels,
+            inputs,
+            outputs,
+            size,
+        }
+    }
+
+    /// 按执行顺序排列的公式
+    pub fn expr_infos(&self) -> &[ExprInfo<'static>] {
+        &self.expr_infos
+    }
+
+    /// 按依赖关系分层后的公式，同一层内的公式互不依赖
+    pub fn levels(&self) -> Vec<Vec<&ExprInfo<'static>>> {
+        self.levels
+            .iter()
+            .map(|level| level.iter().map(|&pos| &self.expr_infos[pos]).collect())
+            .collect()
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// 只被读取、没有被任何公式赋值的变量，需要由环境提供，按名称排序
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    /// 被公式赋值的变量，按名称排序
+    pub fn outputs(&self) -> &[String] {
+        &self.outputs
+    }
+
+    /// 分析前的公式总数，即执行结果的长度
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
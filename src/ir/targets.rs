@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+
+/// 从排好序的公式中挑选出计算 `targets` 所必需的公式。
+///
+/// `items` 为按执行顺序排列的各公式读写变量集合，返回值为被选中公式在 `items`
+/// 中的位置，按执行顺序排列。从后往前扫描：公式写入了仍需要的变量时被选中，
+/// 其写入的变量不再需要，而它读取的变量成为新的需要。
+pub fn select_for_targets(
+    items: &[(&HashSet<String>, &HashSet<String>)],
+    targets: &[&str],
+) -> Vec<usize> {
+    let mut needed: HashSet<&str> = targets.iter().copied().collect();
+    let mut selected = Vec::new();
+    for (pos, (reads, writes)) in items.iter().enumerate().rev() {
+        if !writes.iter().any(|name| needed.contains(name.as_str())) {
+            continue;
+        }
+        selected.push(pos);
+        for name in writes.iter() {
+            needed.remove(name.as_str());
+        }
+        needed.extend(reads.iter().map(String::as_str));
+    }
+    selected.reverse();
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_select() {
+        // a = m + n; b = a * 2; c = n + w; x = a + b; y = c * 2
+        let items = [
+            (set(&["m", "n"]), set(&["a"])),
+            (set(&["a"]), set(&["b"])),
+            (set(&["n", "w"]), set(&["c"])),
+            (set(&["a", "b"]), set(&["x"])),
+            (set(&["c"]), set(&["y"])),
+        ];
+        let items: Vec<_> = items.iter().map(|(r, w)| (r, w)).collect();
+        assert_eq!(vec![0, 1, 3], select_for_targets(&items, &["x"]));
+        assert_eq!(vec![2, 4], select_for_targets(&items, &["y"]));
+        assert_eq!(vec![0, 1], select_for_targets(&items, &["b"]));
+        assert!(select_for_targets(&items, &["m"]).is_empty());
+    }
+
+    #[test]
+    fn test_overwrite() {
+        // a = 1; a = a + m; x = a
+        let items = [
+            (set(&[]), set(&["a"])),
+            (set(&["a", "m"]), set(&["a"])),
+            (set(&["a"]), set(&["x"])),
+        ];
+        let items: Vec<_> = items.iter().map(|(r, w)| (r, w)).collect();
+        assert_eq!(vec![0, 1, 2], select_for_targets(&items, &["x"]));
+        assert_eq!(vec![0, 1], select_for_targets(&items, &["a"]));
+    }
+}
@@ -4,22 +4,34 @@
 //! that supports both syntax tree interpretation and bytecode virtual machine execution.
 
 pub mod chunk;
+pub mod column;
+pub mod diagnostic;
 pub mod environment;
 pub mod error;
 pub mod expr;
 pub mod field;
 pub mod functions;
 pub mod ir;
+pub mod limits;
+pub mod parallel;
 pub mod parser;
 pub mod runner;
+pub mod span;
 pub mod values;
 pub mod visitors;
 pub mod vm;
 
-pub use chunk::Chunk;
-pub use environment::{DefaultEnvironment, Environment};
-pub use error::{RspError, RspResult};
+pub use chunk::{Chunk, Program};
+pub use column::{Batch, Column};
+pub use diagnostic::{Diagnostic, ErrorCode};
+pub use environment::{
+    DefaultEnvironment, DefaultSlotEnvironment, Environment, OverlayEnvironment, SlotEnvironment,
+};
+pub use error::{ErrorKind, RspError, RspResult};
 pub use field::Field;
-pub use parser::{Parser, Scanner, Token, TokenType};
+pub use limits::{CancelToken, Limits};
+pub use parser::{Parser, PartialParse, Scanner, Token, TokenType};
 pub use runner::{ExecuteMode, RspRunner};
+pub use span::Span;
 pub use values::Value;
+pub use visitors::OptLevel;
@@ -0,0 +1,164 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{RspError, RspResult, Value};
+
+/// 第一条指令之前以及之后每执行这么多条指令检查一次超时和取消标记，避免每条指令都读取时钟
+const CHECK_INTERVAL: u64 = 1024;
+
+/// 协作式取消标记。克隆后的标记共享同一个状态，可以在其它线程中调用 `cancel`，
+/// 正在执行的公式会在下一次检查时以 [`RspError::Cancelled`] 结束
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// 一次执行的资源限制，超出时返回对应的错误而不是 panic。
+///
+/// 语法树解释执行和闭包执行时每访问一个节点计为一条指令，栈深度为表达式的嵌套深度。
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// 一次执行最多执行的指令数
+    pub max_instructions: Option<u64>,
+    /// 最大栈深度，默认为 [`Limits::DEFAULT_STACK`]
+    pub max_stack: usize,
+    /// 运算和函数调用产生的字符串的最大字节数
+    pub max_string_len: Option<usize>,
+    /// 一次执行的最长时间，从开始执行时计时
+    pub timeout: Option<Duration>,
+    pub cancel_token: Option<CancelToken>,
+}
+
+impl Limits {
+    pub const DEFAULT_STACK: usize = 256;
+
+    /// 不限制指令数、字符串长度和执行时间，栈深度为默认值
+    pub fn new() -> Self {
+        Self {
+            max_instructions: None,
+            max_stack: Self::DEFAULT_STACK,
+            max_string_len: None,
+            timeout: None,
+            cancel_token: None,
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.max_instructions.is_none()
+            && self.max_stack == Self::DEFAULT_STACK
+            && self.max_string_len.is_none()
+            && self.timeout.is_none()
+            && self.cancel_token.is_none()
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 一次执行中已经消耗的资源，按 [`Limits`] 检查
+#[derive(Clone, Debug)]
+pub(crate) struct Budget {
+    executed: u64,
+    max_instructions: u64,
+    max_stack: usize,
+    max_string_len: usize,
+    start: Instant,
+    timeout: Option<Duration>,
+    cancel_token: Option<CancelToken>,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: &Limits) -> Self {
+        Self::started_at(limits, Instant::now())
+    }
+
+    /// 从 `start` 开始计时，并行执行时各个工作线程共用同一个开始时间
+    pub(crate) fn started_at(limits: &Limits, start: Instant) -> Self {
+        Self {
+            executed: 0,
+            max_instructions: limits.max_instructions.unwrap_or(u64::MAX),
+            max_stack: limits.max_stack,
+            max_string_len: limits.max_string_len.unwrap_or(usize::MAX),
+            start,
+            timeout: limits.timeout,
+            cancel_token: limits.cancel_token.clone(),
+        }
+    }
+
+    /// 记录执行了一条指令
+    #[inline]
+    pub(crate) fn step(&mut self) -> RspResult<()> {
+        self.step_n(1)
+    }
+
+    /// 记录执行了 `n` 条指令，按列执行时一条指令计为处理的行数
+    #[inline]
+    pub(crate) fn step_n(&mut self, n: u64) -> RspResult<()> {
+        let before = self.executed;
+        self.executed = before.saturating_add(n);
+        if self.executed > self.max_instructions {
+            return Err(RspError::InstructionLimit {
+                limit: self.max_instructions,
+            });
+        }
+        if before == 0 || before / CHECK_INTERVAL != self.executed / CHECK_INTERVAL {
+            self.check()?;
+        }
+        Ok(())
+    }
+
+    /// 检查是否超时或已被取消
+    pub(crate) fn check(&self) -> RspResult<()> {
+        if let Some(token) = &self.cancel_token
+            && token.is_cancelled()
+        {
+            return Err(RspError::Cancelled);
+        }
+        if let Some(timeout) = self.timeout
+            && self.start.elapsed() > timeout
+        {
+            return Err(RspError::Timeout { timeout });
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn check_stack(&self, depth: usize) -> RspResult<()> {
+        if depth > self.max_stack {
+            return Err(RspError::StackOverflow {
+                limit: self.max_stack,
+            });
+        }
+        Ok(())
+    }
+
+    /// 检查运算结果，字符串不能超过长度限制
+    #[inline]
+    pub(crate) fn check_value(&self, value: &Value) -> RspResult<()> {
+        if let Value::String(s) = value
+            && s.len() > self.max_string_len
+        {
+            return Err(RspError::StringTooLong {
+                length: s.len(),
+                limit: self.max_string_len,
+            });
+        }
+        Ok(())
+    }
+}
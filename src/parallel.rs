@@ -0,0 +1,98 @@
+use crate::environment::{Environment, OverlayEnvironment};
+use crate::{ErrorKind, RspError, RspResult, Value};
+use std::collections::HashMap;
+use std::thread;
+
+/// 每个线程至少分到的公式数量，避免为很小的层创建线程
+const MIN_BATCH: usize = 64;
+
+type BatchOutput = (Vec<(usize, Value)>, HashMap<String, Value>);
+
+/// 默认的工作线程数量：可用的 CPU 核数
+pub fn default_threads() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// 逐层执行公式，每一层按线程数切分后并行执行。
+///
+/// 执行期间共享环境只读，各线程的写入先记录在自己的 [`OverlayEnvironment`] 中，
+/// 整层完成后按原有顺序合并回 `env`。`init` 为每个工作线程创建一次执行状态，
+/// `exec` 执行单个公式并返回 (公式序号, 结果)。
+pub(crate) fn run_levels<T, E, S, I, F>(
+    levels: &[Vec<T>],
+    env: &mut E,
+    threads: usize,
+    init: I,
+    exec: F,
+) -> RspResult<Vec<(usize, Value)>>
+where
+    T: Sync,
+    E: Environment + Sync,
+    I: Fn() -> S + Sync,
+    F: Fn(&mut S, &T, &mut OverlayEnvironment<E>) -> RspResult<(usize, Value)> + Sync,
+{
+    let mut results = Vec::new();
+    for level in levels {
+        if level.is_empty() {
+            continue;
+        }
+        let workers = threads.max(1).min(level.len().div_ceil(MIN_BATCH));
+        let shared: &E = env;
+        let outputs: Vec<RspResult<BatchOutput>> = if workers == 1 {
+            vec![run_batch(level, shared, &init, &exec)]
+        } else {
+            let size = level.len().div_ceil(workers);
+            let (init, exec) = (&init, &exec);
+            thread::scope(|s| {
+                let handles: Vec<_> = level
+                    .chunks(size)
+                    .map(|batch| s.spawn(move || run_batch(batch, shared, init, exec)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle.join().unwrap_or_else(|_| {
+                            Err(RspError::RuntimeError {
+                                message: "Worker thread panicked".to_string(),
+                                span: None,
+                                kind: ErrorKind::Other,
+                            })
+                        })
+                    })
+                    .collect()
+            })
+        };
+
+        for output in outputs {
+            let (batch_results, writes) = output?;
+            results.extend(batch_results);
+            for (name, value) in writes {
+                if !env.put(name.clone(), value) {
+                    return Err(RspError::RuntimeError {
+                        message: format!("Undefined variable: {}", name),
+                        span: None,
+                        kind: ErrorKind::UndefinedVariable { name },
+                    });
+                }
+            }
+        }
+    }
+    Ok(results)
+}
+
+fn run_batch<T, E, S, I, F>(batch: &[T], env: &E, init: &I, exec: &F) -> RspResult<BatchOutput>
+where
+    E: Environment,
+    I: Fn() -> S,
+    F: Fn(&mut S, &T, &mut OverlayEnvironment<E>) -> RspResult<(usize, Value)>,
+{
+    let mut state = init();
+    let mut overlay = OverlayEnvironment::new(env);
+    let mut results = Vec::with_capacity(batch.len());
+    for item in batch {
+        results.push(exec(&mut state, item, &mut overlay)?);
+    }
+    Ok((results, overlay.into_writes()))
+}
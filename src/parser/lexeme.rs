@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// token 的文本。
+///
+/// 直接解析时借用源字符串，不产生任何拷贝；转换为不借用源字符串的语法树后
+/// 变为共享的 `Arc<str>`，相同的名称可以通过 [`Interner`] 共用同一份内存。
+#[derive(Clone)]
+pub enum Lexeme<'a> {
+    Borrowed(&'a str),
+    Shared(Arc<str>),
+}
+
+impl<'a> Lexeme<'a> {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Lexeme::Borrowed(s) => s,
+            Lexeme::Shared(s) => s,
+        }
+    }
+
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self, Lexeme::Borrowed(_))
+    }
+
+    pub fn into_owned(self, interner: &mut Interner) -> Lexeme<'static> {
+        match self {
+            Lexeme::Borrowed(s) => Lexeme::Shared(interner.intern(s)),
+            Lexeme::Shared(s) => Lexeme::Shared(s),
+        }
+    }
+}
+
+impl<'a> Deref for Lexeme<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'a> PartialEq for Lexeme<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<'a> fmt::Debug for Lexeme<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl<'a> fmt::Display for Lexeme<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// 字符串驻留池，同一个名称只保存一份
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(shared) = self.strings.get(s) {
+            return shared.clone();
+        }
+        let shared: Arc<str> = Arc::from(s);
+        self.strings.insert(shared.clone());
+        shared
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut interner = Interner::new();
+        let a = Lexeme::Borrowed("price").into_owned(&mut interner);
+        let b = Lexeme::Borrowed("price").into_owned(&mut interner);
+        let c = Lexeme::Borrowed("amount").into_owned(&mut interner);
+        assert_eq!(2, interner.len());
+        assert_eq!(a, b);
+        assert_eq!("amount", &*c);
+        match (a, b) {
+            (Lexeme::Shared(a), Lexeme::Shared(b)) => assert!(Arc::ptr_eq(&a, &b)),
+            _ => panic!("expected shared lexemes"),
+        }
+    }
+}
@@ -1,9 +1,12 @@
+pub mod lexeme;
+#[allow(clippy::module_inception)]
 pub mod parser;
 pub mod precedence;
 pub mod scanner;
 pub mod token;
 
-pub use parser::Parser;
+pub use lexeme::{Interner, Lexeme};
+pub use parser::{Parser, PartialParse};
 pub use precedence::Precedence;
 pub use scanner::Scanner;
 pub use token::{Token, TokenType};
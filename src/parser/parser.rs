@@ -1,22 +1,58 @@
-use crate::error::{RspError, RspResult};
+use crate::error::{ErrorKind, RspError, RspResult};
 use crate::expr::{Expr, GetExpr};
 use crate::parser::precedence::Precedence;
 use crate::parser::scanner::Scanner;
+use crate::span::Span;
 use crate::{Token, TokenType, Value};
-use std::rc::Rc;
+use std::sync::Arc;
+
+/// 表达式的最大嵌套层数，避免深层嵌套的输入在解析和执行时耗尽线程栈。
+/// 同时限制递归解析的层数和语法树的高度，`a + a + ... + a` 这样的长链虽然不递归解析，
+/// 也会生成同样高的语法树。未优化编译时，这个深度在 2MB 的线程栈上也能安全地解析、编译和执行
+const MAX_DEPTH: usize = 256;
+
+/// 容错解析的结果
+pub struct PartialParse<'a> {
+    /// 出错的部分由 `Expr::Error` 节点代替，其余部分与正常解析的结果相同
+    pub expr: Expr<'a>,
+    /// 按出现顺序排列的所有错误，都是 `RspError::ParseError`
+    pub errors: Vec<RspError>,
+}
+
+impl PartialParse<'_> {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
 
 pub struct Parser<'a> {
-    previous: Rc<Token<'a>>,
-    current: Rc<Token<'a>>,
+    previous: Arc<Token<'a>>,
+    current: Arc<Token<'a>>,
     scanner: Scanner<'a>,
+    depth: usize,
+    /// 当前层已经解析完的子表达式中最高的语法树高度
+    height: usize,
+    /// 遇到错误时记录下来并跳到同步点继续解析，而不是直接返回
+    recovering: bool,
+    /// 出错后到下一个同步点之前不再记录新的错误，避免一个错误引起一连串的报错
+    panic_mode: bool,
+    errors: Vec<RspError>,
+    /// 已消耗的 token 中尚未闭合的左括号数
+    parens: usize,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(source: &'a str) -> Self {
         Self {
-            previous: Rc::new(Token::default()),
-            current: Rc::new(Token::default()),
+            previous: Arc::new(Token::default()),
+            current: Arc::new(Token::default()),
             scanner: Scanner::new(source),
+            depth: 0,
+            height: 0,
+            recovering: false,
+            panic_mode: false,
+            errors: Vec::new(),
+            parens: 0,
         }
     }
 
@@ -24,17 +60,117 @@ impl<'a> Parser<'a> {
         self.advance()?;
         let result = self.expression_prec(Precedence::PREC_NONE)?;
         if self.current.token_type != TokenType::Eof {
-            return Err(RspError::ParseError {
-                line: self.current.line,
-                message: format!("Unknown token: {:?}", self.current),
-            });
+            return Err(self.parse_err(format!("Unknown token: {:?}", self.current)));
         }
         Ok(result)
     }
 
+    /// 容错解析，一次返回所有错误。
+    ///
+    /// 出错后跳过后续的 token，直到同一层括号内的逗号、右括号或者公式末尾，出错的子表达式
+    /// 由 `Expr::Error` 节点代替，其余部分仍然可以用于自动补全等只需要部分语法树的场景。
+    /// 没有错误时结果与 [`Parser::parse`] 相同
+    pub fn parse_recover(&mut self) -> PartialParse<'a> {
+        self.recovering = true;
+        // 容错解析时 `advance` 和 `expression_prec` 都不会返回错误
+        let expr = self
+            .advance()
+            .and_then(|_| self.expression_prec(Precedence::PREC_NONE))
+            .unwrap_or_else(|e| {
+                self.report(e);
+                Expr::error(self.current.span)
+            });
+        if !self.is_at_end() {
+            self.report(self.parse_err(format!("Unknown token: {:?}", self.current)));
+            while !self.is_at_end() {
+                let _ = self.advance();
+            }
+        }
+        // 词法错误在扫描到下一个 token 时就记录了，可能早于前面 token 上的语法错误
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|e| e.span().map(|span| span.offset));
+        PartialParse { expr, errors }
+    }
+
+    /// 解析并转换为不借用源字符串的语法树
+    pub fn parse_owned(&mut self) -> RspResult<Expr<'static>> {
+        self.parse().map(Expr::into_owned)
+    }
+
     pub fn expression_prec(&mut self, min_prec: i32) -> RspResult<Expr<'a>> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.parse_err_with(
+                format!("Expression nested too deeply, max depth: {}", MAX_DEPTH),
+                ErrorKind::NestingTooDeep,
+            ));
+        }
+        self.depth += 1;
+        let (start, parens, outer) = (self.current.span, self.parens, self.height);
+        let result = self.expression_inner(min_prec);
+        let result = self.recover(result, start, parens);
+        self.depth -= 1;
+        self.height = outer.max(self.height);
+        result
+    }
+
+    /// 容错解析时记录错误，跳到同步点后用从 `start` 开始的 `Expr::Error` 节点代替出错的部分。
+    /// `parens` 是开始解析出错部分时未闭合的左括号数，出错部分中已经消耗的左括号也要跳过对应的右括号
+    fn recover(
+        &mut self,
+        result: RspResult<Expr<'a>>,
+        start: Span,
+        parens: usize,
+    ) -> RspResult<Expr<'a>> {
+        match result {
+            Err(e) if self.recovering => {
+                self.report(e);
+                self.synchronize(parens);
+                // 出错时一个 token 都没有消耗，节点是 `start` 处长度为 0 的一段
+                let span = if self.previous.span.end() > start.start() {
+                    start.to(self.previous.span)
+                } else {
+                    Span { len: 0, ..start }
+                };
+                Ok(Expr::error(span))
+            }
+            result => result,
+        }
+    }
+
+    fn report(&mut self, error: RspError) {
+        if !self.panic_mode {
+            self.panic_mode = true;
+            self.errors.push(error);
+        }
+    }
+
+    /// 跳到未闭合的左括号数不超过 `parens` 时的逗号、右括号或者公式末尾，不消耗同步点本身
+    fn synchronize(&mut self, parens: usize) {
+        while !self.is_at_end() {
+            if matches!(
+                self.current.token_type,
+                TokenType::Comma | TokenType::RightParen
+            ) && self.parens <= parens
+            {
+                return;
+            }
+            let _ = self.advance();
+        }
+    }
+
+    fn expression_inner(&mut self, min_prec: i32) -> RspResult<Expr<'a>> {
+        // 逗号、右括号和公式末尾不能开始一个表达式，它们是容错解析的同步点，不消耗它们直接报错
+        if matches!(
+            self.current.token_type,
+            TokenType::Comma | TokenType::RightParen | TokenType::Eof
+        ) {
+            return Err(self.parse_err(format!("Unknown token: {:?}", self.current)));
+        }
         self.advance()?;
+        // 解析前缀和中缀表达式时，`self.height` 记录其中子表达式的最大高度
+        self.height = 0;
         let mut lhs = self.parse_prefix(self.previous.clone())?;
+        let mut height = self.height + 1;
         while self.current.token_type != TokenType::Eof {
             let precedence = self.get_precedence(&self.current.token_type);
             if precedence <= min_prec {
@@ -42,8 +178,17 @@ impl<'a> Parser<'a> {
             }
 
             self.advance()?;
+            self.height = 0;
             lhs = self.parse_infix(lhs, self.previous.clone())?;
+            height = height.max(self.height) + 1;
+            if height > MAX_DEPTH {
+                return Err(self.parse_err_with(
+                    format!("Expression nested too deeply, max depth: {}", MAX_DEPTH),
+                    ErrorKind::NestingTooDeep,
+                ));
+            }
         }
+        self.height = height;
 
         Ok(lhs)
     }
@@ -67,7 +212,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_prefix(&mut self, token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn parse_prefix(&mut self, token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         match token.token_type {
             TokenType::Number
             | TokenType::String
@@ -79,13 +224,14 @@ impl<'a> Parser<'a> {
             TokenType::Minus | TokenType::Bang => self.unary(token, Precedence::PREC_UNARY),
             TokenType::If => self.if_(token),
             _ => Err(RspError::ParseError {
-                line: token.line,
+                span: token.span,
                 message: format!("Unknown token: {:?}", token),
+                kind: ErrorKind::Other,
             }),
         }
     }
 
-    fn parse_infix(&mut self, lhs: Expr<'a>, token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn parse_infix(&mut self, lhs: Expr<'a>, token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         match token.token_type {
             TokenType::Plus | TokenType::Minus => {
                 self.binary(lhs, token, Precedence::PREC_TERM, false)
@@ -109,17 +255,18 @@ impl<'a> Parser<'a> {
             TokenType::LeftParen => self.call(lhs, token),
             TokenType::Dot => self.get(lhs, token),
             _ => Err(RspError::ParseError {
-                line: token.line,
+                span: token.span,
                 message: format!("Unknown infix operator: {:?}", token),
+                kind: ErrorKind::Other,
             }),
         }
     }
 
-    fn assign(&mut self, lhs: Expr<'a>, token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn assign(&mut self, lhs: Expr<'a>, token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         // 右结合，优先级降低一位，有连续等号时先解析后面的
         let rhs = self.expression_prec(Precedence::PREC_ASSIGNMENT - 1)?;
 
-        if let Expr::Get(GetExpr { object, name }) = lhs {
+        if let Expr::Get(GetExpr { object, name, .. }) = lhs {
             Ok(Expr::set(*object, name, rhs))
         } else {
             Ok(Expr::assign(lhs, token.clone(), rhs))
@@ -129,7 +276,7 @@ impl<'a> Parser<'a> {
     fn binary(
         &mut self,
         lhs: Expr<'a>,
-        token: Rc<Token<'a>>,
+        token: Arc<Token<'a>>,
         precedence: i32,
         is_right: bool,
     ) -> RspResult<Expr<'a>> {
@@ -137,26 +284,30 @@ impl<'a> Parser<'a> {
         Ok(Expr::binary(lhs, token.clone(), rhs))
     }
 
-    fn call(&mut self, callee: Expr<'a>, _token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn call(&mut self, callee: Expr<'a>, _token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         let mut arguments = Vec::new();
 
         if !self.check(&crate::TokenType::RightParen) {
             loop {
                 if arguments.len() >= 255 {
-                    return Err(self.parse_err("Can't have more than 255 arguments".to_string()));
+                    return Err(self.parse_err_with(
+                        "Can't have more than 255 arguments".to_string(),
+                        ErrorKind::TooManyArguments,
+                    ));
                 }
                 arguments.push(self.expression_prec(Precedence::PREC_NONE)?);
 
                 if !self.match_token(&[crate::TokenType::Comma])? {
                     break;
                 }
+                self.panic_mode = false;
             }
         }
-        let paren = self.consume(crate::TokenType::RightParen, "Expected ')' after arguments")?;
+        let paren = self.expect(crate::TokenType::RightParen, "Expected ')' after arguments")?;
         Ok(Expr::call(callee, arguments, paren))
     }
 
-    fn get(&mut self, object: Expr<'a>, _token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn get(&mut self, object: Expr<'a>, _token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         let name = self.consume(
             crate::TokenType::Identifier,
             "Expect property name after '.'",
@@ -164,31 +315,32 @@ impl<'a> Parser<'a> {
         Ok(Expr::get(object, name))
     }
 
-    fn group(&mut self, _token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn group(&mut self, _token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         let expr = self.expression_prec(Precedence::PREC_NONE)?;
-        self.consume(TokenType::RightParen, "Expected ')' after expression")?;
+        self.expect(TokenType::RightParen, "Expected ')' after expression")?;
         Ok(expr)
     }
 
-    fn id(&mut self, token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn id(&mut self, token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         Ok(Expr::id(token.clone()))
     }
 
-    fn if_(&mut self, _token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn if_(&mut self, token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         self.consume(crate::TokenType::LeftParen, "Expected '(' after 'if'")?;
         let condition = self.expression_prec(Precedence::PREC_NONE)?;
-        self.consume(crate::TokenType::Comma, "Expected ',' after condition")?;
+        self.expect(crate::TokenType::Comma, "Expected ',' after condition")?;
         let then_branch = self.expression_prec(Precedence::PREC_NONE)?;
-        self.consume(crate::TokenType::Comma, "Expected ',' after then branch")?;
+        self.expect(crate::TokenType::Comma, "Expected ',' after then branch")?;
         let else_branch = self.expression_prec(Precedence::PREC_NONE)?;
-        self.consume(
+        let paren = self.expect(
             crate::TokenType::RightParen,
             "Expected ')' after else branch",
         )?;
-        Ok(Expr::if_expr(condition, then_branch, Some(else_branch)))
+        let span = token.span.to(paren.span);
+        Ok(Expr::if_expr(condition, then_branch, Some(else_branch)).with_span(span))
     }
 
-    fn literal(&mut self, token: Rc<Token<'a>>) -> RspResult<Expr<'a>> {
+    fn literal(&mut self, token: Arc<Token<'a>>) -> RspResult<Expr<'a>> {
         let value = match token.token_type {
             TokenType::Number | TokenType::String => token.literal.clone().unwrap_or(Value::Null),
             TokenType::True => Value::Boolean(true),
@@ -196,28 +348,33 @@ impl<'a> Parser<'a> {
             TokenType::Null => Value::Null,
             _ => Value::Null,
         };
-        Ok(Expr::literal(value))
+        Ok(Expr::literal(value).with_span(token.span))
     }
 
     fn logic(
         &mut self,
         lhs: Expr<'a>,
-        token: Rc<Token<'a>>,
+        token: Arc<Token<'a>>,
         precedence: i32,
     ) -> RspResult<Expr<'a>> {
         let rhs = self.expression_prec(precedence)?;
         Ok(Expr::logic(lhs, token.clone(), rhs))
     }
 
-    fn unary(&mut self, token: Rc<Token<'a>>, precedence: i32) -> RspResult<Expr<'a>> {
+    fn unary(&mut self, token: Arc<Token<'a>>, precedence: i32) -> RspResult<Expr<'a>> {
         let rhs = self.expression_prec(precedence)?;
         Ok(Expr::unary(token.clone(), rhs))
     }
 
     pub fn parse_err(&self, message: String) -> RspError {
+        self.parse_err_with(message, ErrorKind::Other)
+    }
+
+    fn parse_err_with(&self, message: String, kind: ErrorKind) -> RspError {
         RspError::ParseError {
-            line: self.current.line,
+            span: self.current.span,
             message,
+            kind,
         }
     }
 
@@ -231,24 +388,54 @@ impl<'a> Parser<'a> {
         Ok(false)
     }
 
-    pub fn consume(&mut self, token_type: TokenType, message: &str) -> RspResult<Rc<Token<'a>>> {
+    pub fn consume(&mut self, token_type: TokenType, message: &str) -> RspResult<Arc<Token<'a>>> {
         if self.check(&token_type) {
             self.advance()?;
             Ok(self.previous.clone())
         } else {
-            Err(RspError::ParseError {
-                line: self.current.line,
-                message: message.to_string(),
-            })
+            Err(self.parse_err(message.to_string()))
+        }
+    }
+
+    /// 与 `consume` 相同，但容错解析时缺少分隔符或右括号不中止解析：
+    /// 记录错误后跳到同步点，同步点正是所需的 token 时消耗它
+    fn expect(&mut self, token_type: TokenType, message: &str) -> RspResult<Arc<Token<'a>>> {
+        if !self.recovering || self.check(&token_type) {
+            let token = self.consume(token_type, message)?;
+            self.panic_mode = false;
+            return Ok(token);
         }
+        self.report(self.parse_err(message.to_string()));
+        self.synchronize(self.parens);
+        if self.check(&token_type) {
+            self.advance()?;
+            self.panic_mode = false;
+        }
+        Ok(self.previous.clone())
     }
 
     fn advance(&mut self) -> RspResult<()> {
         self.previous = self.current.clone();
+        match self.previous.token_type {
+            TokenType::LeftParen => self.parens += 1,
+            TokenType::RightParen => self.parens = self.parens.saturating_sub(1),
+            _ => {}
+        }
 
         if !self.is_at_end() {
-            let token = self.scanner.next_token()?;
-            self.current = Rc::new(token);
+            let token = loop {
+                match self.scanner.next_token() {
+                    Ok(token) => break token,
+                    // 扫描器已经越过出错的字符，容错解析时记录错误后继续扫描。
+                    // 跳过字符后的语法错误多半是它引起的，到下一个同步点之前不再报告
+                    Err(e) if self.recovering => {
+                        self.errors.push(e);
+                        self.panic_mode = true;
+                    }
+                    Err(e) => return Err(e),
+                }
+            };
+            self.current = Arc::new(token);
         }
         Ok(())
     }
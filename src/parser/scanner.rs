@@ -1,18 +1,24 @@
-use crate::error::{RspError, RspResult};
+use crate::error::{ErrorKind, RspError, RspResult};
+use crate::span::Span;
 use crate::values::Value;
 use crate::{Token, TokenType};
 use std::iter::Peekable;
-use std::rc::Rc;
 use std::str::Chars;
+use std::sync::Arc;
 
 pub struct Scanner<'a> {
     source: &'a str,
     chars: Peekable<Chars<'a>>,
-    tokens: Vec<Rc<Token<'a>>>,
+    tokens: Vec<Arc<Token<'a>>>,
     current_char: Option<char>,
     start: usize,
     current: usize,
     line: usize,
+    /// 下一个字符所在的列，从 1 开始
+    column: usize,
+    /// 当前 token 起始处的行号和列号
+    start_line: usize,
+    start_column: usize,
 }
 
 fn is_alpha(c: char) -> bool {
@@ -25,37 +31,41 @@ fn is_alpha_numeric(c: char) -> bool {
 
 fn is_chinese_character(c: char) -> bool {
     // 检查基本汉字和扩展A区
-    (c >= '\u{4E00}' && c <= '\u{9FFF}') || (c >= '\u{3400}' && c <= '\u{4DBF}')
+    ('\u{4E00}'..='\u{9FFF}').contains(&c) || ('\u{3400}'..='\u{4DBF}').contains(&c)
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
         Self {
             chars: source.chars().peekable(),
-            source: source,
-            tokens: Vec::<Rc<Token>>::new(),
+            source,
+            tokens: Vec::<Arc<Token>>::new(),
             current_char: None,
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
-    pub fn scan_tokens(&mut self) -> RspResult<Vec<Rc<Token<'a>>>> {
+    pub fn scan_tokens(&mut self) -> RspResult<Vec<Arc<Token<'a>>>> {
         while !self.is_at_end() {
             let token = self.next_token()?;
-            self.tokens.push(Rc::new(token));
+            self.tokens.push(Arc::new(token));
         }
 
-        let token = Token::new(TokenType::Eof, "", None, self.line);
-        self.tokens.push(Rc::new(token));
+        self.mark_start();
+        let token = self.token_with_literal(TokenType::Eof, None);
+        self.tokens.push(Arc::new(token));
 
         Ok(std::mem::take(&mut self.tokens))
     }
 
     pub fn next_token(&mut self) -> RspResult<Token<'a>> {
         self.skip_whitespace();
-        self.start = self.current;
+        self.mark_start();
         if self.is_at_end() {
             return self.make_token(TokenType::Eof);
         }
@@ -66,6 +76,13 @@ impl<'a> Scanner<'a> {
         Ok(token)
     }
 
+    /// 记录下一个 token 的起始位置
+    fn mark_start(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
+    }
+
     fn skip_whitespace(&mut self) {
         loop {
             let c = self.peek();
@@ -145,32 +162,30 @@ impl<'a> Scanner<'a> {
                 if self.match_char('|') {
                     self.make_token(TokenType::Or)
                 } else {
-                    return Err(RspError::ParseError {
-                        line: self.line,
-                        message: format!("Unexpected character: {}", c),
-                    });
+                    Err(self.error(
+                        format!("Unexpected character: {}", c),
+                        ErrorKind::UnexpectedCharacter,
+                    ))
                 }
             }
             '&' => {
                 if self.match_char('&') {
                     self.make_token(TokenType::And)
                 } else {
-                    return Err(RspError::ParseError {
-                        line: self.line,
-                        message: format!("Unexpected character: {}", c),
-                    });
+                    Err(self.error(
+                        format!("Unexpected character: {}", c),
+                        ErrorKind::UnexpectedCharacter,
+                    ))
                 }
             }
             '"' => self.string(),
             c if c.is_ascii_digit() => self.number(),
             c if is_alpha(c) => self.identifier(),
-            '\0' => Ok(Token::new(TokenType::Eof, "", None, self.line)),
-            _ => {
-                return Err(RspError::ParseError {
-                    line: self.line,
-                    message: format!("Unexpected character: {}", c),
-                });
-            }
+            '\0' => self.make_token(TokenType::Eof),
+            _ => Err(self.error(
+                format!("Unexpected character: {}", c),
+                ErrorKind::UnexpectedCharacter,
+            )),
         }
     }
 
@@ -183,10 +198,10 @@ impl<'a> Scanner<'a> {
         }
 
         if self.is_at_end() {
-            return Err(RspError::ParseError {
-                line: self.line,
-                message: "Unterminated string".to_string(),
-            });
+            return Err(self.error(
+                "Unterminated string".to_string(),
+                ErrorKind::UnterminatedString,
+            ));
         }
 
         self.advance(); // Closing quote
@@ -210,25 +225,23 @@ impl<'a> Scanner<'a> {
                     self.advance();
                 }
             } else {
-                return Err(RspError::ParseError {
-                    line: self.line,
-                    message: "Invalid number format".to_string(),
-                });
+                return Err(self.error(
+                    "Invalid number format".to_string(),
+                    ErrorKind::InvalidNumber,
+                ));
             }
         }
 
         let value_str = &self.source[self.start..self.current];
         let value = if is_double {
-            let d: f64 = value_str.parse().map_err(|_| RspError::ParseError {
-                line: self.line,
-                message: "Invalid number".to_string(),
-            })?;
+            let d: f64 = value_str
+                .parse()
+                .map_err(|_| self.error("Invalid number".to_string(), ErrorKind::InvalidNumber))?;
             Value::Double(d)
         } else {
-            let i: i32 = value_str.parse().map_err(|_| RspError::ParseError {
-                line: self.line,
-                message: "Invalid number".to_string(),
-            })?;
+            let i: i32 = value_str
+                .parse()
+                .map_err(|_| self.error("Invalid number".to_string(), ErrorKind::InvalidNumber))?;
             Value::Integer(i)
         };
 
@@ -273,10 +286,11 @@ impl<'a> Scanner<'a> {
         if let Some(c) = self.chars.next() {
             self.current_char = Some(c);
             self.current += c.len_utf8();
-            return Some(c);
+            self.column = if c == '\n' { 1 } else { self.column + 1 };
+            Some(c)
         } else {
             self.current_char = None;
-            return None;
+            None
         }
     }
 
@@ -311,6 +325,24 @@ impl<'a> Scanner<'a> {
 
     fn token_with_literal(&mut self, token_type: TokenType, literal: Option<Value>) -> Token<'a> {
         let text = &self.source[self.start..self.current];
-        Token::new(token_type, text, literal, self.line)
+        Token::new(token_type, text, literal, self.span())
+    }
+
+    /// 当前 token 从起始处到已扫描位置的一段
+    fn span(&self) -> Span {
+        Span::new(
+            self.start,
+            self.current - self.start,
+            self.start_line,
+            self.start_column,
+        )
+    }
+
+    fn error(&self, message: String, kind: ErrorKind) -> RspError {
+        RspError::ParseError {
+            span: self.span(),
+            message,
+            kind,
+        }
     }
 }
@@ -1,3 +1,5 @@
+use crate::parser::lexeme::{Interner, Lexeme};
+use crate::span::Span;
 use crate::values::Value;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -57,23 +59,37 @@ pub enum TokenType {
 #[derive(Debug, Clone)]
 pub struct Token<'a> {
     pub token_type: TokenType,
-    pub lexeme: &'a str,
+    pub lexeme: Lexeme<'a>,
     pub literal: Option<Value>,
+    /// 起始行号，与 `span.line` 相同
     pub line: usize,
+    pub span: Span,
 }
 
 impl<'a> Token<'a> {
-    pub fn new(
-        token_type: TokenType,
-        lexeme: &'a str,
-        literal: Option<Value>,
-        line: usize,
-    ) -> Self {
+    pub fn new(token_type: TokenType, lexeme: &'a str, literal: Option<Value>, span: Span) -> Self {
         Self {
             token_type,
-            lexeme,
+            lexeme: Lexeme::Borrowed(lexeme),
             literal,
-            line,
+            line: span.line as usize,
+            span,
+        }
+    }
+
+    /// 转换为不再借用源字符串的 token
+    pub fn into_owned(self) -> Token<'static> {
+        self.into_owned_with(&mut Interner::new())
+    }
+
+    /// 转换为不再借用源字符串的 token，文本放入驻留池中与其它 token 共享
+    pub fn into_owned_with(self, interner: &mut Interner) -> Token<'static> {
+        Token {
+            token_type: self.token_type,
+            lexeme: self.lexeme.into_owned(interner),
+            literal: self.literal,
+            line: self.line,
+            span: self.span,
         }
     }
 }
@@ -82,9 +98,10 @@ impl<'a> Default for Token<'a> {
     fn default() -> Self {
         Self {
             token_type: TokenType::Error,
-            lexeme: "",
+            lexeme: Lexeme::Borrowed(""),
             literal: None,
             line: 0,
+            span: Span::default(),
         }
     }
 }
@@ -1,26 +1,39 @@
 use crate::Field;
-use crate::RspResult;
 use crate::Value;
-use crate::chunk::Chunk;
-use crate::environment::{DefaultEnvironment, Environment};
-use crate::expr::Expr;
-use crate::ir::{Analyzer, ExprInfo};
-use crate::parser::Parser;
-use crate::visitors::{Evaluator, OpCodeCompiler};
-use crate::vm::VM;
+use crate::chunk::{Chunk, ChunkReader, ChunkVariables, ConstantPool, Program, scan_blocks};
+use crate::column::{Batch, Column};
+use crate::environment::{DefaultEnvironment, Environment, SlotEnvironment};
+use crate::expr::{Expr, OwnedExpr};
+use crate::ir::{Analyzer, ExecutionPlan, ExprInfo};
+use crate::limits::{Budget, Limits};
+use crate::parallel;
+use crate::parser::{Interner, Parser};
+use crate::visitors::closure_compiler::NamedSlots;
+use crate::visitors::{ClosureProgram, Evaluator, OpCodeCompiler, OptLevel, Optimizer};
+use crate::vm::{ExResult, VM, VectorVM, Verifier};
+use crate::{RspError, RspResult};
 
-use std::collections::HashSet;
-use std::rc::Rc;
+use std::borrow::Borrow;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Instant;
 
 pub struct RspRunner {
     need_sort: bool,
     execute_mode: ExecuteMode,
+    threads: usize,
+    verify_chunk: bool,
+    opt_level: OptLevel,
+    limits: Limits,
+    debug_info: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExecuteMode {
     SyntaxTree,
     ChunkVM,
+    /// 把表达式编译为闭包树执行，见 [`ClosureProgram`]。资源限制按语法树解释执行的方式计算
+    Closure,
 }
 
 impl RspRunner {
@@ -28,6 +41,11 @@ impl RspRunner {
         Self {
             need_sort: true,
             execute_mode: ExecuteMode::SyntaxTree,
+            threads: parallel::default_threads(),
+            verify_chunk: true,
+            opt_level: OptLevel::None,
+            limits: Limits::new(),
+            debug_info: true,
         }
     }
 
@@ -39,6 +57,32 @@ impl RspRunner {
         self.execute_mode = mode;
     }
 
+    /// 设置并行执行时使用的线程数，默认为 CPU 核数
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// 执行字节码前是否先校验，默认开启。只有确定字节码来自可信来源时才应关闭
+    pub fn set_verify_chunk(&mut self, verify_chunk: bool) {
+        self.verify_chunk = verify_chunk;
+    }
+
+    /// 设置解析后对语法树的优化级别，默认为 `OptLevel::None`，即不做优化
+    pub fn set_opt_level(&mut self, level: OptLevel) {
+        self.opt_level = level;
+    }
+
+    /// 设置每次执行的资源限制，所有执行方式都会检查
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// 编译的字节码是否带有源码映射，默认开启。关闭后运行时错误不再指向出错的子表达式，
+    /// 已编译的字节码可以用 [`Chunk::strip_debug_info`] 去掉
+    pub fn set_debug_info(&mut self, debug_info: bool) {
+        self.debug_info = debug_info;
+    }
+
     pub fn execute(&mut self, expression: &str) -> RspResult<Value> {
         let mut env = DefaultEnvironment::new();
         self.execute_with_env(expression, &mut env)
@@ -68,75 +112,436 @@ impl RspRunner {
         let ana = Analyzer::new(exprs, self.need_sort);
         let expr_infos = ana.analyze()?;
 
-        let results = if self.execute_mode == ExecuteMode::ChunkVM {
-            let chunk = self.compile_ir(&expr_infos)?;
-            self.run_chunk(&chunk, env)
-        } else {
-            self.run_ir(&expr_infos, env)
+        match self.execute_mode {
+            ExecuteMode::ChunkVM => {
+                let chunk = self.compile_ir(&expr_infos)?;
+                self.run_chunk(&chunk, env)
+            }
+            ExecuteMode::Closure => {
+                let program = ClosureProgram::compile(&expr_infos)?;
+                self.run_closures(&program, env)
+            }
+            ExecuteMode::SyntaxTree => self.run_ir(&expr_infos, env),
+        }
+    }
+
+    /// 只计算 `targets` 中的变量：根据依赖关系找出它们直接或间接依赖的公式并执行，
+    /// 其余公式不执行，对应的结果为 `Value::Null`
+    pub fn execute_for_targets<E: Environment>(
+        &mut self,
+        expressions: &[&str],
+        targets: &[&str],
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        let exprs = self.parse(expressions)?;
+
+        let ana = Analyzer::new(exprs, self.need_sort);
+        let expr_infos = ana.analyze_targets(targets)?;
+
+        let mut results = match self.execute_mode {
+            ExecuteMode::ChunkVM => {
+                let chunk = self.compile_ir(&expr_infos)?;
+                self.run_chunk(&chunk, env)?
+            }
+            ExecuteMode::Closure => {
+                let program = ClosureProgram::compile(&expr_infos)?;
+                self.run_closures(&program, env)?
+            }
+            ExecuteMode::SyntaxTree => self.run_ir(&expr_infos, env)?,
         };
-        results
+        results.resize(expressions.len(), Value::Null);
+        Ok(results)
+    }
+
+    /// 与 `execute_multiple_with_env` 相同，但按依赖层次并行执行，要求环境可以跨线程共享
+    pub fn execute_multiple_parallel_with_env<E: Environment + Sync>(
+        &mut self,
+        expressions: &[&str],
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        let exprs = self.parse(expressions)?;
+        let ana = Analyzer::new(exprs, self.need_sort);
+
+        match self.execute_mode {
+            ExecuteMode::ChunkVM => {
+                let expr_infos = ana.analyze()?;
+                let chunk = self.compile_ir(&expr_infos)?;
+                self.run_chunk_parallel(&chunk, env)
+            }
+            ExecuteMode::Closure => {
+                let program = ClosureProgram::compile(&ana.analyze()?)?;
+                self.run_closures_parallel(&program, env)
+            }
+            ExecuteMode::SyntaxTree => {
+                let levels = ana.analyze_levels()?;
+                self.run_ir_parallel(&levels, env)
+            }
+        }
+    }
+
+    /// 解析并分析表达式，生成可以缓存、克隆和跨线程传递的执行计划
+    pub fn analyze<S: AsRef<str>>(&mut self, expressions: &[S]) -> RspResult<ExecutionPlan> {
+        let srcs: Vec<&str> = expressions.iter().map(AsRef::as_ref).collect();
+        let exprs = self.parse(&srcs)?;
+        Analyzer::new(exprs, self.need_sort).into_plan()
     }
 
-    pub fn run_ir<E: Environment>(
+    pub fn run_plan<E: Environment>(
         &mut self,
-        expr_infos: &[&ExprInfo],
+        plan: &ExecutionPlan,
         env: &mut E,
     ) -> RspResult<Vec<Value>> {
-        // let mut variables = HashSet::new();
-        // for info in expr_infos {
-        //     variables.union(info.get_reads());
-        //     variables.union(info.get_writes());
-        // }
-        // let fields = self.get_fields(&variables);
-        // let flag = env.before_execute(variables.into_iter().collect());
-        // if !flag {
-        //     return Ok(Vec::new());
-        // }
+        let mut results = self.run_ir(plan.expr_infos(), env)?;
+        results.resize(plan.size(), Value::Null);
+        Ok(results)
+    }
 
-        let n = expr_infos.len();
+    pub fn run_plan_parallel<E: Environment + Sync>(
+        &mut self,
+        plan: &ExecutionPlan,
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        let mut results = self.run_ir_parallel(&plan.levels(), env)?;
+        results.resize(plan.size(), Value::Null);
+        Ok(results)
+    }
+
+    pub fn compile_plan(&mut self, plan: &ExecutionPlan) -> RspResult<Chunk> {
+        self.compile_ir(plan.expr_infos())
+    }
+
+    pub fn run_ir<'e, E: Environment, T: Borrow<ExprInfo<'e>>>(
+        &mut self,
+        expr_infos: &[T],
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        let names = expr_infos.iter().flat_map(|info| {
+            let info = info.borrow();
+            info.get_reads()
+                .iter()
+                .chain(info.get_writes().iter())
+                .cloned()
+        });
+        let names: BTreeSet<String> = names.collect();
+        Self::before_execute(env, &names)?;
+
+        let n = expr_infos
+            .iter()
+            .map(|info| info.borrow().get_index() + 1)
+            .max()
+            .unwrap_or(0);
         let mut result = vec![Value::default(); n];
+        let mut evtor = Evaluator::with_limits(env, &self.limits);
         for info in expr_infos {
-            let expr = info.get_expr();
-            let mut evtor = Evaluator::new(env);
-            let v = evtor.evaluate(expr)?;
+            let info = info.borrow();
+            let v = evtor.evaluate(info.get_expr())?;
             result[info.get_index()] = v;
         }
         Ok(result)
     }
 
+    /// 逐层执行分析器给出的公式层，同一层内的公式分配到多个线程上并行执行
+    pub fn run_ir_parallel<E: Environment + Sync>(
+        &mut self,
+        levels: &[Vec<&ExprInfo>],
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        let names: BTreeSet<String> = levels
+            .iter()
+            .flatten()
+            .flat_map(|info| info.get_reads().iter().chain(info.get_writes().iter()))
+            .cloned()
+            .collect();
+        Self::before_execute(env, &names)?;
+        // 只传入部分公式时序号可以不连续，与 `run_ir` 一样按最大序号分配结果
+        let n = levels
+            .iter()
+            .flatten()
+            .map(|info| info.get_index() + 1)
+            .max()
+            .unwrap_or(0);
+        let (limits, start) = (&self.limits, Instant::now());
+        let ex_results = parallel::run_levels(
+            levels,
+            env,
+            self.threads,
+            || Budget::started_at(limits, start),
+            |budget, info, env| {
+                let mut evtor = Evaluator::with_budget(env, budget.clone());
+                let v = evtor.evaluate(info.get_expr());
+                *budget = evtor.into_budget();
+                Ok((info.get_index(), v?))
+            },
+        )?;
+        let mut result = vec![Value::default(); n];
+        for (index, v) in ex_results {
+            result[index] = v;
+        }
+        Ok(result)
+    }
+
+    /// 按顺序执行字节码。只解码常量池并扫描一遍公式块，不复制字节码，也不计算分层和槽位；
+    /// 同一份字节码需要反复执行时，先用 `load_chunk` 加载再用 `run_program` 执行
     pub fn run_chunk<E: Environment>(
         &mut self,
         chunk: &Chunk,
         env: &mut E,
     ) -> RspResult<Vec<Value>> {
-        // let chunk_reader = ChunkReader::new(chunk, self.context.get_tracer());
-        // let fields = self.get_fields(&chunk_reader.get_variables());
-        // let flag = env.before_execute(&strs.iter().map(|str| str).collect());
-        // if !flag {
-        //     return None;
-        // }
+        if self.verify_chunk {
+            Verifier::verify(chunk)?;
+        }
+        let pool = ConstantPool::from_bytes(&chunk.constants)?;
+        let source_map = chunk.source_map()?;
+        let mut reader = ChunkReader::new(&chunk.codes, &pool);
+        if let Some(source_map) = &source_map {
+            reader = reader.with_source_map(source_map);
+        }
+        let blocks = scan_blocks(&mut reader)?;
+        let size = Program::check_blocks(&blocks)?;
+        Self::before_chunk(env, &ChunkVariables::from_blocks(&blocks))?;
+        reader.new_position(0);
+        let mut vm = VM::with_limits(self.limits.clone());
+        let ex_results = vm.execute_reader_with_env(&mut reader, env)?;
+        Self::collect_results(ex_results, size)
+    }
+
+    /// 只执行字节码中计算 `targets` 所需的公式块，结果按公式序号存放，未执行的公式位置为 `Value::Null`
+    pub fn run_chunk_for_targets<E: Environment>(
+        &mut self,
+        chunk: &Chunk,
+        targets: &[&str],
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        let program = self.load_chunk(chunk)?;
+        self.run_program_for_targets(&program, targets, env)
+    }
+
+    pub fn run_chunk_parallel<E: Environment + Sync>(
+        &mut self,
+        chunk: &Chunk,
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        let program = self.load_chunk(chunk)?;
+        self.run_program_parallel(&program, env)
+    }
+
+    /// 校验并加载字节码。加载时会复制字节码，解码常量池和调试信息，并计算公式块的分层和变量槽位，
+    /// 同一份字节码需要反复执行时，加载一次后用 `run_program` 执行
+    pub fn load_chunk(&self, chunk: &Chunk) -> RspResult<Program> {
+        if self.verify_chunk {
+            Verifier::verify(chunk)?;
+        }
+        Program::new(chunk)
+    }
+
+    pub fn run_program<E: Environment>(
+        &mut self,
+        program: &Program,
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        Self::before_chunk(env, program.variables())?;
+        let mut vm = VM::with_limits(self.limits.clone());
+        let ex_results = vm.execute_program(program, env)?;
+        Self::collect_results(ex_results, program.size())
+    }
+
+    /// 按槽位读写变量执行，适合同一份字节码在大量数据上反复执行的场景
+    pub fn run_program_slots<S: SlotEnvironment>(
+        &mut self,
+        program: &Program,
+        env: &mut S,
+    ) -> RspResult<Vec<Value>> {
+        let mut vm = VM::with_limits(self.limits.clone());
+        let ex_results = vm.execute_program_slots(program, env)?;
+        Self::collect_results(ex_results, program.size())
+    }
+
+    pub fn run_program_for_targets<E: Environment>(
+        &mut self,
+        program: &Program,
+        targets: &[&str],
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        let variables = ChunkVariables::from_blocks(program.target_blocks(targets));
+        Self::before_chunk(env, &variables)?;
+        let mut vm = VM::with_limits(self.limits.clone());
+        let ex_results = vm.execute_program_targets(program, targets, env)?;
+        Self::collect_results(ex_results, program.size())
+    }
+
+    pub fn run_program_parallel<E: Environment + Sync>(
+        &mut self,
+        program: &Program,
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        Self::before_chunk(env, program.variables())?;
+        let ex_results =
+            VM::execute_program_parallel_with_limits(program, env, self.threads, &self.limits)?;
+        Self::collect_results(ex_results, program.size())
+    }
+
+    /// 在 `batch` 的所有行上按列执行字节码，见 [`VectorVM`]。
+    /// 结果按公式序号存放，每个公式一列，赋值的变量以列的形式写回 `batch`
+    pub fn run_program_batch(
+        &mut self,
+        program: &Program,
+        batch: &mut Batch,
+    ) -> RspResult<Vec<Column>> {
+        VectorVM::with_limits(self.limits.clone()).execute_program(program, batch)
+    }
+
+    pub fn run_chunk_batch(&mut self, chunk: &Chunk, batch: &mut Batch) -> RspResult<Vec<Column>> {
+        let program = self.load_chunk(chunk)?;
+        self.run_program_batch(&program, batch)
+    }
+
+    pub fn execute_batch(
+        &mut self,
+        expressions: &[&str],
+        batch: &mut Batch,
+    ) -> RspResult<Vec<Column>> {
+        let chunk = self.compile_source(expressions)?;
+        self.run_chunk_batch(&chunk, batch)
+    }
+
+    /// 解析、分析并把表达式编译为闭包树。编译结果不能序列化，适合在进程内反复执行
+    pub fn compile_closures<S: AsRef<str>>(
+        &mut self,
+        expressions: &[S],
+    ) -> RspResult<ClosureProgram> {
+        let srcs: Vec<&str> = expressions.iter().map(AsRef::as_ref).collect();
+        let exprs = self.parse(&srcs)?;
+        let ana = Analyzer::new(exprs, self.need_sort);
+        ClosureProgram::compile(&ana.analyze()?)
+    }
+
+    pub fn run_closures<E: Environment>(
+        &mut self,
+        program: &ClosureProgram,
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        Self::before_execute(env, program.names())?;
+        let results = program.run_with_limits(&mut NamedSlots::new(program, env), &self.limits)?;
+        Ok(Self::collect_indexed(results, program.size()))
+    }
 
-        let mut vm = VM::new();
-        let ex_results = vm.execute_with_env(chunk, env)?;
-        let mut result = vec![Value::default(); ex_results.len()];
+    /// 按槽位读写变量执行闭包，槽位通过 [`ClosureProgram::slot`] 查询
+    pub fn run_closures_slots<S: SlotEnvironment>(
+        &mut self,
+        program: &ClosureProgram,
+        env: &mut S,
+    ) -> RspResult<Vec<Value>> {
+        let results = program.run_with_limits(env, &self.limits)?;
+        Ok(Self::collect_indexed(results, program.size()))
+    }
+
+    pub fn run_closures_parallel<E: Environment + Sync>(
+        &mut self,
+        program: &ClosureProgram,
+        env: &mut E,
+    ) -> RspResult<Vec<Value>> {
+        Self::before_execute(env, program.names())?;
+        let (limits, start) = (&self.limits, Instant::now());
+        let results = parallel::run_levels(
+            program.levels(),
+            env,
+            self.threads,
+            || Budget::started_at(limits, start),
+            |budget, pos, env| {
+                program.run_formula(*pos, &mut NamedSlots::new(program, env), budget)
+            },
+        )?;
+        Ok(Self::collect_indexed(results, program.size()))
+    }
+
+    fn collect_indexed(results: Vec<(usize, Value)>, size: usize) -> Vec<Value> {
+        let mut values = vec![Value::default(); size];
+        for (index, v) in results {
+            values[index] = v;
+        }
+        values
+    }
+
+    fn before_chunk<E: Environment>(env: &mut E, variables: &ChunkVariables) -> RspResult<()> {
+        Self::before_execute(env, variables.paths.iter().chain(&variables.writes))
+    }
+
+    /// 执行前把将要读写的变量告诉环境，环境返回 `false` 时中止执行。
+    /// `paths` 为读写的完整访问路径，变量名取路径的第一段，各种执行方式都按同样的规则生成
+    fn before_execute<'s, E, P>(env: &mut E, paths: P) -> RspResult<()>
+    where
+        E: Environment,
+        P: IntoIterator<Item = &'s String>,
+    {
+        let paths: BTreeSet<&String> = paths.into_iter().collect();
+        let vars: BTreeSet<&str> = paths
+            .iter()
+            .map(|path| path.split('.').next().unwrap_or(path))
+            .collect();
+        let vars: Vec<String> = vars.into_iter().map(str::to_string).collect();
+        let fields = Self::get_fields(paths);
+        if !env.before_execute(&vars, &fields) {
+            return Err(RspError::Aborted {
+                message: "before_execute returned false".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// 按公式序号存放执行结果，未执行的公式位置为 `Value::Null`。
+    /// 未经校验的字节码执行时可能遇到加载时没有扫描到的公式序号，超出范围时返回错误
+    fn collect_results(ex_results: Vec<ExResult>, size: usize) -> RspResult<Vec<Value>> {
+        let mut result = vec![Value::default(); size];
         for res in ex_results {
-            let r = res.result;
-            let index = res.index;
-            result[index as usize] = r;
+            let Some(slot) = usize::try_from(res.index)
+                .ok()
+                .and_then(|index| result.get_mut(index))
+            else {
+                return Err(RspError::ChunkError {
+                    message: format!("Formula index {} out of range, size: {}", res.index, size),
+                });
+            };
+            *slot = res.result;
         }
         Ok(result)
     }
 
     pub fn parse<'a>(&mut self, expressions: &[&'a str]) -> RspResult<Vec<Expr<'a>>> {
+        let optimizer = Optimizer::new(self.opt_level);
         let mut exprs = Vec::new();
         for expr in expressions {
             let mut parser = Parser::new(expr);
             let expr = parser.parse()?;
-            exprs.push(expr);
+            exprs.push(optimizer.optimize(expr));
+        }
+        Ok(exprs)
+    }
+
+    /// 解析表达式并转换为不借用源字符串的语法树，同名变量共享同一份字符串
+    pub fn parse_owned<S: AsRef<str>>(&mut self, expressions: &[S]) -> RspResult<Vec<OwnedExpr>> {
+        let optimizer = Optimizer::new(self.opt_level);
+        let mut interner = Interner::new();
+        let mut exprs = Vec::with_capacity(expressions.len());
+        for src in expressions {
+            let expr = optimizer.optimize(Parser::new(src.as_ref()).parse()?);
+            exprs.push(expr.into_owned_with(&mut interner));
         }
         Ok(exprs)
     }
 
+    /// 容错解析每个公式，返回出错公式的序号及其全部错误，按公式序号排列，见 [`Parser::parse_recover`]。
+    /// 不会在第一个出错的公式处停下，编辑器可以据此一次标出所有公式中的错误
+    pub fn parse_recover<S: AsRef<str>>(&self, expressions: &[S]) -> Vec<(usize, Vec<RspError>)> {
+        expressions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, src)| {
+                let partial = Parser::new(src.as_ref()).parse_recover();
+                (!partial.is_ok()).then_some((index, partial.errors))
+            })
+            .collect()
+    }
+
     pub fn compile_source(&mut self, expressions: &[&str]) -> RspResult<Chunk> {
         let exprs = self.parse(expressions)?;
         let ana = Analyzer::new(exprs, self.need_sort);
@@ -144,18 +549,25 @@ impl RspRunner {
         self.compile_ir(&expr_infos)
     }
 
-    pub fn compile_ir(&mut self, expr_infos: &[&ExprInfo]) -> RspResult<Chunk> {
+    pub fn compile_ir<'e, T: Borrow<ExprInfo<'e>>>(
+        &mut self,
+        expr_infos: &[T],
+    ) -> RspResult<Chunk> {
         let mut compiler = OpCodeCompiler::new();
+        compiler.set_debug_info(self.debug_info);
         compiler.begin_compile();
         for expr_info in expr_infos {
-            compiler.compile(expr_info)?;
+            compiler.compile(expr_info.borrow())?;
         }
-        let result = compiler.end_compile();
+        let result = compiler.end_compile()?;
         Ok(result)
     }
 
-    fn _get_fields(&self, strs: &HashSet<String>) -> Vec<Rc<Field>> {
-        strs.iter().map(|str| Field::with_str(str)).collect()
+    fn get_fields<'s, P: IntoIterator<Item = &'s String>>(paths: P) -> Vec<Arc<Field>> {
+        paths
+            .into_iter()
+            .map(|path| Field::with_str(path))
+            .collect()
     }
 }
 
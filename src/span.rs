@@ -0,0 +1,80 @@
+use std::fmt;
+
+/// 源码中的一段位置。
+///
+/// `offset` 和 `len` 按字节计算，可以直接用来切片源字符串；`line` 和 `column` 从 1 开始，
+/// 列按字符计算，指向这一段的第一个字符。没有位置信息时（例如优化时新建的节点）行号为 0，
+/// 只知道行号时（例如汇编文本）列号为 0。
+///
+/// 每个语法树节点和错误都带有位置，字段使用 `u32` 让节点保持紧凑，深层嵌套的表达式解析时占用的栈更少
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub offset: u32,
+    pub len: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// 超出 `u32` 的位置按最大值保存
+fn clamp(n: usize) -> u32 {
+    u32::try_from(n).unwrap_or(u32::MAX)
+}
+
+impl Span {
+    pub fn new(offset: usize, len: usize, line: usize, column: usize) -> Self {
+        Self {
+            offset: clamp(offset),
+            len: clamp(len),
+            line: clamp(line),
+            column: clamp(column),
+        }
+    }
+
+    /// 只有行号的位置
+    pub fn at_line(line: usize) -> Self {
+        Self::new(0, 0, line, 0)
+    }
+
+    /// 起始位置的字节偏移
+    pub fn start(&self) -> usize {
+        self.offset as usize
+    }
+
+    /// 结束位置的字节偏移，不包含
+    pub fn end(&self) -> usize {
+        self.start() + self.len as usize
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.line > 0
+    }
+
+    /// 从 `self` 开始到 `other` 结束的一段，任一方没有位置信息时返回另一方
+    pub fn to(self, other: Span) -> Span {
+        if !other.is_known() {
+            return self;
+        }
+        if !self.is_known() {
+            return other;
+        }
+        Span {
+            len: clamp(other.end().max(self.end()) - self.start()),
+            ..self
+        }
+    }
+
+    /// 这一段在源码中的文本，位置超出源码时返回 `None`
+    pub fn slice<'s>(&self, source: &'s str) -> Option<&'s str> {
+        source.get(self.start()..self.end())
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.column == 0 {
+            write!(f, "line {}", self.line)
+        } else {
+            write!(f, "line {}, column {}", self.line, self.column)
+        }
+    }
+}
@@ -5,6 +5,12 @@ pub struct Instance {
     pub fields: std::collections::HashMap<String, Value>,
 }
 
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Instance {
     pub fn new() -> Self {
         Self {
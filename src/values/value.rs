@@ -1,13 +1,14 @@
 use crate::values::Instance;
 use std::fmt;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum Value {
     Integer(i32),
     Double(f64),
     String(String),
     Boolean(bool),
     Instance(Box<Instance>),
+    #[default]
     Null,
 }
 
@@ -78,7 +79,7 @@ impl Value {
 
     pub fn as_str(&self) -> &str {
         match self {
-            Value::String(s) => &s,
+            Value::String(s) => s,
             _ => "",
         }
     }
@@ -130,12 +131,6 @@ impl fmt::Display for Value {
     }
 }
 
-impl Default for Value {
-    fn default() -> Self {
-        Value::Null
-    }
-}
-
 impl From<i32> for Value {
     fn from(value: i32) -> Self {
         Value::Integer(value)
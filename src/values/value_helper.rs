@@ -1,13 +1,22 @@
+use crate::ErrorKind;
 use crate::RspResult;
 use crate::TokenType;
 use crate::Value;
+use crate::span::Span;
 
+/// 计算二元运算。整数的加、减、乘、除和取余溢出时按补码回绕，不报错，
+/// 如 `2147483647 + 1` 的结果是 `-2147483648`；整数除数为 0 时返回 `Division by zero` 错误
 pub fn evaluate_binary(left: &Value, right: &Value, operator: &TokenType) -> RspResult<Value> {
+    if let Some(v) = evaluate_numeric(left, right, operator) {
+        return Ok(v);
+    }
     match operator {
         TokenType::Plus => {
             if !left.is_number() && !left.is_string() || !right.is_number() && !right.is_string() {
                 return Err(crate::error::RspError::RuntimeError {
                     message: "Operands must be number or string".to_string(),
+                    span: None,
+                    kind: ErrorKind::TypeMismatch,
                 });
             }
             if left.is_string() || right.is_string() {
@@ -20,7 +29,9 @@ pub fn evaluate_binary(left: &Value, right: &Value, operator: &TokenType) -> Rsp
                 if left.is_double() || right.is_double() {
                     Ok(Value::Double(left.as_double() + right.as_double()))
                 } else {
-                    Ok(Value::Integer(left.as_integer() + right.as_integer()))
+                    Ok(Value::Integer(
+                        left.as_integer().wrapping_add(right.as_integer()),
+                    ))
                 }
             }
         }
@@ -29,7 +40,9 @@ pub fn evaluate_binary(left: &Value, right: &Value, operator: &TokenType) -> Rsp
             if left.is_double() || right.is_double() {
                 Ok(Value::Double(left.as_double() - right.as_double()))
             } else {
-                Ok(Value::Integer(left.as_integer() - right.as_integer()))
+                Ok(Value::Integer(
+                    left.as_integer().wrapping_sub(right.as_integer()),
+                ))
             }
         }
         TokenType::Star => {
@@ -37,28 +50,31 @@ pub fn evaluate_binary(left: &Value, right: &Value, operator: &TokenType) -> Rsp
             if left.is_double() || right.is_double() {
                 Ok(Value::Double(left.as_double() * right.as_double()))
             } else {
-                Ok(Value::Integer(left.as_integer() * right.as_integer()))
+                Ok(Value::Integer(
+                    left.as_integer().wrapping_mul(right.as_integer()),
+                ))
             }
         }
         TokenType::Slash => {
             check_number_operands(left, right)?;
-            if right.is_integer() && right.as_integer() == 0 {
-                return Err(crate::error::RspError::RuntimeError {
-                    message: "Division by zero".to_string(),
-                });
-            }
+            check_divisor(right)?;
             if left.is_double() || right.is_double() {
                 Ok(Value::Double(left.as_double() / right.as_double()))
             } else {
-                Ok(Value::Integer(left.as_integer() / right.as_integer()))
+                Ok(Value::Integer(
+                    left.as_integer().wrapping_div(right.as_integer()),
+                ))
             }
         }
         TokenType::Percent => {
             check_number_operands(left, right)?;
+            check_divisor(right)?;
             if left.is_double() || right.is_double() {
                 Ok(Value::Double(left.as_double() % right.as_double()))
             } else {
-                Ok(Value::Integer(left.as_integer() % right.as_integer()))
+                Ok(Value::Integer(
+                    left.as_integer().wrapping_rem(right.as_integer()),
+                ))
             }
         }
         TokenType::StarStar => {
@@ -85,10 +101,37 @@ pub fn evaluate_binary(left: &Value, right: &Value, operator: &TokenType) -> Rsp
         TokenType::EqualEqual => Ok(Value::Boolean(left.equals(right))),
         _ => Err(crate::error::RspError::RuntimeError {
             message: "Invalid binary operator".to_string(),
+            span: None,
+            kind: ErrorKind::Other,
         }),
     }
 }
 
+/// 两个操作数同为整数或同为浮点数时的快速路径，结果与通用实现一致，整数溢出时同样按补码回绕
+fn evaluate_numeric(left: &Value, right: &Value, operator: &TokenType) -> Option<Value> {
+    use Value::{Boolean, Double, Integer};
+    let value = match (operator, left, right) {
+        (TokenType::Plus, Integer(x), Integer(y)) => Integer(x.wrapping_add(*y)),
+        (TokenType::Minus, Integer(x), Integer(y)) => Integer(x.wrapping_sub(*y)),
+        (TokenType::Star, Integer(x), Integer(y)) => Integer(x.wrapping_mul(*y)),
+        (TokenType::Greater, Integer(x), Integer(y)) => Boolean(x > y),
+        (TokenType::GreaterEqual, Integer(x), Integer(y)) => Boolean(x >= y),
+        (TokenType::Less, Integer(x), Integer(y)) => Boolean(x < y),
+        (TokenType::LessEqual, Integer(x), Integer(y)) => Boolean(x <= y),
+        (TokenType::Plus, Double(x), Double(y)) => Double(x + y),
+        (TokenType::Minus, Double(x), Double(y)) => Double(x - y),
+        (TokenType::Star, Double(x), Double(y)) => Double(x * y),
+        (TokenType::Slash, Double(x), Double(y)) => Double(x / y),
+        (TokenType::Greater, Double(x), Double(y)) => Boolean(x > y),
+        (TokenType::GreaterEqual, Double(x), Double(y)) => Boolean(x >= y),
+        (TokenType::Less, Double(x), Double(y)) => Boolean(x < y),
+        (TokenType::LessEqual, Double(x), Double(y)) => Boolean(x <= y),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// 计算一元运算。整数取负溢出时按补码回绕，`-2147483648` 取负仍是它本身
 pub fn evaluate_unary(right: &Value, operator: &TokenType) -> RspResult<Value> {
     match operator {
         TokenType::Bang => {
@@ -96,15 +139,17 @@ pub fn evaluate_unary(right: &Value, operator: &TokenType) -> RspResult<Value> {
             Ok(Value::Boolean(!truthy))
         }
         TokenType::Minus => {
-            check_number_operand(&right)?;
+            check_number_operand(right)?;
             if right.is_integer() {
-                Ok(Value::Integer(-right.as_integer()))
+                Ok(Value::Integer(right.as_integer().wrapping_neg()))
             } else {
                 Ok(Value::Double(-right.as_double()))
             }
         }
         _ => Err(crate::error::RspError::RuntimeError {
             message: "Invalid unary operator".to_string(),
+            span: None,
+            kind: ErrorKind::Other,
         }),
     }
 }
@@ -115,16 +160,47 @@ fn check_number_operand(operand: &Value) -> RspResult<()> {
     } else {
         Err(crate::error::RspError::RuntimeError {
             message: "Operand must be a number".to_string(),
+            span: None,
+            kind: ErrorKind::TypeMismatch,
         })
     }
 }
 
+/// 二元运算出错时指向的位置：整数除以 0 或对 0 取余时指向除数，其它错误指向整个表达式
+pub fn binary_error_span(operator: &TokenType, right: &Value, whole: Span, divisor: Span) -> Span {
+    if is_zero_divisor(operator, right) {
+        divisor
+    } else {
+        whole
+    }
+}
+
+/// 整数除法或取余的除数为 0
+pub fn is_zero_divisor(operator: &TokenType, right: &Value) -> bool {
+    let zero = right.is_integer() && right.as_integer() == 0;
+    zero && matches!(operator, TokenType::Slash | TokenType::Percent)
+}
+
+/// 整数除数为 0 时报错
+fn check_divisor(right: &Value) -> RspResult<()> {
+    if right.is_integer() && right.as_integer() == 0 {
+        return Err(crate::error::RspError::RuntimeError {
+            message: "Division by zero".to_string(),
+            span: None,
+            kind: ErrorKind::DivisionByZero,
+        });
+    }
+    Ok(())
+}
+
 fn check_number_operands(left: &Value, right: &Value) -> RspResult<()> {
     if left.is_number() && right.is_number() {
         Ok(())
     } else {
         Err(crate::error::RspError::RuntimeError {
             message: format!("Operands must be numbers. left: {}, right: {}", left, right),
+            span: None,
+            kind: ErrorKind::TypeMismatch,
         })
     }
 }
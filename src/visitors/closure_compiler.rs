@@ -0,0 +1,486 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::{
+    ErrorKind, RspError, RspResult,
+    environment::{Environment, SlotEnvironment},
+    expr::{
+        AssignExpr, BinaryExpr, CallExpr, ErrorExpr, Expr, GetExpr, IdExpr, IfExpr, LiteralExpr,
+        LogicExpr, SetExpr, UnaryExpr, Visitor,
+    },
+    functions::FunctionManager,
+    ir::{ExprInfo, levels::split_levels},
+    limits::{Budget, Limits},
+    parser::TokenType,
+    span::Span,
+    values::{Value, value_helper},
+};
+
+/// 编译后的单个表达式，变量已经解析为槽位
+type Closure = Arc<dyn Fn(&mut Frame<'_>) -> RspResult<Value> + Send + Sync>;
+
+/// 闭包执行时的上下文，资源消耗与语法树解释执行的计算方式相同
+struct Frame<'e> {
+    env: &'e mut dyn SlotEnvironment,
+    budget: &'e mut Budget,
+    /// 已经求出、等待参与运算的值的个数，对应字节码执行时的栈深度
+    pending: usize,
+}
+
+impl Frame<'_> {
+    /// 求值一个节点，计为一条指令
+    #[inline]
+    fn eval(&mut self, closure: &Closure) -> RspResult<Value> {
+        self.budget.step()?;
+        let value = closure(self)?;
+        self.budget.check_stack(self.pending + 1)?;
+        Ok(value)
+    }
+
+    /// 求值 `closure` 时已有 `held` 个值在等待参与运算
+    #[inline]
+    fn eval_holding(&mut self, closure: &Closure, held: usize) -> RspResult<Value> {
+        self.pending += held;
+        let result = self.eval(closure);
+        self.pending -= held;
+        result
+    }
+
+    /// 直接读取、没有编译为闭包的 `n` 个操作数同样按节点计数
+    #[inline]
+    fn operands(&mut self, n: usize) -> RspResult<()> {
+        self.budget.step_n(n as u64)?;
+        self.budget.check_stack(self.pending + n)
+    }
+}
+
+/// 编译为闭包树的一批公式。
+///
+/// 每个表达式只编译一次，变量名在编译时解析为槽位，执行时不再遍历语法树，也不需要解码字节码。
+/// 语义与字节码虚拟机一致：读取未定义的变量时报错，`&&`/`||` 短路时返回左操作数本身。
+/// 闭包无法序列化，需要缓存或跨进程传递时使用字节码。`ClosureProgram` 可以放在 `Arc` 中被多个线程共享。
+#[derive(Clone)]
+pub struct ClosureProgram {
+    /// (公式序号, 闭包)，按执行顺序排列
+    formulas: Vec<(usize, Closure)>,
+    levels: Vec<Vec<usize>>,
+    slot_names: Vec<String>,
+    /// 读写的所有变量，按名称排序
+    names: Vec<String>,
+}
+
+impl ClosureProgram {
+    pub fn compile<'e, T: Borrow<ExprInfo<'e>>>(expr_infos: &[T]) -> RspResult<Self> {
+        let mut compiler = ClosureCompiler::new();
+        let mut formulas = Vec::with_capacity(expr_infos.len());
+        let mut names = BTreeSet::new();
+        for info in expr_infos {
+            let info = info.borrow();
+            formulas.push((info.get_index(), info.get_expr().accept(&mut compiler)?));
+            names.extend(info.get_reads().iter().chain(info.get_writes()).cloned());
+        }
+        let levels = split_levels(expr_infos.iter().map(|info| {
+            let info = info.borrow();
+            (info.get_reads(), info.get_writes())
+        }));
+        Ok(Self {
+            formulas,
+            levels,
+            slot_names: compiler.slot_names,
+            names: names.into_iter().collect(),
+        })
+    }
+
+    /// 变量对应的槽位，变量没有在表达式中出现时返回 `None`
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slot_names.iter().position(|n| n == name)
+    }
+
+    /// 按槽位顺序排列的变量名
+    pub fn slot_names(&self) -> &[String] {
+        &self.slot_names
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slot_names.len()
+    }
+
+    /// 读写的所有变量，按名称排序且不重复
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// 公式的个数，结果按公式序号存放时需要的长度
+    pub fn size(&self) -> usize {
+        self.formulas
+            .iter()
+            .map(|(index, _)| index + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 按执行顺序依次执行，返回 (公式序号, 结果)
+    pub fn run(&self, env: &mut dyn SlotEnvironment) -> RspResult<Vec<(usize, Value)>> {
+        self.run_with_limits(env, &Limits::new())
+    }
+
+    /// 带资源限制执行，与语法树解释执行一样每个节点计为一条指令
+    pub fn run_with_limits(
+        &self,
+        env: &mut dyn SlotEnvironment,
+        limits: &Limits,
+    ) -> RspResult<Vec<(usize, Value)>> {
+        let mut budget = Budget::new(limits);
+        (0..self.formulas.len())
+            .map(|pos| self.run_formula(pos, env, &mut budget))
+            .collect()
+    }
+
+    /// 依赖分层，每一层为 `formulas` 中的位置
+    pub(crate) fn levels(&self) -> &[Vec<usize>] {
+        &self.levels
+    }
+
+    /// 执行单个公式，消耗的资源计入 `budget`
+    pub(crate) fn run_formula(
+        &self,
+        pos: usize,
+        env: &mut dyn SlotEnvironment,
+        budget: &mut Budget,
+    ) -> RspResult<(usize, Value)> {
+        let (index, closure) = &self.formulas[pos];
+        let mut frame = Frame {
+            env,
+            budget,
+            pending: 0,
+        };
+        Ok((*index, frame.eval(closure)?))
+    }
+}
+
+/// 让按名称读写的 [`Environment`] 可以按 [`ClosureProgram`] 的槽位访问
+pub(crate) struct NamedSlots<'a, E: Environment> {
+    names: &'a [String],
+    env: &'a mut E,
+}
+
+impl<'a, E: Environment> NamedSlots<'a, E> {
+    pub(crate) fn new(program: &'a ClosureProgram, env: &'a mut E) -> Self {
+        Self {
+            names: program.slot_names(),
+            env,
+        }
+    }
+}
+
+impl<'a, E: Environment> SlotEnvironment for NamedSlots<'a, E> {
+    fn get_slot(&self, slot: usize) -> Option<&Value> {
+        self.env.get(self.names.get(slot)?)
+    }
+
+    fn put_slot(&mut self, slot: usize, value: Value) -> bool {
+        match self.names.get(slot) {
+            Some(name) => self.env.put(name.clone(), value),
+            None => false,
+        }
+    }
+}
+
+/// 把语法树编译为闭包树，变量名按首次出现的顺序分配槽位
+struct ClosureCompiler {
+    slots: HashMap<String, usize>,
+    slot_names: Vec<String>,
+    function_manager: FunctionManager,
+}
+
+impl ClosureCompiler {
+    fn new() -> Self {
+        Self {
+            slots: HashMap::new(),
+            slot_names: Vec::new(),
+            function_manager: FunctionManager::new(),
+        }
+    }
+
+    fn compile(&mut self, expr: &Expr) -> RspResult<Closure> {
+        expr.accept(self)
+    }
+
+    fn slot(&mut self, name: &str) -> usize {
+        if let Some(&slot) = self.slots.get(name) {
+            return slot;
+        }
+        let slot = self.slot_names.len();
+        self.slots.insert(name.to_string(), slot);
+        self.slot_names.push(name.to_string());
+        slot
+    }
+}
+
+fn undefined_variable(name: &str, span: Span) -> RspError {
+    RspError::RuntimeError {
+        message: format!("Undefined variable: {}", name),
+        span: Some(span),
+        kind: ErrorKind::UndefinedVariable {
+            name: name.to_string(),
+        },
+    }
+}
+
+/// 出错位置与语法树解释执行一致，见 [`value_helper::binary_error_span`]
+fn binary(
+    frame: &Frame,
+    a: &Value,
+    b: &Value,
+    op: &TokenType,
+    spans: (Span, Span),
+) -> RspResult<Value> {
+    let value = value_helper::evaluate_binary(a, b, op)
+        .map_err(|e| e.with_span(value_helper::binary_error_span(op, b, spans.0, spans.1)))?;
+    frame.budget.check_value(&value)?;
+    Ok(value)
+}
+
+impl Visitor<RspResult<Closure>> for ClosureCompiler {
+    fn visit_binary(&mut self, expr: &BinaryExpr) -> RspResult<Closure> {
+        let op = expr.operator.token_type.clone();
+        let spans = (expr.span, expr.right.span());
+        // 操作数是变量或常量时直接借用，省去一次复制
+        let closure: Closure = match (&*expr.left, &*expr.right) {
+            (Expr::Id(a), Expr::Id(b)) => {
+                let (a_name, b_name) = (a.name.lexeme.to_string(), b.name.lexeme.to_string());
+                let (a_span, b_span) = (a.span, b.span);
+                let (a, b) = (self.slot(&a_name), self.slot(&b_name));
+                Arc::new(move |frame| {
+                    frame.operands(2)?;
+                    let a = frame
+                        .env
+                        .get_slot(a)
+                        .ok_or_else(|| undefined_variable(&a_name, a_span))?;
+                    let b = frame
+                        .env
+                        .get_slot(b)
+                        .ok_or_else(|| undefined_variable(&b_name, b_span))?;
+                    binary(frame, a, b, &op, spans)
+                })
+            }
+            (Expr::Id(a), Expr::Literal(LiteralExpr { value, .. })) => {
+                let (name, value, a_span) = (a.name.lexeme.to_string(), value.clone(), a.span);
+                let a = self.slot(&name);
+                Arc::new(move |frame| {
+                    frame.operands(2)?;
+                    let a = frame
+                        .env
+                        .get_slot(a)
+                        .ok_or_else(|| undefined_variable(&name, a_span))?;
+                    binary(frame, a, &value, &op, spans)
+                })
+            }
+            (left, Expr::Literal(LiteralExpr { value, .. })) => {
+                let (left, value) = (self.compile(left)?, value.clone());
+                Arc::new(move |frame| {
+                    let a = frame.eval(&left)?;
+                    // 右操作数为常量，计为一个节点
+                    frame.budget.step()?;
+                    frame.budget.check_stack(frame.pending + 2)?;
+                    binary(frame, &a, &value, &op, spans)
+                })
+            }
+            (left, right) => {
+                let (left, right) = (self.compile(left)?, self.compile(right)?);
+                Arc::new(move |frame| {
+                    let a = frame.eval(&left)?;
+                    let b = frame.eval_holding(&right, 1)?;
+                    binary(frame, &a, &b, &op, spans)
+                })
+            }
+        };
+        Ok(closure)
+    }
+
+    fn visit_logic(&mut self, expr: &LogicExpr) -> RspResult<Closure> {
+        let left = self.compile(&expr.left)?;
+        let right = self.compile(&expr.right)?;
+        let closure: Closure = if expr.operator.token_type == TokenType::And {
+            Arc::new(move |frame| {
+                let a = frame.eval(&left)?;
+                if a.is_truthy() {
+                    frame.eval(&right)
+                } else {
+                    Ok(a)
+                }
+            })
+        } else {
+            Arc::new(move |frame| {
+                let a = frame.eval(&left)?;
+                if a.is_truthy() {
+                    Ok(a)
+                } else {
+                    frame.eval(&right)
+                }
+            })
+        };
+        Ok(closure)
+    }
+
+    fn visit_literal(&mut self, expr: &LiteralExpr) -> RspResult<Closure> {
+        let value = expr.value.clone();
+        Ok(Arc::new(move |_| Ok(value.clone())))
+    }
+
+    fn visit_unary(&mut self, expr: &UnaryExpr) -> RspResult<Closure> {
+        let (op, span) = (expr.operator.token_type.clone(), expr.span);
+        let right = self.compile(&expr.right)?;
+        Ok(Arc::new(move |frame| {
+            value_helper::evaluate_unary(&frame.eval(&right)?, &op).map_err(|e| e.with_span(span))
+        }))
+    }
+
+    fn visit_id(&mut self, expr: &IdExpr) -> RspResult<Closure> {
+        let (name, span) = (expr.name.lexeme.to_string(), expr.span);
+        let slot = self.slot(&name);
+        Ok(Arc::new(move |frame| {
+            frame
+                .env
+                .get_slot(slot)
+                .cloned()
+                .ok_or_else(|| undefined_variable(&name, span))
+        }))
+    }
+
+    fn visit_assign(&mut self, expr: &AssignExpr) -> RspResult<Closure> {
+        let Expr::Id(id) = &*expr.left else {
+            return Err(RspError::CompileError {
+                message: "Invalid assignment target".to_string(),
+                span: Some(expr.left.span()),
+                kind: ErrorKind::InvalidAssignment,
+            });
+        };
+        let (name, span) = (id.name.lexeme.to_string(), id.span);
+        let slot = self.slot(&name);
+        let right = self.compile(&expr.right)?;
+        Ok(Arc::new(move |frame| {
+            let value = frame.eval(&right)?;
+            if !frame.env.put_slot(slot, value.clone()) {
+                return Err(undefined_variable(&name, span));
+            }
+            Ok(value)
+        }))
+    }
+
+    fn visit_call(&mut self, expr: &CallExpr) -> RspResult<Closure> {
+        let Expr::Id(id) = &*expr.callee else {
+            return Err(RspError::CompileError {
+                message: "Only named functions can be called".to_string(),
+                span: Some(expr.callee.span()),
+                kind: ErrorKind::InvalidCall,
+            });
+        };
+        let name = &id.name.lexeme;
+        let func =
+            self.function_manager
+                .get_shared(name)
+                .ok_or_else(|| RspError::CompileError {
+                    message: format!("Undefined function: {}", name),
+                    span: Some(id.span),
+                    kind: ErrorKind::UndefinedFunction {
+                        name: name.to_string(),
+                    },
+                })?;
+        if func.arity() != expr.arguments.len() {
+            return Err(RspError::CompileError {
+                message: format!(
+                    "Expected {} arguments but got {} for function {}",
+                    func.arity(),
+                    expr.arguments.len(),
+                    name
+                ),
+                span: Some(expr.span),
+                kind: ErrorKind::ArityMismatch,
+            });
+        }
+        let arguments = expr
+            .arguments
+            .iter()
+            .map(|arg| self.compile(arg))
+            .collect::<RspResult<Vec<_>>>()?;
+        let span = expr.span;
+        Ok(Arc::new(move |frame| {
+            let mut values = Vec::with_capacity(arguments.len());
+            for arg in &arguments {
+                let held = values.len();
+                values.push(frame.eval_holding(arg, held)?);
+            }
+            let value = func.call(values).map_err(|e| e.with_span(span))?;
+            frame.budget.check_value(&value)?;
+            Ok(value)
+        }))
+    }
+
+    fn visit_if(&mut self, expr: &IfExpr) -> RspResult<Closure> {
+        let condition = self.compile(&expr.condition)?;
+        let then_branch = self.compile(&expr.then_branch)?;
+        let else_branch = match &expr.else_branch {
+            Some(else_branch) => Some(self.compile(else_branch)?),
+            None => None,
+        };
+        Ok(Arc::new(move |frame| {
+            if frame.eval(&condition)?.is_truthy() {
+                frame.eval(&then_branch)
+            } else if let Some(else_branch) = &else_branch {
+                frame.eval(else_branch)
+            } else {
+                Ok(Value::Null)
+            }
+        }))
+    }
+
+    fn visit_get(&mut self, expr: &GetExpr) -> RspResult<Closure> {
+        let (name, name_span, span) = (expr.name.lexeme.to_string(), expr.name.span, expr.span);
+        let object = self.compile(&expr.object)?;
+        Ok(Arc::new(move |frame| match frame.eval(&object)? {
+            Value::Instance(instance) => {
+                instance
+                    .get(&name)
+                    .cloned()
+                    .ok_or_else(|| RspError::RuntimeError {
+                        message: format!("Undefined property: {}", name),
+                        span: Some(name_span),
+                        kind: ErrorKind::UndefinedProperty {
+                            name: name.to_string(),
+                        },
+                    })
+            }
+            _ => Err(RspError::RuntimeError {
+                message: format!("Only instances have properties. error: {}", name),
+                span: Some(span),
+                kind: ErrorKind::NotAnInstance,
+            }),
+        }))
+    }
+
+    fn visit_set(&mut self, expr: &SetExpr) -> RspResult<Closure> {
+        let (name, span) = (expr.name.lexeme.to_string(), expr.span);
+        let value = self.compile(&expr.value)?;
+        let object = self.compile(&expr.object)?;
+        Ok(Arc::new(move |frame| {
+            let value = frame.eval(&value)?;
+            match frame.eval_holding(&object, 1)? {
+                Value::Instance(mut instance) => {
+                    instance.set(name.clone(), value.clone());
+                    Ok(value)
+                }
+                _ => Err(RspError::RuntimeError {
+                    message: format!("Only instances have properties. error: {}", name),
+                    span: Some(span),
+                    kind: ErrorKind::NotAnInstance,
+                }),
+            }
+        }))
+    }
+
+    fn visit_error(&mut self, expr: &ErrorExpr) -> RspResult<Closure> {
+        Err(expr.to_error())
+    }
+}
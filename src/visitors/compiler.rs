@@ -1,15 +1,16 @@
 use std::collections::HashSet;
 
 use crate::{
-    RspError, RspResult,
-    chunk::{Chunk, ChunkWriter},
+    ErrorKind, RspError, RspResult,
+    chunk::{Chunk, ChunkWriter, SourceEntry, SourceMap},
     expr::{
-        AssignExpr, BinaryExpr, CallExpr, Expr, GetExpr, IdExpr, IfExpr, LiteralExpr, LogicExpr,
-        SetExpr, UnaryExpr, Visitor,
+        AssignExpr, BinaryExpr, CallExpr, ErrorExpr, Expr, GetExpr, IdExpr, IfExpr, LiteralExpr,
+        LogicExpr, SetExpr, UnaryExpr, Visitor,
     },
     functions::FunctionManager,
     ir::ExprInfo,
     parser::TokenType,
+    span::Span,
     values::Value,
     vm::OpCode,
 };
@@ -18,6 +19,16 @@ pub struct OpCodeCompiler {
     chunk_writer: ChunkWriter,
     var_set: HashSet<String>,
     function_manager: FunctionManager,
+    debug_info: bool,
+    source_map: SourceMap,
+    /// 正在编译的公式序号
+    formula: u32,
+}
+
+impl Default for OpCodeCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OpCodeCompiler {
@@ -26,12 +37,21 @@ impl OpCodeCompiler {
             chunk_writer: ChunkWriter::new(),
             var_set: HashSet::new(),
             function_manager: FunctionManager::new(),
+            debug_info: true,
+            source_map: SourceMap::new(),
+            formula: 0,
         }
     }
 
+    /// 是否在字节码中写入源码映射，默认开启。关闭后字节码更小，运行时错误只带有公式序号
+    pub fn set_debug_info(&mut self, debug_info: bool) {
+        self.debug_info = debug_info;
+    }
+
     pub fn begin_compile(&mut self) {
         self.chunk_writer.clear();
         self.var_set.clear();
+        self.source_map = SourceMap::new();
     }
 
     pub fn compile(&mut self, expr_info: &ExprInfo) -> RspResult<()> {
@@ -44,38 +64,56 @@ impl OpCodeCompiler {
     }
 
     pub fn compile_expr(&mut self, expr: &Expr, order: usize) -> RspResult<()> {
+        self.formula = order as u32;
         self.emit_op_with_arg(OpCode::Begin, order as i32);
         self.execute(expr)?;
         self.emit_op(OpCode::End);
         Ok(())
     }
 
-    pub fn end_compile(&mut self) -> Chunk {
+    pub fn end_compile(&mut self) -> RspResult<Chunk> {
         self.emit_op(OpCode::Exit);
         self.chunk_writer
-            .set_variables(&self.var_set.iter().cloned().collect::<Vec<_>>());
-        self.chunk_writer.flush()
+            .set_variables(&self.var_set.iter().cloned().collect::<Vec<_>>())?;
+        let mut chunk = self.chunk_writer.flush();
+        if !self.source_map.is_empty() {
+            chunk.debug = std::mem::take(&mut self.source_map).to_bytes();
+        }
+        Ok(chunk)
     }
 
     fn execute(&mut self, expr: &Expr) -> RspResult<()> {
         expr.accept(self)
     }
 
+    /// 记录下一条指令对应的源码位置，只在可能出错的指令前调用
+    fn mark(&mut self, span: Span, operands: Option<(Span, Span)>) {
+        if self.debug_info {
+            self.source_map.push(SourceEntry {
+                offset: self.chunk_writer.position() as u32,
+                formula: self.formula,
+                span,
+                operands,
+            });
+        }
+    }
+
     fn emit_op(&mut self, op: OpCode) {
         self.chunk_writer.write_code(op);
     }
 
     fn emit_op_with_arg(&mut self, op: OpCode, arg: i32) {
         self.chunk_writer.write_code(op);
-        self.chunk_writer.write_int(arg as i32);
+        self.chunk_writer.write_int(arg);
     }
 
-    fn emit_constant(&mut self, value: Value) {
-        let index = self.make_constant(value);
+    fn emit_constant(&mut self, value: Value) -> RspResult<()> {
+        let index = self.make_constant(value)?;
         self.emit_op_with_arg(OpCode::Constant, index as i32);
+        Ok(())
     }
 
-    fn make_constant(&mut self, value: Value) -> usize {
+    fn make_constant(&mut self, value: Value) -> RspResult<usize> {
         self.chunk_writer.add_constant(value)
     }
 
@@ -93,8 +131,6 @@ impl OpCodeCompiler {
 
 impl Visitor<RspResult<()>> for OpCodeCompiler {
     fn visit_binary(&mut self, expr: &BinaryExpr) -> RspResult<()> {
-        self.execute(&expr.left)?;
-        self.execute(&expr.right)?;
         let op = match &expr.operator.token_type {
             TokenType::Plus => OpCode::Add,
             TokenType::Minus => OpCode::Subtract,
@@ -111,9 +147,42 @@ impl Visitor<RspResult<()>> for OpCodeCompiler {
             t => {
                 return Err(RspError::RuntimeError {
                     message: format!("Unknown binary operator: {:?}", t),
+                    span: Some(expr.operator.span),
+                    kind: ErrorKind::Other,
                 });
             }
         };
+        let operands = Some((expr.left.span(), expr.right.span()));
+        // 操作数是变量或常量时合并为一条指令，减少分派和栈操作
+        match (&*expr.left, &*expr.right) {
+            (Expr::Id(a), Expr::Id(b)) => {
+                let a = self.make_constant(Value::String(a.name.lexeme.to_string()))?;
+                let b = self.make_constant(Value::String(b.name.lexeme.to_string()))?;
+                self.mark(expr.span, operands);
+                self.emit_op_with_arg(OpCode::GlobalGlobalOp, a as i32);
+                self.chunk_writer.write_int(b as i32);
+            }
+            (Expr::Id(a), Expr::Literal(b)) => {
+                let a = self.make_constant(Value::String(a.name.lexeme.to_string()))?;
+                let b = self.make_constant(b.value.clone())?;
+                self.mark(expr.span, operands);
+                self.emit_op_with_arg(OpCode::GlobalConstOp, a as i32);
+                self.chunk_writer.write_int(b as i32);
+            }
+            (left, Expr::Literal(b)) => {
+                self.execute(left)?;
+                let b = self.make_constant(b.value.clone())?;
+                self.mark(expr.span, operands);
+                self.emit_op_with_arg(OpCode::ConstOp, b as i32);
+            }
+            (left, right) => {
+                self.execute(left)?;
+                self.execute(right)?;
+                self.mark(expr.span, operands);
+                self.emit_op(op);
+                return Ok(());
+            }
+        }
         self.emit_op(op);
         Ok(())
     }
@@ -141,19 +210,22 @@ impl Visitor<RspResult<()>> for OpCodeCompiler {
             Value::Boolean(v) if v => self.emit_op(OpCode::True),
             Value::Boolean(v) if !v => self.emit_op(OpCode::False),
             Value::Null => self.emit_op(OpCode::Null),
-            _ => self.emit_constant(expr.value.clone()),
+            _ => self.emit_constant(expr.value.clone())?,
         };
         Ok(())
     }
 
     fn visit_unary(&mut self, expr: &UnaryExpr) -> RspResult<()> {
         self.execute(&expr.right)?;
+        self.mark(expr.span, None);
         match &expr.operator.token_type {
             TokenType::Bang => self.emit_op(OpCode::Not),
             TokenType::Minus => self.emit_op(OpCode::Negate),
             t => {
                 return Err(RspError::CompileError {
                     message: format!("unsupported unary operator: {:?}", t),
+                    span: Some(expr.operator.span),
+                    kind: ErrorKind::Other,
                 });
             }
         }
@@ -161,7 +233,8 @@ impl Visitor<RspResult<()>> for OpCodeCompiler {
     }
 
     fn visit_id(&mut self, expr: &IdExpr) -> RspResult<()> {
-        let constant = self.make_constant(Value::String(expr.name.lexeme.to_string()));
+        let constant = self.make_constant(Value::String(expr.name.lexeme.to_string()))?;
+        self.mark(expr.span, None);
         self.emit_op_with_arg(OpCode::GetGlobal, constant as i32);
         Ok(())
     }
@@ -169,39 +242,52 @@ impl Visitor<RspResult<()>> for OpCodeCompiler {
     fn visit_assign(&mut self, expr: &AssignExpr) -> RspResult<()> {
         self.execute(&expr.right)?;
         if let Expr::Id(id_expr) = &*expr.left {
-            let constant = self.make_constant(Value::String(id_expr.name.lexeme.to_string()));
+            let constant = self.make_constant(Value::String(id_expr.name.lexeme.to_string()))?;
+            self.mark(expr.span, Some((id_expr.span, expr.right.span())));
             self.emit_op_with_arg(OpCode::SetGlobal, constant as i32);
         }
         Ok(())
     }
 
     fn visit_call(&mut self, expr: &CallExpr) -> RspResult<()> {
-        if let Expr::Id(id_expr) = &*expr.callee {
-            let name = &id_expr.name.lexeme;
-            let func = self
-                .function_manager
-                .get(name)
-                .ok_or(RspError::CompileError {
-                    message: format!("Undefined function: {}", name),
-                })?;
-
-            if func.arity() != expr.arguments.len() {
-                return Err(RspError::CompileError {
-                    message: format!(
-                        "Expected {} arguments but got {} for function {}",
-                        func.arity(),
-                        expr.arguments.len(),
-                        name
-                    ),
-                });
-            }
+        let Expr::Id(id_expr) = &*expr.callee else {
+            return Err(RspError::CompileError {
+                message: "Only named functions can be called".to_string(),
+                span: Some(expr.callee.span()),
+                kind: ErrorKind::InvalidCall,
+            });
+        };
+        let name = &id_expr.name.lexeme;
+        let func = self
+            .function_manager
+            .get(name)
+            .ok_or(RspError::CompileError {
+                message: format!("Undefined function: {}", name),
+                span: Some(id_expr.span),
+                kind: ErrorKind::UndefinedFunction {
+                    name: name.to_string(),
+                },
+            })?;
 
-            for arg in &expr.arguments {
-                self.execute(arg)?;
-            }
-            let constant = self.make_constant(Value::String(name.to_string()));
-            self.emit_op_with_arg(OpCode::Call, constant as i32);
+        if func.arity() != expr.arguments.len() {
+            return Err(RspError::CompileError {
+                message: format!(
+                    "Expected {} arguments but got {} for function {}",
+                    func.arity(),
+                    expr.arguments.len(),
+                    name
+                ),
+                span: Some(expr.span),
+                kind: ErrorKind::ArityMismatch,
+            });
+        }
+
+        for arg in &expr.arguments {
+            self.execute(arg)?;
         }
+        let constant = self.make_constant(Value::String(name.to_string()))?;
+        self.mark(expr.span, None);
+        self.emit_op_with_arg(OpCode::Call, constant as i32);
         Ok(())
     }
 
@@ -224,7 +310,8 @@ impl Visitor<RspResult<()>> for OpCodeCompiler {
 
     fn visit_get(&mut self, expr: &GetExpr) -> RspResult<()> {
         self.execute(&expr.object)?;
-        let constant = self.make_constant(Value::String(expr.name.lexeme.to_string()));
+        let constant = self.make_constant(Value::String(expr.name.lexeme.to_string()))?;
+        self.mark(expr.span, Some((expr.object.span(), expr.name.span)));
         self.emit_op_with_arg(OpCode::GetProperty, constant as i32);
         Ok(())
     }
@@ -232,8 +319,13 @@ impl Visitor<RspResult<()>> for OpCodeCompiler {
     fn visit_set(&mut self, expr: &SetExpr) -> RspResult<()> {
         self.execute(&expr.value)?;
         self.execute(&expr.object)?;
-        let constant = self.make_constant(Value::String(expr.name.lexeme.to_string()));
+        let constant = self.make_constant(Value::String(expr.name.lexeme.to_string()))?;
+        self.mark(expr.span, None);
         self.emit_op_with_arg(OpCode::SetProperty, constant as i32);
         Ok(())
     }
+
+    fn visit_error(&mut self, expr: &ErrorExpr) -> RspResult<()> {
+        Err(expr.to_error())
+    }
 }
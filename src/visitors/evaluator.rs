@@ -1,34 +1,63 @@
 use crate::environment::Environment;
-use crate::error::{RspError, RspResult};
+use crate::error::{ErrorKind, RspError, RspResult};
+use crate::functions::FunctionManager;
+use crate::limits::{Budget, Limits};
 
 use crate::TokenType;
 use crate::expr::Visitor;
 use crate::values::{Value, value_helper};
 
 use crate::expr::{
-    AssignExpr, BinaryExpr, CallExpr, Expr, GetExpr, IdExpr, IfExpr, LiteralExpr, LogicExpr,
-    SetExpr, UnaryExpr,
+    AssignExpr, BinaryExpr, CallExpr, ErrorExpr, Expr, GetExpr, IdExpr, IfExpr, LiteralExpr,
+    LogicExpr, SetExpr, UnaryExpr,
 };
 
 pub struct Evaluator<'a, E: Environment> {
     environment: &'a mut E,
+    budget: Budget,
+    /// 已经求出、等待参与运算的值的个数，对应字节码执行时的栈深度
+    pending: usize,
+    /// 第一次调用函数时才创建
+    function_manager: Option<FunctionManager>,
 }
 
 impl<'a, E: Environment> Evaluator<'a, E> {
     pub fn new(environment: &'a mut E) -> Self {
-        Self { environment }
+        Self::with_limits(environment, &Limits::new())
+    }
+
+    /// 每访问一个节点计为一条指令，等待参与运算的值的个数按栈深度限制
+    pub fn with_limits(environment: &'a mut E, limits: &Limits) -> Self {
+        Self::with_budget(environment, Budget::new(limits))
+    }
+
+    pub(crate) fn with_budget(environment: &'a mut E, budget: Budget) -> Self {
+        Self {
+            environment,
+            budget,
+            pending: 0,
+            function_manager: None,
+        }
+    }
+
+    /// 取回已消耗的资源，继续用于下一个公式
+    pub(crate) fn into_budget(self) -> Budget {
+        self.budget
     }
 
     pub fn evaluate(&mut self, expr: &Expr) -> RspResult<Value> {
-        expr.accept(self)
+        self.budget.step()?;
+        let value = expr.accept(self)?;
+        self.budget.check_stack(self.pending + 1)?;
+        Ok(value)
     }
 
-    fn call_function(&self, _callee: Value, _arguments: Vec<Value>) -> RspResult<Value> {
-        // For now, we'll implement basic function calling
-        // In a full implementation, this would handle built-in functions
-        Err(crate::error::RspError::RuntimeError {
-            message: "Function calling not implemented".to_string(),
-        })
+    /// 求值 `expr` 时已有 `held` 个值在等待参与运算
+    fn evaluate_holding(&mut self, expr: &Expr, held: usize) -> RspResult<Value> {
+        self.pending += held;
+        let result = self.evaluate(expr);
+        self.pending -= held;
+        result
     }
 }
 
@@ -38,10 +67,17 @@ impl<'a, E: Environment> Visitor<RspResult<Value>> for Evaluator<'a, E> {
             left,
             operator,
             right,
+            ..
         } = expr;
         let left_val = self.evaluate(left)?;
-        let right_val = self.evaluate(right)?;
-        value_helper::evaluate_binary(&left_val, &right_val, &operator.token_type)
+        let right_val = self.evaluate_holding(right, 1)?;
+        let op = &operator.token_type;
+        let value = value_helper::evaluate_binary(&left_val, &right_val, op).map_err(|e| {
+            let span = value_helper::binary_error_span(op, &right_val, expr.span, right.span());
+            e.with_span(span)
+        })?;
+        self.budget.check_value(&value)?;
+        Ok(value)
     }
 
     fn visit_logic(&mut self, expr: &LogicExpr) -> RspResult<Value> {
@@ -49,74 +85,126 @@ impl<'a, E: Environment> Visitor<RspResult<Value>> for Evaluator<'a, E> {
             left,
             operator,
             right,
+            span,
         } = expr;
 
+        // 短路时返回左操作数本身，与字节码和闭包执行一致
         let left_val = self.evaluate(left)?;
         match operator.token_type {
             TokenType::Or => {
                 if left_val.is_truthy() {
-                    Ok(Value::Boolean(true))
+                    Ok(left_val)
                 } else {
                     self.evaluate(right)
                 }
             }
             TokenType::And => {
                 if !left_val.is_truthy() {
-                    Ok(Value::Boolean(false))
+                    Ok(left_val)
                 } else {
                     self.evaluate(right)
                 }
             }
             _ => Err(crate::error::RspError::RuntimeError {
                 message: "Invalid logical operator".to_string(),
+                span: Some(*span),
+                kind: ErrorKind::Other,
             }),
         }
     }
 
     fn visit_literal(&mut self, expr: &LiteralExpr) -> RspResult<Value> {
-        let LiteralExpr { value } = expr;
+        let LiteralExpr { value, .. } = expr;
         Ok(value.clone())
     }
 
     fn visit_unary(&mut self, expr: &UnaryExpr) -> RspResult<Value> {
-        let UnaryExpr { operator, right } = expr;
+        let UnaryExpr {
+            operator,
+            right,
+            span,
+        } = expr;
         let right_val = self.evaluate(right)?;
         value_helper::evaluate_unary(&right_val, &operator.token_type)
+            .map_err(|e| e.with_span(*span))
     }
 
     fn visit_id(&mut self, expr: &IdExpr) -> RspResult<Value> {
-        let IdExpr { name } = expr;
-        Ok(self
-            .environment
+        let IdExpr { name, span } = expr;
+        self.environment
             .get(&name.lexeme)
-            .unwrap_or(&Value::Null)
-            .clone())
+            .cloned()
+            .ok_or_else(|| RspError::RuntimeError {
+                message: format!("Undefined variable: {}", name.lexeme),
+                span: Some(*span),
+                kind: ErrorKind::UndefinedVariable {
+                    name: name.lexeme.to_string(),
+                },
+            })
     }
 
     fn visit_assign(&mut self, expr: &AssignExpr) -> RspResult<Value> {
-        let AssignExpr { left, right, .. } = expr;
-        if let Expr::Id(IdExpr { name }) = &**left {
+        let AssignExpr {
+            left, right, span, ..
+        } = expr;
+        if let Expr::Id(IdExpr { name, .. }) = &**left {
             // Variable assignment
             let value = self.evaluate(right)?;
             self.environment.put(name.lexeme.to_string(), value.clone());
-            return Ok(value);
+            Ok(value)
         } else {
             Err(RspError::RuntimeError {
-                message: "Invalic assign expression".to_string(),
+                message: "Invalid assignment target".to_string(),
+                span: Some(*span),
+                kind: ErrorKind::InvalidAssignment,
             })
         }
     }
 
     fn visit_call(&mut self, expr: &CallExpr) -> RspResult<Value> {
-        let CallExpr {
-            callee, arguments, ..
-        } = expr;
-        let callee_val = self.evaluate(callee)?;
-        let mut arg_values = Vec::new();
-        for arg in arguments {
-            arg_values.push(self.evaluate(arg)?);
+        let Expr::Id(id) = &*expr.callee else {
+            return Err(RspError::RuntimeError {
+                message: "Only named functions can be called".to_string(),
+                span: Some(expr.callee.span()),
+                kind: ErrorKind::InvalidCall,
+            });
+        };
+        let name = &id.name.lexeme;
+        let functions = self
+            .function_manager
+            .get_or_insert_with(FunctionManager::new);
+        let Some(function) = functions.get_shared(name) else {
+            return Err(RspError::RuntimeError {
+                message: format!("Undefined function: {}", name),
+                span: Some(id.span),
+                kind: ErrorKind::UndefinedFunction {
+                    name: name.to_string(),
+                },
+            });
+        };
+        let arity = function.arity();
+        if arity != expr.arguments.len() {
+            return Err(RspError::RuntimeError {
+                message: format!(
+                    "Expected {} arguments but got {} for function {}",
+                    arity,
+                    expr.arguments.len(),
+                    name
+                ),
+                span: Some(expr.span),
+                kind: ErrorKind::ArityMismatch,
+            });
+        }
+        let mut arg_values = Vec::with_capacity(arity);
+        for arg in &expr.arguments {
+            let held = arg_values.len();
+            arg_values.push(self.evaluate_holding(arg, held)?);
         }
-        self.call_function(callee_val, arg_values)
+        let value = function
+            .call(arg_values)
+            .map_err(|e| e.with_span(expr.span))?;
+        self.budget.check_value(&value)?;
+        Ok(value)
     }
 
     fn visit_if(&mut self, expr: &IfExpr) -> RspResult<Value> {
@@ -124,6 +212,7 @@ impl<'a, E: Environment> Visitor<RspResult<Value>> for Evaluator<'a, E> {
             condition,
             then_branch,
             else_branch,
+            ..
         } = expr;
 
         let condition_val = self.evaluate(condition)?;
@@ -137,7 +226,7 @@ impl<'a, E: Environment> Visitor<RspResult<Value>> for Evaluator<'a, E> {
     }
 
     fn visit_get(&mut self, expr: &GetExpr) -> RspResult<Value> {
-        let GetExpr { object, name } = expr;
+        let GetExpr { object, name, span } = expr;
         let object_val = self.evaluate(object)?;
         if let Some(instance) = object_val.as_instance() {
             match instance.get(&name.lexeme) {
@@ -147,6 +236,8 @@ impl<'a, E: Environment> Visitor<RspResult<Value>> for Evaluator<'a, E> {
         } else {
             Err(crate::error::RspError::RuntimeError {
                 message: "Only instances have properties".to_string(),
+                span: Some(*span),
+                kind: ErrorKind::NotAnInstance,
             })
         }
     }
@@ -156,17 +247,24 @@ impl<'a, E: Environment> Visitor<RspResult<Value>> for Evaluator<'a, E> {
             object,
             name,
             value,
+            span,
         } = expr;
 
         let mut object_val = self.evaluate(object)?;
-        let value_val = self.evaluate(value)?;
+        let value_val = self.evaluate_holding(value, 1)?;
         if let Some(instance) = object_val.as_instance_mut() {
             instance.set(name.lexeme.to_string(), value_val.clone());
             Ok(value_val)
         } else {
             Err(crate::error::RspError::RuntimeError {
                 message: "Only instances have fields".to_string(),
+                span: Some(*span),
+                kind: ErrorKind::NotAnInstance,
             })
         }
     }
+
+    fn visit_error(&mut self, expr: &ErrorExpr) -> RspResult<Value> {
+        Err(expr.to_error())
+    }
 }
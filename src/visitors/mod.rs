@@ -1,9 +1,13 @@
+pub mod closure_compiler;
 pub mod compiler;
 pub mod evaluator;
+pub mod optimizer;
 pub mod variable_set;
 pub mod vars_query;
 
+pub use closure_compiler::ClosureProgram;
 pub use compiler::OpCodeCompiler;
 pub use evaluator::Evaluator;
+pub use optimizer::{OptLevel, Optimizer};
 pub use variable_set::VariableSet;
 pub use vars_query::VarsQuery;
@@ -0,0 +1,222 @@
+use crate::TokenType;
+use crate::expr::{
+    AssignExpr, BinaryExpr, CallExpr, Expr, GetExpr, IfExpr, LiteralExpr, LogicExpr, SetExpr,
+    UnaryExpr,
+};
+use crate::functions::FunctionManager;
+use crate::values::{Value, value_helper};
+
+/// 语法树优化级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// 不做任何优化
+    None,
+    /// 折叠常量子表达式、删除不会执行的分支、预先计算纯函数调用，结果与不优化时完全一致
+    Basic,
+    /// 在 `Basic` 的基础上化简 `x * 1`、`x + 0` 等恒等式。
+    /// 化简假定 `x` 是数值，`x` 不是数值时原本应报的类型错误会被省略
+    Full,
+}
+
+/// 对语法树做常量折叠和代数化简，在分析依赖关系之前执行
+pub struct Optimizer {
+    level: OptLevel,
+    function_manager: FunctionManager,
+}
+
+impl Optimizer {
+    pub fn new(level: OptLevel) -> Self {
+        Self {
+            level,
+            function_manager: FunctionManager::new(),
+        }
+    }
+
+    pub fn optimize<'a>(&self, expr: Expr<'a>) -> Expr<'a> {
+        if self.level == OptLevel::None {
+            return expr;
+        }
+        // 折叠或重建的节点保留原来的位置，执行时报错仍能指向源码
+        let span = expr.span();
+        match expr {
+            Expr::Binary(BinaryExpr {
+                left,
+                operator,
+                right,
+                ..
+            }) => {
+                let left = self.optimize(*left);
+                let right = self.optimize(*right);
+                if let (Some(l), Some(r)) = (literal(&left), literal(&right))
+                    && let Some(v) = fold(value_helper::evaluate_binary(l, r, &operator.token_type))
+                {
+                    return Expr::literal(v).with_span(span);
+                }
+                if self.level == OptLevel::Full {
+                    match simplify(left, &operator.token_type, right) {
+                        Ok(expr) => expr,
+                        Err((left, right)) => Expr::binary(left, operator, right),
+                    }
+                } else {
+                    Expr::binary(left, operator, right)
+                }
+            }
+            Expr::Logic(LogicExpr {
+                left,
+                operator,
+                right,
+                ..
+            }) => {
+                let left = self.optimize(*left);
+                let right = self.optimize(*right);
+                // 短路时结果是左操作数本身
+                match (literal(&left), &operator.token_type) {
+                    (Some(l), TokenType::And) if l.is_truthy() => right,
+                    (Some(l), TokenType::Or) if !l.is_truthy() => right,
+                    (Some(l), _) => Expr::literal(l.clone()).with_span(span),
+                    _ => Expr::logic(left, operator, right),
+                }
+            }
+            Expr::Unary(UnaryExpr {
+                operator, right, ..
+            }) => {
+                let right = self.optimize(*right);
+                if let Some(r) = literal(&right)
+                    && let Some(v) = fold(value_helper::evaluate_unary(r, &operator.token_type))
+                {
+                    return Expr::literal(v).with_span(span);
+                }
+                Expr::unary(operator, right)
+            }
+            Expr::Assign(AssignExpr {
+                left,
+                operator,
+                right,
+                ..
+            }) => Expr::assign(*left, operator, self.optimize(*right)),
+            Expr::Call(CallExpr {
+                callee,
+                arguments,
+                r_paren,
+                ..
+            }) => {
+                let arguments: Vec<Expr<'a>> =
+                    arguments.into_iter().map(|a| self.optimize(a)).collect();
+                if let Some(v) = self.call_pure(&callee, &arguments) {
+                    return Expr::literal(v).with_span(span);
+                }
+                Expr::call(*callee, arguments, r_paren)
+            }
+            Expr::If(IfExpr {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            }) => {
+                let condition = self.optimize(*condition);
+                let then_branch = self.optimize(*then_branch);
+                let else_branch = else_branch.map(|e| self.optimize(*e));
+                match literal(&condition) {
+                    Some(c) if c.is_truthy() => then_branch,
+                    Some(_) => {
+                        else_branch.unwrap_or_else(|| Expr::literal(Value::Null).with_span(span))
+                    }
+                    None => Expr::if_expr(condition, then_branch, else_branch).with_span(span),
+                }
+            }
+            Expr::Get(GetExpr { object, name, .. }) => Expr::get(self.optimize(*object), name),
+            Expr::Set(SetExpr {
+                object,
+                name,
+                value,
+                ..
+            }) => Expr::set(self.optimize(*object), name, self.optimize(*value)),
+            expr @ (Expr::Literal(_) | Expr::Id(_) | Expr::Error(_)) => expr,
+        }
+    }
+
+    /// 参数都是常量的纯函数调用，直接计算出结果
+    fn call_pure(&self, callee: &Expr, arguments: &[Expr]) -> Option<Value> {
+        let Expr::Id(id) = callee else {
+            return None;
+        };
+        let func = self.function_manager.get(&id.name.lexeme)?;
+        if !func.is_pure() || func.arity() != arguments.len() {
+            return None;
+        }
+        let args: Option<Vec<Value>> = arguments.iter().map(|a| literal(a).cloned()).collect();
+        fold(func.call(args?))
+    }
+}
+
+fn literal<'e>(expr: &'e Expr) -> Option<&'e Value> {
+    match expr {
+        Expr::Literal(LiteralExpr { value, .. }) => Some(value),
+        _ => None,
+    }
+}
+
+/// 计算出错时不折叠，把错误留到执行时报告；对象不能作为常量
+fn fold(result: crate::RspResult<Value>) -> Option<Value> {
+    result.ok().filter(|v| !v.is_instance())
+}
+
+/// 化简单位元：`x + 0`、`0 + x`、`x - 0`、`x * 1`、`1 * x`、`x / 1`。
+/// 只认整数单位元，`x * 1.0` 会把整数变成浮点数，不能化简
+fn simplify<'a>(
+    left: Expr<'a>,
+    operator: &TokenType,
+    right: Expr<'a>,
+) -> Result<Expr<'a>, (Expr<'a>, Expr<'a>)> {
+    let is = |expr: &Expr, n: i32| matches!(literal(expr), Some(Value::Integer(v)) if *v == n);
+    match operator {
+        TokenType::Plus | TokenType::Minus if is(&right, 0) => Ok(left),
+        TokenType::Plus if is(&left, 0) => Ok(right),
+        TokenType::Star | TokenType::Slash if is(&right, 1) => Ok(left),
+        TokenType::Star if is(&left, 1) => Ok(right),
+        _ => Err((left, right)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn optimize(src: &str, level: OptLevel) -> Option<Value> {
+        let expr = Parser::new(src).parse().unwrap();
+        literal(&Optimizer::new(level).optimize(expr)).cloned()
+    }
+
+    #[test]
+    fn test_fold() {
+        let basic = OptLevel::Basic;
+        assert_eq!(
+            Some(Value::Double(4.0)),
+            optimize("100 / 5 ** 2 ** 1", basic)
+        );
+        assert_eq!(Some(Value::Integer(5)), optimize("abs(2 - 7)", basic));
+        assert_eq!(Some(Value::Integer(1)), optimize("if(1 > 2, x, 1)", basic));
+        assert_eq!(Some(Value::Boolean(true)), optimize("true || x", basic));
+        assert_eq!(
+            Some(Value::Boolean(false)),
+            optimize("!(1 == 1) && x", basic)
+        );
+        // 出错的表达式和非纯函数留到执行时
+        assert_eq!(None, optimize("1 / 0", basic));
+        assert_eq!(None, optimize("\"a\" - 1", basic));
+        assert_eq!(None, optimize("clock()", basic));
+        assert_eq!(None, optimize("0 && x", basic));
+        assert_eq!(None, optimize("1 + 2", OptLevel::None));
+    }
+
+    #[test]
+    fn test_simplify() {
+        let expr = Parser::new("(x + 0) * (3 - 2)").parse().unwrap();
+        let full = Optimizer::new(OptLevel::Full).optimize(expr.clone());
+        assert!(matches!(full, Expr::Id(_)));
+        let basic = Optimizer::new(OptLevel::Basic).optimize(expr);
+        assert!(matches!(basic, Expr::Binary(_)));
+        assert_eq!(None, optimize("x * 1.0", OptLevel::Full));
+    }
+}
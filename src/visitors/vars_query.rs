@@ -6,6 +6,12 @@ pub struct VarsQuery {
     vars: VariableSet,
 }
 
+impl Default for VarsQuery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl VarsQuery {
     pub fn new() -> Self {
         VarsQuery {
@@ -82,6 +88,7 @@ impl Visitor<()> for VarsQuery {
             condition,
             then_branch,
             else_branch,
+            ..
         } = expr;
         self.execute(condition);
         self.execute(then_branch);
@@ -106,6 +113,8 @@ impl Visitor<()> for VarsQuery {
         self.vars.add_assign(id);
         self.execute(&expr.value);
     }
+
+    fn visit_error(&mut self, _expr: &ErrorExpr) {}
 }
 
 #[cfg(test)]
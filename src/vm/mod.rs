@@ -1,5 +1,10 @@
 pub mod opcode;
+pub mod vector;
+pub mod verifier;
+#[allow(clippy::module_inception)]
 pub mod vm;
 
 pub use opcode::OpCode;
-pub use vm::VM;
+pub use vector::VectorVM;
+pub use verifier::Verifier;
+pub use vm::{ExResult, VM};
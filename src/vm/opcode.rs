@@ -34,9 +34,94 @@ pub enum OpCode {
     End = 30,
     Return = 31,
     Exit = 32,
+    /// `GetGlobal a; GetGlobal b; <op>` 合并后的指令，操作数为两个变量名常量下标和一个二元运算指令
+    GlobalGlobalOp = 33,
+    /// `GetGlobal a; Constant k; <op>` 合并后的指令，操作数为变量名常量下标、常量下标和二元运算指令
+    GlobalConstOp = 34,
+    /// `Constant k; <op>` 合并后的指令，左操作数取自栈顶，操作数为常量下标和二元运算指令
+    ConstOp = 35,
     Unknown = 255,
 }
 
+impl OpCode {
+    /// 指令操作数占用的字节数
+    pub fn operand_size(&self) -> usize {
+        use OpCode::*;
+        match self {
+            Constant | GetGlobal | SetGlobal | GetProperty | SetProperty | Jump | JumpIfFalse
+            | Call | Begin => 4,
+            GlobalGlobalOp | GlobalConstOp => 9,
+            ConstOp => 5,
+            _ => 0,
+        }
+    }
+
+    /// 是否为弹出两个操作数、压入一个结果的二元运算指令
+    pub fn is_binary(&self) -> bool {
+        use OpCode::*;
+        matches!(
+            self,
+            EqualEqual
+                | BangEqual
+                | Greater
+                | GreaterEqual
+                | Less
+                | LessEqual
+                | Add
+                | Subtract
+                | Multiply
+                | Divide
+                | Mode
+                | Power
+        )
+    }
+
+    /// 按名称查找指令，名称与 `Debug` 输出一致，如 `"JumpIfFalse"`
+    pub fn from_name(name: &str) -> Option<OpCode> {
+        use OpCode::*;
+        let op = match name {
+            "Constant" => Constant,
+            "Null" => Null,
+            "True" => True,
+            "False" => False,
+            "Pop" => Pop,
+            "GetLocal" => GetLocal,
+            "SetLocal" => SetLocal,
+            "GetGlobal" => GetGlobal,
+            "DefineGlobal" => DefineGlobal,
+            "SetGlobal" => SetGlobal,
+            "GetProperty" => GetProperty,
+            "SetProperty" => SetProperty,
+            "EqualEqual" => EqualEqual,
+            "BangEqual" => BangEqual,
+            "Greater" => Greater,
+            "GreaterEqual" => GreaterEqual,
+            "Less" => Less,
+            "LessEqual" => LessEqual,
+            "Add" => Add,
+            "Subtract" => Subtract,
+            "Multiply" => Multiply,
+            "Divide" => Divide,
+            "Mode" => Mode,
+            "Power" => Power,
+            "Not" => Not,
+            "Negate" => Negate,
+            "Jump" => Jump,
+            "JumpIfFalse" => JumpIfFalse,
+            "Call" => Call,
+            "Begin" => Begin,
+            "End" => End,
+            "Return" => Return,
+            "Exit" => Exit,
+            "GlobalGlobalOp" => GlobalGlobalOp,
+            "GlobalConstOp" => GlobalConstOp,
+            "ConstOp" => ConstOp,
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
 impl From<u8> for OpCode {
     fn from(v: u8) -> Self {
         use OpCode::*;
@@ -74,6 +159,9 @@ impl From<u8> for OpCode {
             30 => End,
             31 => Return,
             32 => Exit,
+            33 => GlobalGlobalOp,
+            34 => GlobalConstOp,
+            35 => ConstOp,
             _ => Unknown,
         }
     }
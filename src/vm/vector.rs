@@ -0,0 +1,556 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::{
+    RspResult,
+    chunk::{ChunkReader, CodeBlock, Program},
+    column::{Batch, Column},
+    environment::{DefaultEnvironment, Environment},
+    error::{ErrorKind, RspError},
+    functions::FunctionManager,
+    limits::{Budget, Limits},
+    parser::TokenType,
+    values::{Value, value_helper},
+    vm::{
+        OpCode, VM,
+        vm::{Culprit, Trace},
+    },
+};
+
+/// 栈上的值：所有行共用的标量，或者每行一个值的整列
+#[derive(Clone)]
+enum Operand {
+    Scalar(Value),
+    Column(Rc<Column>),
+}
+
+impl Operand {
+    fn get(&self, row: usize) -> Value {
+        match self {
+            Operand::Scalar(v) => v.clone(),
+            Operand::Column(c) => c.get(row),
+        }
+    }
+
+    fn into_column(self, rows: usize) -> Column {
+        match self {
+            Operand::Scalar(v) => Column::repeat(&v, rows),
+            Operand::Column(c) => Rc::try_unwrap(c).unwrap_or_else(|c| (*c).clone()),
+        }
+    }
+
+    fn int_lane(&self) -> Option<Lane<'_, i32>> {
+        match self {
+            Operand::Scalar(Value::Integer(i)) => Some(Lane::Scalar(*i)),
+            Operand::Column(c) => match &**c {
+                Column::Integer(v) => Some(Lane::Slice(v)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// 整数和浮点数都转为浮点数，整数列需要先复制到 `buf` 中
+    fn double_lane<'c>(&'c self, buf: &'c mut Vec<f64>) -> Option<Lane<'c, f64>> {
+        match self {
+            Operand::Scalar(Value::Integer(i)) => Some(Lane::Scalar(*i as f64)),
+            Operand::Scalar(Value::Double(d)) => Some(Lane::Scalar(*d)),
+            Operand::Column(c) => match &**c {
+                Column::Double(v) => Some(Lane::Slice(v)),
+                Column::Integer(v) => {
+                    buf.extend(v.iter().map(|i| *i as f64));
+                    Some(Lane::Slice(buf))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// 数值快速路径的一个操作数
+#[derive(Clone, Copy)]
+enum Lane<'c, T> {
+    Scalar(T),
+    Slice(&'c [T]),
+}
+
+impl<T: Copy + PartialEq> Lane<'_, T> {
+    fn contains(&self, x: T) -> bool {
+        match self {
+            Lane::Scalar(v) => *v == x,
+            Lane::Slice(v) => v.contains(&x),
+        }
+    }
+}
+
+/// 逐行计算 `f(a, b)`，标量与整列组合时不需要展开标量
+fn zip_map<A: Copy, B: Copy, R>(
+    a: Lane<A>,
+    b: Lane<B>,
+    rows: usize,
+    f: impl Fn(A, B) -> R,
+) -> Vec<R> {
+    match (a, b) {
+        (Lane::Slice(a), Lane::Slice(b)) => a.iter().zip(b).map(|(x, y)| f(*x, *y)).collect(),
+        (Lane::Slice(a), Lane::Scalar(y)) => a.iter().map(|x| f(*x, y)).collect(),
+        (Lane::Scalar(x), Lane::Slice(b)) => b.iter().map(|y| f(x, *y)).collect(),
+        (Lane::Scalar(x), Lane::Scalar(y)) => (0..rows).map(|_| f(x, y)).collect(),
+    }
+}
+
+/// 按列执行字节码的虚拟机。
+///
+/// 每条指令一次处理所有行，操作数类型一致的整数、浮点数列使用专门的循环计算，
+/// 其它情况逐行调用与 [`VM`] 相同的运算。包含跳转或属性访问的公式块无法按列执行，
+/// 会退回到逐行用 [`VM`] 执行。结果与逐行执行完全一致，出错时错误信息中带有出错的行号。
+pub struct VectorVM {
+    stack: Vec<Operand>,
+    function_manager: FunctionManager,
+    limits: Limits,
+    budget: Budget,
+}
+
+impl Default for VectorVM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VectorVM {
+    pub fn new() -> Self {
+        Self::with_limits(Limits::new())
+    }
+
+    /// 按 `limits` 限制执行，一条指令按处理的行数计入指令数
+    pub fn with_limits(limits: Limits) -> Self {
+        Self {
+            stack: Vec::new(),
+            function_manager: FunctionManager::new(),
+            budget: Budget::new(&limits),
+            limits,
+        }
+    }
+
+    /// 在 `batch` 的所有行上执行 `program`，返回按公式序号存放的结果列。
+    /// 公式赋值的变量以列的形式写回 `batch`
+    pub fn execute_program(
+        &mut self,
+        program: &Program,
+        batch: &mut Batch,
+    ) -> RspResult<Vec<Column>> {
+        let rows = batch.rows();
+        let mut vars: HashMap<String, Operand> = std::mem::take(&mut batch.columns)
+            .into_iter()
+            .map(|(name, c)| (name, Operand::Column(Rc::new(c))))
+            .collect();
+        let columns: HashSet<String> = vars.keys().cloned().collect();
+        for (name, v) in &batch.scalars {
+            vars.insert(name.clone(), Operand::Scalar(v.clone()));
+        }
+
+        self.budget = Budget::new(&self.limits);
+        let result = self.run_blocks(program, rows, &mut vars);
+
+        // 无论成功与否都把原有的列还给 `batch`，成功时再写回赋值的变量
+        let writes = &program.variables().writes;
+        for (name, v) in vars {
+            let written = result.is_ok() && writes.contains(&name);
+            if written || columns.contains(&name) {
+                batch.scalars.remove(&name);
+                batch.columns.insert(name, v.into_column(rows));
+            }
+        }
+        let results = result?;
+
+        let size = results
+            .iter()
+            .map(|(index, _)| *index as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let mut columns = vec![Column::repeat(&Value::Null, rows); size];
+        for (index, column) in results {
+            columns[index as usize] = column;
+        }
+        Ok(columns)
+    }
+
+    fn run_blocks(
+        &mut self,
+        program: &Program,
+        rows: usize,
+        vars: &mut HashMap<String, Operand>,
+    ) -> RspResult<Vec<(i32, Column)>> {
+        let mut reader = program.reader();
+        let mut results = Vec::with_capacity(program.blocks().len());
+        for block in program.blocks() {
+            let column = if Self::is_vectorizable(&mut reader, block)? {
+                let mut trace = Trace::default();
+                self.run_block(&mut reader, block, rows, vars, &mut trace)
+                    .map_err(|e| trace.locate(e, reader.source_map()))?
+            } else {
+                self.run_rows(&mut reader, block, rows, vars)?
+            };
+            results.push((block.index, column));
+        }
+        Ok(results)
+    }
+
+    /// 公式块中没有跳转和属性访问时才能按列执行
+    fn is_vectorizable(reader: &mut ChunkReader, block: &CodeBlock) -> RspResult<bool> {
+        reader.new_position(block.start);
+        while reader.position() < block.end {
+            let op = reader.read_opcode()?;
+            if matches!(
+                op,
+                OpCode::Jump | OpCode::JumpIfFalse | OpCode::GetProperty | OpCode::SetProperty
+            ) {
+                return Ok(false);
+            }
+            reader.new_position(reader.position() + op.operand_size());
+        }
+        Ok(true)
+    }
+
+    /// 逐行用 [`VM`] 执行公式块，每行使用独立的环境
+    fn run_rows(
+        &mut self,
+        reader: &mut ChunkReader,
+        block: &CodeBlock,
+        rows: usize,
+        vars: &mut HashMap<String, Operand>,
+    ) -> RspResult<Column> {
+        let mut vm = VM::with_limits(self.limits.clone());
+        let mut results = Vec::with_capacity(rows);
+        let mut writes: Vec<(&String, Vec<Value>)> = block
+            .writes
+            .iter()
+            .map(|name| (name, Vec::with_capacity(rows)))
+            .collect();
+        for row in 0..rows {
+            let mut env = DefaultEnvironment::new();
+            for name in block.reads.iter().chain(&block.writes) {
+                if let Some(v) = vars.get(name) {
+                    env.put(name.clone(), v.get(row));
+                }
+            }
+            let result = vm
+                .run_block_with(reader, &mut env, block, &mut self.budget)
+                .map_err(|e| at_row(e, row))?;
+            results.push(result.result);
+            for (name, values) in writes.iter_mut() {
+                values.push(env.get(name).cloned().unwrap_or(Value::Null));
+            }
+        }
+        for (name, values) in writes {
+            let column = Column::from_values(values);
+            vars.insert(name.clone(), Operand::Column(Rc::new(column)));
+        }
+        Ok(Column::from_values(results))
+    }
+
+    fn run_block(
+        &mut self,
+        reader: &mut ChunkReader,
+        block: &CodeBlock,
+        rows: usize,
+        vars: &mut HashMap<String, Operand>,
+        trace: &mut Trace,
+    ) -> RspResult<Column> {
+        self.stack.clear();
+        reader.new_position(block.start);
+        let order = block.index;
+        loop {
+            self.budget.step_n(rows.max(1) as u64)?;
+            trace.start = reader.position();
+            let op = reader.read_opcode()?;
+            match op {
+                OpCode::Begin => {
+                    reader.read_int()?;
+                }
+                OpCode::End => {
+                    let result = self.pop()?;
+                    return Ok(result.into_column(rows));
+                }
+                OpCode::Constant => {
+                    let index = reader.read_int()? as usize;
+                    self.push(Operand::Scalar(reader.read_const(index)?.clone()))?;
+                }
+                OpCode::Null => self.push(Operand::Scalar(Value::Null))?,
+                OpCode::True => self.push(Operand::Scalar(Value::Boolean(true)))?,
+                OpCode::False => self.push(Operand::Scalar(Value::Boolean(false)))?,
+                OpCode::Pop => {
+                    self.pop()?;
+                }
+                OpCode::GetGlobal => {
+                    let value = Self::read_global(reader, vars, order)?;
+                    self.push(value)?;
+                }
+                OpCode::SetGlobal => {
+                    let index = reader.read_int()? as usize;
+                    let name = reader.read_const(index)?.as_str();
+                    let value = self.peek()?.clone();
+                    vars.insert(name.to_string(), value);
+                }
+                OpCode::GlobalGlobalOp => {
+                    let a = Self::read_global(reader, vars, order)
+                        .inspect_err(|_| trace.culprit = Culprit::Left)?;
+                    let b = Self::read_global(reader, vars, order)
+                        .inspect_err(|_| trace.culprit = Culprit::Right)?;
+                    let op = reader.read_opcode()?;
+                    self.push_result(Self::binary(op, &a, &b, rows, trace)?)?;
+                }
+                OpCode::GlobalConstOp => {
+                    let a = Self::read_global(reader, vars, order)
+                        .inspect_err(|_| trace.culprit = Culprit::Left)?;
+                    let index = reader.read_int()? as usize;
+                    let b = Operand::Scalar(reader.read_const(index)?.clone());
+                    let op = reader.read_opcode()?;
+                    self.push_result(Self::binary(op, &a, &b, rows, trace)?)?;
+                }
+                OpCode::ConstOp => {
+                    let index = reader.read_int()? as usize;
+                    let b = Operand::Scalar(reader.read_const(index)?.clone());
+                    let op = reader.read_opcode()?;
+                    let a = self.pop()?;
+                    self.push_result(Self::binary(op, &a, &b, rows, trace)?)?;
+                }
+                op if op.is_binary() => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push_result(Self::binary(op, &a, &b, rows, trace)?)?;
+                }
+                OpCode::Not => {
+                    let a = self.pop()?;
+                    self.push_result(Self::unary(TokenType::Bang, a, rows)?)?;
+                }
+                OpCode::Negate => {
+                    let a = self.pop()?;
+                    self.push_result(Self::unary(TokenType::Minus, a, rows)?)?;
+                }
+                OpCode::Call => {
+                    let index = reader.read_int()? as usize;
+                    let name = reader.read_const(index)?.as_str();
+                    let value = self.call_function(name, rows)?;
+                    self.push_result(value)?;
+                }
+                OpCode::Return => {}
+                _ => {
+                    return Err(RspError::RuntimeError {
+                        message: format!("Unknown instruction: {:?}, order: {}", op, order),
+                        span: None,
+                        kind: ErrorKind::Other,
+                    });
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, value: Operand) -> RspResult<()> {
+        if self.stack.len() >= self.limits.max_stack {
+            return Err(RspError::StackOverflow {
+                limit: self.limits.max_stack,
+            });
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// 压入运算结果，结果中的字符串不能超过长度限制
+    fn push_result(&mut self, value: Operand) -> RspResult<()> {
+        match &value {
+            Operand::Scalar(v) => self.budget.check_value(v)?,
+            Operand::Column(c) => {
+                if let Column::Values(values) = &**c {
+                    for v in values {
+                        self.budget.check_value(v)?;
+                    }
+                }
+            }
+        }
+        self.push(value)
+    }
+
+    fn pop(&mut self) -> RspResult<Operand> {
+        self.stack.pop().ok_or_else(|| RspError::RuntimeError {
+            message: "Stack underflow".to_string(),
+            span: None,
+            kind: ErrorKind::Other,
+        })
+    }
+
+    fn peek(&self) -> RspResult<&Operand> {
+        self.stack.last().ok_or_else(|| RspError::RuntimeError {
+            message: "Stack underflow".to_string(),
+            span: None,
+            kind: ErrorKind::Other,
+        })
+    }
+
+    fn read_global(
+        reader: &mut ChunkReader,
+        vars: &HashMap<String, Operand>,
+        order: i32,
+    ) -> RspResult<Operand> {
+        let index = reader.read_int()? as usize;
+        let name = reader.read_const(index)?.as_str();
+        vars.get(name)
+            .cloned()
+            .ok_or_else(|| RspError::RuntimeError {
+                message: format!("Undefined variable: {}, order: {}", name, order),
+                span: None,
+                kind: ErrorKind::UndefinedVariable {
+                    name: name.to_string(),
+                },
+            })
+    }
+
+    fn call_function(&mut self, name: &str, rows: usize) -> RspResult<Operand> {
+        let function = self
+            .function_manager
+            .get(name)
+            .ok_or_else(|| RspError::RuntimeError {
+                message: format!("Undefined function: {}", name),
+                span: None,
+                kind: ErrorKind::UndefinedFunction {
+                    name: name.to_string(),
+                },
+            })?;
+        let at = self.stack.len().saturating_sub(function.arity());
+        let arguments = self.stack.split_off(at);
+        // 参数都是标量的纯函数只需要调用一次
+        if function.is_pure() && arguments.iter().all(|a| matches!(a, Operand::Scalar(_))) {
+            let values = arguments.iter().map(|a| a.get(0)).collect();
+            return Ok(Operand::Scalar(function.call(values)?));
+        }
+        let values = (0..rows)
+            .map(|row| {
+                function
+                    .call(arguments.iter().map(|a| a.get(row)).collect())
+                    .map_err(|e| at_row(e, row))
+            })
+            .collect::<RspResult<Vec<_>>>()?;
+        Ok(Operand::Column(Rc::new(Column::from_values(values))))
+    }
+
+    fn unary(op: TokenType, a: Operand, rows: usize) -> RspResult<Operand> {
+        let column = match (&a, &op) {
+            (Operand::Scalar(v), _) => {
+                return Ok(Operand::Scalar(value_helper::evaluate_unary(v, &op)?));
+            }
+            (Operand::Column(c), TokenType::Minus) => match &**c {
+                Column::Integer(v) => Column::Integer(v.iter().map(|x| x.wrapping_neg()).collect()),
+                Column::Double(v) => Column::Double(v.iter().map(|x| -x).collect()),
+                _ => Self::generic_unary(&op, &a, rows)?,
+            },
+            (Operand::Column(c), TokenType::Bang) => match &**c {
+                Column::Boolean(v) => Column::Boolean(v.iter().map(|x| !x).collect()),
+                _ => Self::generic_unary(&op, &a, rows)?,
+            },
+            _ => Self::generic_unary(&op, &a, rows)?,
+        };
+        Ok(Operand::Column(Rc::new(column)))
+    }
+
+    fn generic_unary(op: &TokenType, a: &Operand, rows: usize) -> RspResult<Column> {
+        let values = (0..rows)
+            .map(|row| value_helper::evaluate_unary(&a.get(row), op).map_err(|e| at_row(e, row)))
+            .collect::<RspResult<Vec<_>>>()?;
+        Ok(Column::from_values(values))
+    }
+
+    fn binary(
+        op: OpCode,
+        a: &Operand,
+        b: &Operand,
+        rows: usize,
+        trace: &mut Trace,
+    ) -> RspResult<Operand> {
+        let token = VM::binary_token(op)?;
+        if let (Operand::Scalar(a), Operand::Scalar(b)) = (a, b) {
+            return Ok(Operand::Scalar(
+                value_helper::evaluate_binary(a, b, &token)
+                    .inspect_err(|_| trace.culprit = VM::binary_culprit(&token, b))?,
+            ));
+        }
+        let column = match Self::numeric(op, a, b, rows) {
+            Some(column) => column,
+            None => {
+                let values = (0..rows)
+                    .map(|row| {
+                        let b = b.get(row);
+                        value_helper::evaluate_binary(&a.get(row), &b, &token).map_err(|e| {
+                            trace.culprit = VM::binary_culprit(&token, &b);
+                            at_row(e, row)
+                        })
+                    })
+                    .collect::<RspResult<Vec<_>>>()?;
+                Column::from_values(values)
+            }
+        };
+        Ok(Operand::Column(Rc::new(column)))
+    }
+
+    /// 整数、浮点数列的快速路径，语义与 `value_helper::evaluate_binary` 一致。
+    /// 整数除以 0 等需要报错的情况返回 `None`，交给逐行计算报告出错的行
+    fn numeric(op: OpCode, a: &Operand, b: &Operand, rows: usize) -> Option<Column> {
+        use OpCode::*;
+        if let (Some(x), Some(y)) = (a.int_lane(), b.int_lane()) {
+            let column = match op {
+                Add => Column::Integer(zip_map(x, y, rows, |x, y| x.wrapping_add(y))),
+                Subtract => Column::Integer(zip_map(x, y, rows, |x, y| x.wrapping_sub(y))),
+                Multiply => Column::Integer(zip_map(x, y, rows, |x, y| x.wrapping_mul(y))),
+                Divide if !y.contains(0) => {
+                    Column::Integer(zip_map(x, y, rows, |x, y| x.wrapping_div(y)))
+                }
+                Mode if !y.contains(0) => {
+                    Column::Integer(zip_map(x, y, rows, |x, y| x.wrapping_rem(y)))
+                }
+                Power => Column::Double(zip_map(x, y, rows, |x, y| (x as f64).powf(y as f64))),
+                Greater => Column::Boolean(zip_map(x, y, rows, |x, y| x > y)),
+                GreaterEqual => Column::Boolean(zip_map(x, y, rows, |x, y| x >= y)),
+                Less => Column::Boolean(zip_map(x, y, rows, |x, y| x < y)),
+                LessEqual => Column::Boolean(zip_map(x, y, rows, |x, y| x <= y)),
+                _ => return None,
+            };
+            return Some(column);
+        }
+        // 整数除数为 0 时报错，浮点数除数为 0 时得到无穷大
+        if matches!(op, Divide | Mode) && b.int_lane().is_some_and(|y| y.contains(0)) {
+            return None;
+        }
+        let (mut buf_a, mut buf_b) = (Vec::new(), Vec::new());
+        let x = a.double_lane(&mut buf_a)?;
+        let y = b.double_lane(&mut buf_b)?;
+        let column = match op {
+            Add => Column::Double(zip_map(x, y, rows, |x, y| x + y)),
+            Subtract => Column::Double(zip_map(x, y, rows, |x, y| x - y)),
+            Multiply => Column::Double(zip_map(x, y, rows, |x, y| x * y)),
+            Divide => Column::Double(zip_map(x, y, rows, |x, y| x / y)),
+            Mode => Column::Double(zip_map(x, y, rows, |x, y| x % y)),
+            Power => Column::Double(zip_map(x, y, rows, f64::powf)),
+            Greater => Column::Boolean(zip_map(x, y, rows, |x, y| x > y)),
+            GreaterEqual => Column::Boolean(zip_map(x, y, rows, |x, y| x >= y)),
+            Less => Column::Boolean(zip_map(x, y, rows, |x, y| x < y)),
+            LessEqual => Column::Boolean(zip_map(x, y, rows, |x, y| x <= y)),
+            _ => return None,
+        };
+        Some(column)
+    }
+}
+
+/// 在运行时错误信息后追加出错的行号
+fn at_row(error: RspError, row: usize) -> RspError {
+    match error {
+        RspError::RuntimeError {
+            message,
+            span,
+            kind,
+        } => RspError::RuntimeError {
+            message: format!("{}, row: {}", message, row),
+            span,
+            kind,
+        },
+        e => e,
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    RspError, RspResult,
+    chunk::{Chunk, ConstantPool, Program},
+    functions::FunctionManager,
+    values::Value,
+    vm::{OpCode, VM},
+};
+
+/// 解码后的一条指令
+struct Instruction {
+    offset: usize,
+    op: OpCode,
+    operand: i32,
+    /// 下一条指令的位置
+    next: usize,
+}
+
+/// 字节码校验器，在执行前检查来自外部（缓存、其它服务）的字节码是否合法：
+///
+/// - 指令是否合法，操作数是否完整
+/// - 常量下标是否越界，变量名、属性名和函数名是否指向字符串常量，函数是否存在
+/// - 跳转目标是否落在同一个公式块内某条指令的开头
+/// - `Begin`/`End` 是否成对出现，`Exit` 是否位于末尾
+/// - `Begin` 的公式序号是否在 `0..Program::MAX_FORMULAS` 内且互不相同
+/// - 每个公式块内任意执行路径上的栈深度是否一致、是否出现下溢或超出上限，
+///   以及 `End` 时栈上是否恰好只有一个结果
+pub struct Verifier<'a> {
+    code: &'a [u8],
+    pool: ConstantPool,
+    function_manager: FunctionManager,
+}
+
+impl<'a> Verifier<'a> {
+    pub fn new(chunk: &'a Chunk) -> RspResult<Self> {
+        Ok(Self {
+            code: &chunk.codes,
+            pool: ConstantPool::from_bytes(&chunk.constants)?,
+            function_manager: FunctionManager::new(),
+        })
+    }
+
+    pub fn verify(chunk: &Chunk) -> RspResult<()> {
+        Verifier::new(chunk)?.run()
+    }
+
+    pub fn run(&self) -> RspResult<()> {
+        let instructions = self.decode()?;
+        let mut block_start: Option<usize> = None;
+        let mut indexes = HashSet::new();
+        let mut exited = false;
+        for (i, ins) in instructions.iter().enumerate() {
+            if exited {
+                return Err(self.error(ins.offset, "Instruction after Exit"));
+            }
+            match ins.op {
+                OpCode::Begin => {
+                    if block_start.is_some() {
+                        return Err(self.error(ins.offset, "Nested Begin"));
+                    }
+                    if !indexes.insert(ins.operand) {
+                        return Err(self.error(
+                            ins.offset,
+                            &format!("Duplicate formula index: {}", ins.operand),
+                        ));
+                    }
+                    block_start = Some(i);
+                }
+                OpCode::End => match block_start.take() {
+                    Some(start) => self.verify_block(&instructions[start..=i])?,
+                    None => return Err(self.error(ins.offset, "End without Begin")),
+                },
+                OpCode::Exit => {
+                    if block_start.is_some() {
+                        return Err(self.error(ins.offset, "Exit inside a formula block"));
+                    }
+                    exited = true;
+                }
+                _ => {
+                    if block_start.is_none() {
+                        return Err(self.error(ins.offset, "Instruction outside a formula block"));
+                    }
+                }
+            }
+        }
+        if !exited {
+            return Err(self.error(self.code.len(), "Missing Exit"));
+        }
+        Ok(())
+    }
+
+    /// 逐条解码指令，检查指令和操作数本身是否合法
+    fn decode(&self) -> RspResult<Vec<Instruction>> {
+        let mut instructions = Vec::new();
+        let mut ip = 0;
+        while ip < self.code.len() {
+            let offset = ip;
+            let op = OpCode::from(self.code[ip]);
+            ip += 1;
+            match op {
+                OpCode::Unknown | OpCode::GetLocal | OpCode::SetLocal | OpCode::DefineGlobal => {
+                    return Err(self.error(
+                        offset,
+                        &format!("Invalid instruction: {:#04x}", self.code[offset]),
+                    ));
+                }
+                _ => {}
+            }
+
+            let size = op.operand_size();
+            if ip + size > self.code.len() {
+                return Err(self.error(offset, &format!("Truncated operand of {:?}", op)));
+            }
+            let read_int = |at: usize| {
+                let b = &self.code[at..at + 4];
+                i32::from_be_bytes([b[0], b[1], b[2], b[3]])
+            };
+            let operand = if size >= 4 { read_int(ip) } else { 0 };
+            // 合并指令的第二个常量下标，仅 `GlobalGlobalOp`/`GlobalConstOp` 使用
+            let operand2 = if size == 9 { read_int(ip + 4) } else { 0 };
+            if matches!(
+                op,
+                OpCode::GlobalGlobalOp | OpCode::GlobalConstOp | OpCode::ConstOp
+            ) {
+                let byte = self.code[ip + size - 1];
+                if !OpCode::from(byte).is_binary() {
+                    return Err(self.error(
+                        offset,
+                        &format!("Invalid binary operator in {:?}: {:#04x}", op, byte),
+                    ));
+                }
+            }
+            ip += size;
+
+            match op {
+                OpCode::Constant => {
+                    self.constant(offset, operand)?;
+                }
+                OpCode::GetGlobal
+                | OpCode::SetGlobal
+                | OpCode::GetProperty
+                | OpCode::SetProperty => {
+                    self.string_constant(offset, op, operand)?;
+                }
+                OpCode::GlobalGlobalOp => {
+                    self.string_constant(offset, op, operand)?;
+                    self.string_constant(offset, op, operand2)?;
+                }
+                OpCode::GlobalConstOp => {
+                    self.string_constant(offset, op, operand)?;
+                    self.constant(offset, operand2)?;
+                }
+                OpCode::ConstOp => {
+                    self.constant(offset, operand)?;
+                }
+                OpCode::Call => {
+                    let name = self.string_constant(offset, op, operand)?;
+                    if self.function_manager.get(name).is_none() {
+                        return Err(self.error(offset, &format!("Undefined function: {}", name)));
+                    }
+                }
+                OpCode::Begin if operand < 0 || operand as usize >= Program::MAX_FORMULAS => {
+                    return Err(self.error(
+                        offset,
+                        &format!(
+                            "Formula index {} out of range, max: {}",
+                            operand,
+                            Program::MAX_FORMULAS - 1
+                        ),
+                    ));
+                }
+                OpCode::Jump | OpCode::JumpIfFalse if operand < 0 => {
+                    return Err(self.error(offset, &format!("Negative jump offset: {}", operand)));
+                }
+                _ => {}
+            }
+
+            instructions.push(Instruction {
+                offset,
+                op,
+                operand,
+                next: ip,
+            });
+        }
+        Ok(instructions)
+    }
+
+    /// 校验一个 `Begin` ... `End` 公式块内的跳转目标和栈深度
+    fn verify_block(&self, block: &[Instruction]) -> RspResult<()> {
+        let positions: HashMap<usize, usize> = block
+            .iter()
+            .enumerate()
+            .map(|(i, ins)| (ins.offset, i))
+            .collect();
+        let mut depths: Vec<Option<usize>> = vec![None; block.len()];
+        let mut work = vec![(0usize, 0usize)];
+
+        while let Some((i, depth)) = work.pop() {
+            match depths[i] {
+                Some(d) if d == depth => continue,
+                Some(d) => {
+                    return Err(self.error(
+                        block[i].offset,
+                        &format!("Inconsistent stack depth: {} and {}", d, depth),
+                    ));
+                }
+                None => depths[i] = Some(depth),
+            }
+
+            let ins = &block[i];
+            let (required, pushed) = self.stack_effect(ins)?;
+            if depth < required {
+                return Err(self.error(
+                    ins.offset,
+                    &format!("Stack underflow in {:?}: depth {}", ins.op, depth),
+                ));
+            }
+            let after = depth - required + pushed;
+            if after > VM::STACK_MAX {
+                return Err(self.error(ins.offset, "Stack depth exceeds the VM limit"));
+            }
+
+            match ins.op {
+                OpCode::End => {
+                    if depth != 1 {
+                        return Err(self.error(
+                            ins.offset,
+                            &format!("Stack depth at End should be 1, got {}", depth),
+                        ));
+                    }
+                }
+                OpCode::Jump => {
+                    work.push((self.jump_target(block, &positions, ins)?, after));
+                }
+                OpCode::JumpIfFalse => {
+                    work.push((self.jump_target(block, &positions, ins)?, after));
+                    work.push((i + 1, after));
+                }
+                _ => work.push((i + 1, after)),
+            }
+        }
+        Ok(())
+    }
+
+    /// 指令执行前需要的栈深度，以及执行后压入的值的个数
+    fn stack_effect(&self, ins: &Instruction) -> RspResult<(usize, usize)> {
+        let effect = match ins.op {
+            OpCode::Begin | OpCode::Return => (0, 0),
+            OpCode::Constant
+            | OpCode::Null
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetGlobal
+            | OpCode::GlobalGlobalOp
+            | OpCode::GlobalConstOp => (0, 1),
+            OpCode::Pop | OpCode::End => (1, 0),
+            OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::JumpIfFalse
+            | OpCode::ConstOp => (1, 1),
+            OpCode::SetProperty => (2, 1),
+            OpCode::EqualEqual
+            | OpCode::BangEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Mode
+            | OpCode::Power => (2, 1),
+            OpCode::Jump => (0, 0),
+            OpCode::Call => {
+                let name = self.string_constant(ins.offset, ins.op, ins.operand)?;
+                let arity = self
+                    .function_manager
+                    .get(name)
+                    .map(|f| f.arity())
+                    .unwrap_or(0);
+                (arity, 1)
+            }
+            _ => return Err(self.error(ins.offset, &format!("Unexpected {:?}", ins.op))),
+        };
+        Ok(effect)
+    }
+
+    fn jump_target(
+        &self,
+        block: &[Instruction],
+        positions: &HashMap<usize, usize>,
+        ins: &Instruction,
+    ) -> RspResult<usize> {
+        let target = ins.next + ins.operand as usize;
+        match positions.get(&target) {
+            Some(&i) if block[i].op != OpCode::Begin => Ok(i),
+            _ => Err(self.error(ins.offset, &format!("Invalid jump target: {}", target))),
+        }
+    }
+
+    fn constant(&self, offset: usize, operand: i32) -> RspResult<&Value> {
+        let size = self.pool.all().len();
+        if operand < 0 || operand as usize >= size {
+            return Err(self.error(
+                offset,
+                &format!(
+                    "Constant index {} out of range, pool size: {}",
+                    operand, size
+                ),
+            ));
+        }
+        self.pool.read_const(operand as usize)
+    }
+
+    fn string_constant(&self, offset: usize, op: OpCode, operand: i32) -> RspResult<&str> {
+        match self.constant(offset, operand)? {
+            Value::String(s) => Ok(s),
+            v => Err(self.error(
+                offset,
+                &format!("Operand of {:?} must be a string constant, got: {}", op, v),
+            )),
+        }
+    }
+
+    fn error(&self, offset: usize, message: &str) -> RspError {
+        RspError::VerifyError {
+            offset,
+            message: message.to_string(),
+        }
+    }
+}
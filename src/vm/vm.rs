@@ -1,43 +1,138 @@
+use std::time::Instant;
+
 use crate::{
     RspResult,
-    chunk::{Chunk, ChunkReader},
-    environment::{DefaultEnvironment, Environment},
-    error::RspError,
+    chunk::{Chunk, ChunkReader, CodeBlock, ConstantPool, Program, SourceMap},
+    environment::{DefaultEnvironment, Environment, SlotEnvironment},
+    error::{ErrorKind, RspError},
     functions::FunctionManager,
+    limits::{Budget, Limits},
+    parallel,
     parser::TokenType,
     values::{Value, value_helper},
     vm::OpCode,
 };
 
+/// 全局变量的读写方式：按名称读写 [`Environment`]，或者按槽位读写 [`SlotEnvironment`]。
+/// `index` 为变量名在常量池中的下标
+trait Globals {
+    fn get_global(&self, index: usize, name: &str) -> Option<&Value>;
+    fn put_global(&mut self, index: usize, name: &str, value: Value) -> bool;
+}
+
+impl<E: Environment> Globals for E {
+    fn get_global(&self, _index: usize, name: &str) -> Option<&Value> {
+        self.get(name)
+    }
+
+    fn put_global(&mut self, _index: usize, name: &str, value: Value) -> bool {
+        self.put(name.to_string(), value)
+    }
+}
+
+struct SlotGlobals<'a, S: SlotEnvironment> {
+    slots: &'a [Option<usize>],
+    env: &'a mut S,
+}
+
+impl<'a, S: SlotEnvironment> Globals for SlotGlobals<'a, S> {
+    fn get_global(&self, index: usize, _name: &str) -> Option<&Value> {
+        let slot = self.slots.get(index).copied().flatten()?;
+        self.env.get_slot(slot)
+    }
+
+    fn put_global(&mut self, index: usize, _name: &str, value: Value) -> bool {
+        match self.slots.get(index).copied().flatten() {
+            Some(slot) => self.env.put_slot(slot, value),
+            None => false,
+        }
+    }
+}
+
 pub struct ExResult {
     pub result: Value,
     pub index: i32,
 }
 
+/// 引起错误的部分：整条指令对应的表达式，或者二元运算、赋值、属性访问的左右操作数
+#[derive(Clone, Copy, Default)]
+pub(crate) enum Culprit {
+    #[default]
+    Whole,
+    Left,
+    Right,
+}
+
+/// 正在执行的指令的起始偏移和引起错误的部分，出错时据此在源码映射中查找位置
+#[derive(Default)]
+pub(crate) struct Trace {
+    pub start: usize,
+    pub culprit: Culprit,
+}
+
+impl Trace {
+    /// 为运行时错误补上源码位置，没有源码映射或者找不到对应的指令时原样返回
+    pub fn locate(&self, error: RspError, source_map: Option<&SourceMap>) -> RspError {
+        let Some(entry) = source_map.and_then(|map| map.find(self.start)) else {
+            return error;
+        };
+        let span = match (self.culprit, entry.operands) {
+            (Culprit::Left, Some((left, _))) if left.is_known() => left,
+            (Culprit::Right, Some((_, right))) if right.is_known() => right,
+            _ => entry.span,
+        };
+        error.with_span(span)
+    }
+}
+
 pub struct VM {
     stack: Vec<Value>,
     function_manager: FunctionManager,
+    limits: Limits,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
-    const STACK_MAX: usize = 256;
+    pub(crate) const STACK_MAX: usize = Limits::DEFAULT_STACK;
 
     pub fn new() -> Self {
+        Self::with_limits(Limits::new())
+    }
+
+    /// 按 `limits` 限制每次执行的指令数、栈深度、字符串长度和执行时间
+    pub fn with_limits(limits: Limits) -> Self {
         Self {
-            stack: Vec::with_capacity(Self::STACK_MAX),
+            stack: Vec::with_capacity(limits.max_stack.min(Self::STACK_MAX)),
             function_manager: FunctionManager::new(),
+            limits,
         }
     }
 
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
     fn reset(&mut self) {
         self.stack.clear();
     }
 
-    fn push(&mut self, value: Value) {
-        if self.stack.len() >= Self::STACK_MAX {
-            panic!("Stack overflow");
+    fn push(&mut self, value: Value) -> RspResult<()> {
+        if self.stack.len() >= self.limits.max_stack {
+            return Err(RspError::StackOverflow {
+                limit: self.limits.max_stack,
+            });
         }
         self.stack.push(value);
+        Ok(())
     }
 
     fn pop(&mut self) -> Value {
@@ -57,9 +152,8 @@ impl VM {
     }
 
     pub fn execute(&mut self, chunk: &Chunk) -> RspResult<Vec<ExResult>> {
-        let mut reader = ChunkReader::new(&chunk.codes, &chunk.constants, &chunk.vars);
         let mut env = DefaultEnvironment::new();
-        self.run(&mut reader, &mut env)
+        self.execute_with_env(chunk, &mut env)
     }
 
     pub fn execute_reader(&mut self, reader: &mut ChunkReader) -> RspResult<Vec<ExResult>> {
@@ -68,12 +162,19 @@ impl VM {
         self.run(reader, &mut env)
     }
 
+    /// 直接执行字节码，只解码常量池和调试信息，不复制字节码。
+    /// 同一份字节码需要反复执行时，先加载为 [`Program`] 再用 `execute_program` 执行
     pub fn execute_with_env<E: Environment>(
         &mut self,
         chunk: &Chunk,
         env: &mut E,
     ) -> RspResult<Vec<ExResult>> {
-        let mut reader = ChunkReader::new(&chunk.codes, &chunk.constants, &chunk.vars);
+        let pool = ConstantPool::from_bytes(&chunk.constants)?;
+        let source_map = chunk.source_map()?;
+        let mut reader = ChunkReader::new(&chunk.codes, &pool);
+        if let Some(source_map) = &source_map {
+            reader = reader.with_source_map(source_map);
+        }
         self.run(&mut reader, env)
     }
 
@@ -85,20 +186,183 @@ impl VM {
         self.run(reader, env)
     }
 
-    fn run<E: Environment>(
+    /// 执行已加载的字节码。同一个 VM 可以反复执行，栈空间会被复用
+    pub fn execute_program<E: Environment>(
+        &mut self,
+        program: &Program,
+        env: &mut E,
+    ) -> RspResult<Vec<ExResult>> {
+        self.run(&mut program.reader(), env)
+    }
+
+    /// 按槽位读写全局变量执行已加载的字节码，槽位通过 [`Program::slot`] 查询
+    pub fn execute_program_slots<S: SlotEnvironment>(
+        &mut self,
+        program: &Program,
+        env: &mut S,
+    ) -> RspResult<Vec<ExResult>> {
+        let mut globals = SlotGlobals {
+            slots: program.slots(),
+            env,
+        };
+        self.run(&mut program.reader(), &mut globals)
+    }
+
+    /// 按公式块之间的依赖关系分层，每一层的公式块分配到多个线程上并行执行
+    pub fn execute_parallel_with_env<E: Environment + Sync>(
+        chunk: &Chunk,
+        env: &mut E,
+        threads: usize,
+    ) -> RspResult<Vec<ExResult>> {
+        let program = Program::new(chunk)?;
+        Self::execute_program_parallel(&program, env, threads)
+    }
+
+    pub fn execute_program_parallel<E: Environment + Sync>(
+        program: &Program,
+        env: &mut E,
+        threads: usize,
+    ) -> RspResult<Vec<ExResult>> {
+        Self::execute_program_parallel_with_limits(program, env, threads, &Limits::new())
+    }
+
+    /// 带资源限制的并行执行。所有工作线程从同一时刻开始计时，指令数按线程分别计算
+    pub fn execute_program_parallel_with_limits<E: Environment + Sync>(
+        program: &Program,
+        env: &mut E,
+        threads: usize,
+        limits: &Limits,
+    ) -> RspResult<Vec<ExResult>> {
+        let start = Instant::now();
+        let results = parallel::run_levels(
+            &program.levels(),
+            env,
+            threads,
+            || {
+                let budget = Budget::started_at(limits, start);
+                (VM::with_limits(limits.clone()), program.reader(), budget)
+            },
+            |(vm, reader, budget), block, env| {
+                let res = vm.run_block_with(reader, env, block, budget)?;
+                Ok((res.index as usize, res.result))
+            },
+        )?;
+        Ok(results
+            .into_iter()
+            .map(|(index, result)| ExResult {
+                result,
+                index: index as i32,
+            })
+            .collect())
+    }
+
+    /// 只执行计算 `targets` 中变量所需的公式块，跳过无关的 `Begin`/`End` 块
+    pub fn execute_targets_with_env<E: Environment>(
+        &mut self,
+        chunk: &Chunk,
+        targets: &[&str],
+        env: &mut E,
+    ) -> RspResult<Vec<ExResult>> {
+        let program = Program::new(chunk)?;
+        self.execute_program_targets(&program, targets, env)
+    }
+
+    pub fn execute_program_targets<E: Environment>(
+        &mut self,
+        program: &Program,
+        targets: &[&str],
+        env: &mut E,
+    ) -> RspResult<Vec<ExResult>> {
+        let mut reader = program.reader();
+        let mut result = Vec::new();
+        let mut budget = Budget::new(&self.limits);
+        for block in program.target_blocks(targets) {
+            result.push(self.run_block_with(&mut reader, env, block, &mut budget)?);
+        }
+        Ok(result)
+    }
+
+    /// 只执行单个公式块，执行到该块的 `End` 指令为止
+    pub fn run_block<E: Environment>(
         &mut self,
         reader: &mut ChunkReader,
         env: &mut E,
+        block: &CodeBlock,
+    ) -> RspResult<ExResult> {
+        let mut budget = Budget::new(&self.limits);
+        self.run_block_with(reader, env, block, &mut budget)
+    }
+
+    /// 执行单个公式块，消耗的资源计入 `budget`
+    pub(crate) fn run_block_with<E: Environment>(
+        &mut self,
+        reader: &mut ChunkReader,
+        env: &mut E,
+        block: &CodeBlock,
+        budget: &mut Budget,
+    ) -> RspResult<ExResult> {
+        self.reset();
+        budget.check()?;
+        reader.new_position(block.start);
+        let mut result = Vec::with_capacity(1);
+        self.dispatch(reader, env, &mut result, true, budget)?;
+        // 跳转到其它公式块时 `Begin` 会改变序号，结果仍然记在本块的序号上
+        result
+            .pop()
+            .map(|res| ExResult {
+                index: block.index,
+                ..res
+            })
+            .ok_or_else(|| RspError::RuntimeError {
+                message: format!("Block not terminated, order: {}", block.index),
+                span: None,
+                kind: ErrorKind::Other,
+            })
+    }
+
+    fn run<G: Globals>(
+        &mut self,
+        reader: &mut ChunkReader,
+        env: &mut G,
     ) -> RspResult<Vec<ExResult>> {
         let mut result = Vec::new();
-        let mut exp_order = 0;
         self.reset();
+        let mut budget = Budget::new(&self.limits);
+        self.dispatch(reader, env, &mut result, false, &mut budget)?;
+        Ok(result)
+    }
+
+    fn dispatch<G: Globals>(
+        &mut self,
+        reader: &mut ChunkReader,
+        env: &mut G,
+        result: &mut Vec<ExResult>,
+        single_block: bool,
+        budget: &mut Budget,
+    ) -> RspResult<()> {
+        let mut trace = Trace::default();
+        self.dispatch_traced(reader, env, result, single_block, budget, &mut trace)
+            .map_err(|e| trace.locate(e, reader.source_map()))
+    }
+
+    fn dispatch_traced<G: Globals>(
+        &mut self,
+        reader: &mut ChunkReader,
+        env: &mut G,
+        result: &mut Vec<ExResult>,
+        single_block: bool,
+        budget: &mut Budget,
+        trace: &mut Trace,
+    ) -> RspResult<()> {
+        let mut exp_order = 0;
 
         loop {
-            let op = self.read_code(reader);
+            budget.step()?;
+            trace.start = reader.position();
+            let op = self.read_code(reader)?;
             match op {
                 OpCode::Begin => {
-                    exp_order = self.read_int(reader) as i32;
+                    exp_order = self.read_int(reader)?;
                 }
                 OpCode::End => {
                     let v = self.pop();
@@ -106,54 +370,62 @@ impl VM {
                         result: v,
                         index: exp_order,
                     });
+                    if single_block {
+                        return Ok(());
+                    }
                 }
                 OpCode::Constant => {
-                    let constant = self.read_constant(reader);
-                    self.push(constant.clone());
+                    let constant = self.read_constant(reader)?;
+                    self.push(constant.clone())?;
                 }
                 OpCode::Pop => {
                     self.pop();
                 }
                 OpCode::Null => {
-                    self.push(Value::Null);
+                    self.push(Value::Null)?;
                 }
                 OpCode::True => {
-                    self.push(Value::Boolean(true));
+                    self.push(Value::Boolean(true))?;
                 }
                 OpCode::False => {
-                    self.push(Value::Boolean(false));
+                    self.push(Value::Boolean(false))?;
                 }
                 OpCode::GetGlobal => {
-                    let name = self.read_str(reader);
-                    if let Some(value) = env.get(name) {
-                        self.push(value.clone());
-                    } else {
-                        return Err(RspError::RuntimeError {
-                            message: format!("Undefined variable: {}, order: {}", name, exp_order),
-                        });
-                    }
+                    let value = Self::read_global(reader, env, exp_order)?.clone();
+                    self.push(value)?;
                 }
                 OpCode::SetGlobal => {
-                    let name = self.read_str(reader);
+                    let index = self.read_int(reader)? as usize;
+                    let name = reader.read_const(index)?.as_str();
                     let value = self.peek().clone();
-                    if !env.put(name.to_string(), value) {
+                    if !env.put_global(index, name, value) {
+                        trace.culprit = Culprit::Left;
                         return Err(RspError::RuntimeError {
                             message: format!("Undefined variable: {}, order: {}", name, exp_order),
+                            span: None,
+                            kind: ErrorKind::UndefinedVariable {
+                                name: name.to_string(),
+                            },
                         });
                     }
                 }
                 OpCode::GetProperty => {
-                    let name = self.read_str(reader);
+                    let name = self.read_str(reader)?;
                     let object = self.pop();
                     if let Value::Instance(instance) = object {
-                        if let Some(value) = instance.get(&name) {
-                            self.push(value.clone());
+                        if let Some(value) = instance.get(name) {
+                            self.push(value.clone())?;
                         } else {
+                            trace.culprit = Culprit::Right;
                             return Err(RspError::RuntimeError {
                                 message: format!(
                                     "Undefined property: {}, order: {}",
                                     name, exp_order
                                 ),
+                                span: None,
+                                kind: ErrorKind::UndefinedProperty {
+                                    name: name.to_string(),
+                                },
                             });
                         }
                     } else {
@@ -162,11 +434,13 @@ impl VM {
                                 "Only instances have properties. error: {}, order: {}",
                                 name, exp_order
                             ),
+                            span: None,
+                            kind: ErrorKind::NotAnInstance,
                         });
                     }
                 }
                 OpCode::SetProperty => {
-                    let name = self.read_str(reader);
+                    let name = self.read_str(reader)?;
                     let object = self.pop();
                     if let Value::Instance(mut instance) = object {
                         let value = self.peek().clone();
@@ -177,35 +451,72 @@ impl VM {
                                 "Only instances have properties. error: {}, order: {}",
                                 name, exp_order
                             ),
+                            span: None,
+                            kind: ErrorKind::NotAnInstance,
                         });
                     }
                 }
-                OpCode::Add => self.binary_op(TokenType::Plus)?,
-                OpCode::Subtract => self.binary_op(TokenType::Minus)?,
-                OpCode::Multiply => self.binary_op(TokenType::Star)?,
-                OpCode::Divide => self.binary_op(TokenType::Slash)?,
-                OpCode::Mode => self.binary_op(TokenType::Percent)?,
-                OpCode::Power => self.binary_op(TokenType::StarStar)?,
-                OpCode::Greater => self.binary_op(TokenType::Greater)?,
-                OpCode::GreaterEqual => self.binary_op(TokenType::GreaterEqual)?,
-                OpCode::Less => self.binary_op(TokenType::Less)?,
-                OpCode::LessEqual => self.binary_op(TokenType::LessEqual)?,
-                OpCode::EqualEqual => self.binary_op(TokenType::EqualEqual)?,
-                OpCode::BangEqual => self.binary_op(TokenType::BangEqual)?,
+                OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::Mode
+                | OpCode::Power
+                | OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::EqualEqual
+                | OpCode::BangEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let result = Self::binary(op, &a, &b, trace)?;
+                    budget.check_value(&result)?;
+                    self.push(result)?;
+                }
+                OpCode::GlobalGlobalOp => {
+                    let a = Self::read_global(reader, env, exp_order)
+                        .inspect_err(|_| trace.culprit = Culprit::Left)?;
+                    let b = Self::read_global(reader, env, exp_order)
+                        .inspect_err(|_| trace.culprit = Culprit::Right)?;
+                    let op = self.read_code(reader)?;
+                    let result = Self::binary(op, a, b, trace)?;
+                    budget.check_value(&result)?;
+                    self.push(result)?;
+                }
+                OpCode::GlobalConstOp => {
+                    let a = Self::read_global(reader, env, exp_order)
+                        .inspect_err(|_| trace.culprit = Culprit::Left)?;
+                    let index = reader.read_int()? as usize;
+                    let b = reader.read_const(index)?;
+                    let op = self.read_code(reader)?;
+                    let result = Self::binary(op, a, b, trace)?;
+                    budget.check_value(&result)?;
+                    self.push(result)?;
+                }
+                OpCode::ConstOp => {
+                    let index = reader.read_int()? as usize;
+                    let b = reader.read_const(index)?;
+                    let op = self.read_code(reader)?;
+                    let a = self.pop();
+                    let result = Self::binary(op, &a, b, trace)?;
+                    budget.check_value(&result)?;
+                    self.push(result)?;
+                }
                 OpCode::Not => self.pre_unary_op(TokenType::Bang)?,
                 OpCode::Negate => self.pre_unary_op(TokenType::Minus)?,
                 OpCode::Call => {
-                    let name = self.read_str(reader);
-                    self.call_function(&name)?;
+                    let name = self.read_str(reader)?;
+                    self.call_function(name, budget)?;
                 }
                 OpCode::JumpIfFalse => {
-                    let offset = self.read_int(reader) as usize;
+                    let offset = self.read_int(reader)? as usize;
                     if !self.peek().is_truthy() {
                         self.goto_offset(reader, offset);
                     }
                 }
                 OpCode::Jump => {
-                    let offset = self.read_int(reader) as usize;
+                    let offset = self.read_int(reader)? as usize;
                     self.goto_offset(reader, offset);
                 }
                 OpCode::Return => {
@@ -218,25 +529,33 @@ impl VM {
                                 "VM state error, stack not empty: {}",
                                 self.stack.len()
                             ),
+                            span: None,
+                            kind: ErrorKind::Other,
                         });
                     }
-                    return Ok(result);
+                    return Ok(());
                 }
                 _ => {
                     return Err(RspError::RuntimeError {
                         message: format!("Unknown instruction: {:?}, order: {}", op, exp_order),
+                        span: None,
+                        kind: ErrorKind::Other,
                     });
                 }
             }
         }
     }
 
-    fn call_function(&mut self, name: &str) -> RspResult<()> {
+    fn call_function(&mut self, name: &str, budget: &Budget) -> RspResult<()> {
         let arity = if let Some(function) = self.function_manager.get(name) {
             function.arity()
         } else {
             return Err(RspError::RuntimeError {
                 message: format!("Undefined function: {}", name),
+                span: None,
+                kind: ErrorKind::UndefinedFunction {
+                    name: name.to_string(),
+                },
             });
         };
 
@@ -247,48 +566,98 @@ impl VM {
         arguments.reverse(); // Arguments are pushed in reverse order
 
         if let Some(function) = self.function_manager.get(name) {
-            let result = function.call(arguments);
-            self.push(result);
+            let result = function.call(arguments)?;
+            budget.check_value(&result)?;
+            self.push(result)?;
         }
         RspResult::Ok(())
     }
 
-    fn binary_op(&mut self, op_type: TokenType) -> RspResult<()> {
-        let b = self.pop();
-        let a = self.pop();
-        let result = value_helper::evaluate_binary(&a, &b, &op_type)?;
-        self.push(result);
-        RspResult::Ok(())
+    fn binary(op: OpCode, a: &Value, b: &Value, trace: &mut Trace) -> RspResult<Value> {
+        let token = Self::binary_token(op)?;
+        value_helper::evaluate_binary(a, b, &token)
+            .inspect_err(|_| trace.culprit = Self::binary_culprit(&token, b))
+    }
+
+    /// 整数除以 0 时指向除数，与语法树解释执行一致，见 [`value_helper::binary_error_span`]
+    pub(crate) fn binary_culprit(token: &TokenType, b: &Value) -> Culprit {
+        if value_helper::is_zero_divisor(token, b) {
+            Culprit::Right
+        } else {
+            Culprit::Whole
+        }
+    }
+
+    pub(crate) fn binary_token(op: OpCode) -> RspResult<TokenType> {
+        let token = match op {
+            OpCode::Add => TokenType::Plus,
+            OpCode::Subtract => TokenType::Minus,
+            OpCode::Multiply => TokenType::Star,
+            OpCode::Divide => TokenType::Slash,
+            OpCode::Mode => TokenType::Percent,
+            OpCode::Power => TokenType::StarStar,
+            OpCode::Greater => TokenType::Greater,
+            OpCode::GreaterEqual => TokenType::GreaterEqual,
+            OpCode::Less => TokenType::Less,
+            OpCode::LessEqual => TokenType::LessEqual,
+            OpCode::EqualEqual => TokenType::EqualEqual,
+            OpCode::BangEqual => TokenType::BangEqual,
+            _ => {
+                return Err(RspError::RuntimeError {
+                    message: format!("Invalid binary operator: {:?}", op),
+                    span: None,
+                    kind: ErrorKind::Other,
+                });
+            }
+        };
+        Ok(token)
+    }
+
+    /// 读取变量名操作数并取出变量的值
+    fn read_global<'g, G: Globals>(
+        reader: &mut ChunkReader,
+        env: &'g G,
+        exp_order: i32,
+    ) -> RspResult<&'g Value> {
+        let index = reader.read_int()? as usize;
+        let name = reader.read_const(index)?.as_str();
+        env.get_global(index, name)
+            .ok_or_else(|| RspError::RuntimeError {
+                message: format!("Undefined variable: {}, order: {}", name, exp_order),
+                span: None,
+                kind: ErrorKind::UndefinedVariable {
+                    name: name.to_string(),
+                },
+            })
     }
 
     fn pre_unary_op(&mut self, op_type: TokenType) -> RspResult<()> {
         let operand = self.pop();
         let result = value_helper::evaluate_unary(&operand, &op_type)?;
-        self.push(result);
+        self.push(result)?;
         RspResult::Ok(())
     }
 
-    fn read_str<'a>(&mut self, reader: &'a mut ChunkReader) -> &'a str {
-        let value = self.read_constant(reader);
-        value.as_str()
+    fn read_str<'a>(&mut self, reader: &'a mut ChunkReader) -> RspResult<&'a str> {
+        let value = self.read_constant(reader)?;
+        Ok(value.as_str())
     }
 
-    fn read_constant<'a>(&mut self, reader: &'a mut ChunkReader) -> &'a Value {
-        let index = reader.read_int() as usize;
+    fn read_constant<'a>(&mut self, reader: &'a mut ChunkReader) -> RspResult<&'a Value> {
+        let index = reader.read_int()? as usize;
         reader.read_const(index)
     }
 
-    fn read_code(&mut self, reader: &mut ChunkReader) -> OpCode {
-        let code = reader.read_byte();
-        OpCode::from(code)
+    fn read_code(&mut self, reader: &mut ChunkReader) -> RspResult<OpCode> {
+        reader.read_opcode()
     }
 
-    fn read_int(&mut self, reader: &mut ChunkReader) -> i32 {
+    fn read_int(&mut self, reader: &mut ChunkReader) -> RspResult<i32> {
         reader.read_int()
     }
 
     fn goto_offset(&mut self, reader: &mut ChunkReader, offset: usize) {
         let cur_pos = reader.position();
-        reader.new_position(cur_pos + offset);
+        reader.new_position(cur_pos.saturating_add(offset));
     }
 }
@@ -0,0 +1,145 @@
+use rspression::chunk::{assemble, disassemble};
+use rspression::vm::{OpCode, VM, Verifier};
+use rspression::{DefaultEnvironment, Environment, RspError, RspRunner, Value};
+
+#[test]
+fn test_round_trip() {
+    let srcs = [
+        "x = a * 2.5 + 1",
+        "y = x > 3 && b || !c",
+        "z = abs(a - x) + \"a;b\"",
+    ];
+    let chunk = RspRunner::new().compile_source(&srcs).unwrap();
+    let text = disassemble(&chunk).unwrap();
+    let assembled = assemble(&text).unwrap();
+    assert_eq!(chunk.codes, assembled.codes);
+    assert_eq!(chunk.constants, assembled.constants);
+    assert_eq!(chunk.vars, assembled.vars);
+}
+
+#[test]
+fn test_opcode_names() {
+    // 除 `Unknown` 外的每条指令都能按 `Debug` 输出的名称找回
+    for op in (0..=u8::MAX).map(OpCode::from) {
+        let found = OpCode::from_name(&format!("{:?}", op));
+        if op == OpCode::Unknown {
+            assert_eq!(None, found);
+        } else {
+            assert_eq!(Some(op), found);
+        }
+    }
+    assert_eq!(None, OpCode::from_name("getglobal"));
+}
+
+#[test]
+fn test_labels_and_constants() {
+    let source = r#"
+        .const limit 10
+        .code
+            Begin 0
+            GetGlobal "n"
+            Constant limit
+            Greater
+            JumpIfFalse small      ; n > 10 ? "big" : "small"
+            Pop
+            Constant "big"
+            Jump done
+        small:
+            Pop
+            Constant "small"
+        done:
+            End
+            Exit
+    "#;
+    let chunk = assemble(source).unwrap();
+    Verifier::verify(&chunk).unwrap();
+    let text = disassemble(&chunk).unwrap();
+    assert!(
+        text.contains("JumpIfFalse    11      ; -> 0032"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("Jump           6       ; -> 0038"),
+        "{}",
+        text
+    );
+
+    let mut vm = VM::new();
+    for (n, expected) in [(20, "big"), (3, "small")] {
+        let mut env = DefaultEnvironment::new();
+        env.put("n".to_string(), Value::Integer(n));
+        let results = vm.execute_with_env(&chunk, &mut env).unwrap();
+        assert_eq!(Value::String(expected.to_string()), results[0].result);
+    }
+}
+
+#[test]
+fn test_stack_balance() {
+    let source = "
+        Begin 0
+        True
+        True
+        End
+        Exit
+    ";
+    let chunk = assemble(source).unwrap();
+    match Verifier::verify(&chunk) {
+        Err(RspError::VerifyError { message, .. }) => {
+            assert!(message.contains("Stack depth at End should be 1"))
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
+#[test]
+fn test_exit_stack_not_empty() {
+    let source = "
+        True
+        Exit
+    ";
+    let chunk = assemble(source).unwrap();
+    let mut runner = RspRunner::new();
+    runner.set_verify_chunk(false);
+    let mut env = DefaultEnvironment::new();
+    match runner.run_chunk(&chunk, &mut env) {
+        Err(RspError::RuntimeError { message, .. }) => {
+            assert!(message.contains("stack not empty: 1"), "{}", message)
+        }
+        other => panic!("unexpected: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_raw_bytes() {
+    let chunk = assemble("Begin 0\n.byte 200\nEnd\nExit").unwrap();
+    assert_eq!(200, chunk.codes[5]);
+    assert_eq!(OpCode::End as u8, chunk.codes[6]);
+    assert!(
+        disassemble(&chunk)
+            .unwrap()
+            .contains("<invalid instruction 0xc8>")
+    );
+}
+
+#[test]
+fn test_errors() {
+    let cases = [
+        ("Jump nowhere\nExit", 1, "Undefined label: nowhere"),
+        ("Exit\nFoo", 2, "Unknown instruction: Foo"),
+        ("Add 1", 1, "Add takes 0 operands"),
+        ("ConstOp 1 Not", 1, "Invalid operator: Not"),
+        ("Constant missing", 1, "Undefined constant: missing"),
+        ("a:\na:", 2, "Duplicate label: a"),
+        ("Constant \"open", 1, "Unterminated string"),
+    ];
+    for (source, expected_line, fragment) in cases {
+        match assemble(source) {
+            Err(RspError::ParseError { span, message, .. }) => {
+                assert_eq!(expected_line, span.line, "{}", source);
+                assert!(message.contains(fragment), "{}", message);
+            }
+            other => panic!("unexpected: {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use rspression::{
+    Batch, Column, DefaultEnvironment, Environment, ExecuteMode, RspError, RspRunner, Value,
+};
+
+const ROWS: usize = 64;
+
+fn get_batch() -> Batch {
+    let mut batch = Batch::new(ROWS);
+    let a: Vec<i32> = (0..ROWS as i32).map(|i| i - 20).collect();
+    let b: Vec<f64> = (0..ROWS).map(|i| i as f64 * 0.5 + 1.0).collect();
+    let c: Vec<i32> = (0..ROWS as i32).map(|i| i % 7 + 1).collect();
+    let flag: Vec<bool> = (0..ROWS).map(|i| i % 3 == 0).collect();
+    let name: Vec<Value> = (0..ROWS)
+        .map(|i| Value::String(format!("n{}", i)))
+        .collect();
+    batch.set_column("a", a).unwrap();
+    batch.set_column("b", b).unwrap();
+    batch.set_column("c", c).unwrap();
+    batch.set_column("flag", flag).unwrap();
+    batch.set_column("name", name).unwrap();
+    batch.set_scalar("rate", Value::Double(0.25));
+    batch.set_scalar("k", Value::Integer(3));
+    batch
+}
+
+/// 逐行用字节码虚拟机执行，作为按列执行结果的参照
+fn run_rows(srcs: &[&str], batch: &Batch) -> Vec<Vec<Value>> {
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::ChunkVM);
+    let chunk = runner.compile_source(srcs).unwrap();
+    let program = runner.load_chunk(&chunk).unwrap();
+    let mut columns = vec![Vec::with_capacity(ROWS); srcs.len()];
+    for row in 0..ROWS {
+        let mut env = DefaultEnvironment::new();
+        for name in ["a", "b", "c", "flag", "name"] {
+            env.put(name.to_string(), batch.column(name).unwrap().get(row));
+        }
+        for name in ["rate", "k"] {
+            env.put(name.to_string(), batch.scalar(name).unwrap().clone());
+        }
+        let values = runner.run_program(&program, &mut env).unwrap();
+        for (i, v) in values.into_iter().enumerate() {
+            columns[i].push(v);
+        }
+    }
+    columns
+}
+
+fn assert_same(srcs: &[&str]) {
+    let mut batch = get_batch();
+    let expected = run_rows(srcs, &batch);
+    let mut runner = RspRunner::new();
+    let columns = runner.execute_batch(srcs, &mut batch).unwrap();
+    assert_eq!(srcs.len(), columns.len());
+    for (src, (column, expected)) in srcs.iter().zip(columns.iter().zip(&expected)) {
+        assert_eq!(ROWS, column.len(), "{}", src);
+        assert_eq!(&column.to_values(), expected, "{}", src);
+    }
+}
+
+#[test]
+fn test_numeric() {
+    assert_same(&[
+        "x = a + c * k",
+        "y = b * rate - a / c",
+        "z = x % c + b ** 2",
+        "p = -a + -b",
+        "q = a * 2.5 >= b",
+        "r = y <= x",
+    ]);
+}
+
+#[test]
+fn test_mixed() {
+    assert_same(&[
+        "s = name + \"-\" + a",
+        "t = a == c",
+        "u = !flag",
+        "v = abs(a) + abs(b)",
+        "w = flag != (a > 0)",
+    ]);
+}
+
+#[test]
+fn test_fallback() {
+    // 包含跳转的公式块逐行执行，写入的变量同样按列写回
+    assert_same(&[
+        "x = if(a > 0, a, -a * 2.0)",
+        "y = flag && a",
+        "z = a > 3 || name",
+        "m = x * 2 + b",
+    ]);
+}
+
+#[test]
+fn test_column_types() {
+    let mut batch = get_batch();
+    let mut runner = RspRunner::new();
+    let columns = runner
+        .execute_batch(
+            &["x = a + c", "y = a * rate", "z = a > c", "s = name + 1"],
+            &mut batch,
+        )
+        .unwrap();
+    assert!(matches!(columns[0], Column::Integer(_)));
+    assert!(matches!(columns[1], Column::Double(_)));
+    assert!(matches!(columns[2], Column::Boolean(_)));
+    assert!(matches!(columns[3], Column::Values(_)));
+    assert_eq!(Some(&columns[0]), batch.column("x"));
+    assert_eq!(Value::Integer(-19), batch.column("x").unwrap().get(0));
+    // 原有的列保留在 batch 中
+    assert_eq!(Value::Integer(-20), batch.column("a").unwrap().get(0));
+    assert_eq!(Some(&Value::Integer(3)), batch.scalar("k"));
+}
+
+#[test]
+fn test_errors() {
+    let mut runner = RspRunner::new();
+    let mut batch = get_batch();
+    let err = runner
+        .execute_batch(&["a / (c - 3)"], &mut batch)
+        .unwrap_err();
+    assert!(
+        matches!(&err, RspError::RuntimeError { message, .. } if message == "Division by zero, row: 2"),
+        "{}",
+        err
+    );
+    let err = runner
+        .execute_batch(&["a + missing"], &mut batch)
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Undefined variable: missing"),
+        "{}",
+        err
+    );
+    // 出错后原有的列仍然保留
+    assert_eq!(ROWS, batch.column("a").unwrap().len());
+
+    let err = batch.set_column("a", vec![1, 2, 3]).unwrap_err();
+    assert!(err.to_string().contains("expected 64"), "{}", err);
+}
@@ -42,10 +42,7 @@ impl TestHelper {
     }
 
     pub fn read_chunk_file(file_path: &Path) -> Option<Chunk> {
-        if let Ok(bytes) = fs::read(file_path) {
-            Some(Chunk::from_bytes(&bytes))
-        } else {
-            None
-        }
+        let bytes = fs::read(file_path).ok()?;
+        Chunk::from_bytes(&bytes).ok()
     }
 }
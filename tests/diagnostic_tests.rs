@@ -0,0 +1,200 @@
+use rspression::diagnostic::suggest;
+use rspression::functions::FunctionManager;
+use rspression::vm::VM;
+use rspression::{
+    DefaultEnvironment, Diagnostic, Environment, ErrorCode, ErrorKind, Parser, RspError, RspRunner,
+    Value,
+};
+
+fn get_env() -> DefaultEnvironment {
+    let mut env = DefaultEnvironment::new();
+    env.put("total".to_string(), Value::Integer(6));
+    env.put("count".to_string(), Value::Integer(0));
+    env.put("price".to_string(), Value::Double(1.5));
+    env
+}
+
+/// 编译后直接交给虚拟机执行，不经过运行器对未定义变量的处理
+fn vm_error(src: &str) -> RspError {
+    let chunk = RspRunner::new().compile_source(&[src]).unwrap();
+    let Err(err) = VM::new().execute_with_env(&chunk, &mut get_env()) else {
+        panic!("expected error: {}", src);
+    };
+    err
+}
+
+fn note(code: ErrorCode) -> String {
+    format!("  = note: {}\n", code.explanation())
+}
+
+#[test]
+fn test_undefined_variable() {
+    let src = "c = totl * 2";
+    let env = get_env();
+    let diagnostic =
+        Diagnostic::new(&vm_error(src)).with_suggestions(&env, &FunctionManager::new(), &[src]);
+    assert_eq!(ErrorCode::UndefinedVariable, diagnostic.code);
+    assert_eq!(vec!["total"], diagnostic.suggestions);
+    let expected = "error[E0102]: undefined variable
+ --> line 1, column 5
+  |
+1 | c = totl * 2
+  |     ^^^^ Undefined variable: totl, order: 0
+  |
+  = help: did you mean `total`?
+"
+    .to_string()
+        + &note(ErrorCode::UndefinedVariable);
+    assert_eq!(expected, diagnostic.render(src));
+}
+
+#[test]
+fn test_suggest_assigned_variable() {
+    // 由其它公式赋值的变量也作为候选，属性赋值和无法解析的公式被忽略
+    let srcs = [
+        "subtotal = total * 2",
+        "c = subtotl + 1",
+        "total.p = 1",
+        "d = (",
+    ];
+    let err = vm_error(srcs[1]);
+    assert_eq!(
+        Some(&ErrorKind::UndefinedVariable {
+            name: "subtotl".to_string()
+        }),
+        err.kind()
+    );
+    let diagnostic =
+        Diagnostic::new(&err).with_suggestions(&get_env(), &FunctionManager::new(), &srcs);
+    assert_eq!(vec!["subtotal"], diagnostic.suggestions);
+}
+
+#[test]
+fn test_undefined_function() {
+    let src = "x = ab(total) + clok()";
+    let err = RspRunner::new().compile_source(&[src]).unwrap_err();
+    let diagnostic =
+        Diagnostic::new(&err).with_suggestions(&get_env(), &FunctionManager::new(), &[src]);
+    assert_eq!("E0103", diagnostic.code.code());
+    assert_eq!(vec!["abs"], diagnostic.suggestions);
+    assert_eq!(
+        Some(ErrorKind::UndefinedFunction {
+            name: "ab".to_string()
+        }),
+        diagnostic.kind
+    );
+    assert!(
+        diagnostic
+            .render(src)
+            .contains("1 | x = ab(total) + clok()\n  |     ^^ Undefined function: ab\n")
+    );
+}
+
+#[test]
+fn test_error_codes() {
+    let cases = [
+        ("a = 1 +", ErrorCode::Syntax),
+        ("a = 1 $ 2", ErrorCode::UnexpectedCharacter),
+        ("a = \"abc", ErrorCode::UnterminatedString),
+        ("c = total / count", ErrorCode::DivisionByZero),
+        ("c = -\"s\"", ErrorCode::TypeMismatch),
+        ("c = abs(1, 2)", ErrorCode::ArityMismatch),
+        ("c = total.p", ErrorCode::NotAnInstance),
+        ("c = 1(2)", ErrorCode::InvalidCall),
+    ];
+    for (src, code) in cases {
+        let err = match Parser::new(src).parse() {
+            Err(err) => err,
+            Ok(_) => match RspRunner::new().compile_source(&[src]) {
+                Err(err) => err,
+                Ok(_) => vm_error(src),
+            },
+        };
+        assert_eq!(code, Diagnostic::new(&err).code, "{}: {}", src, err);
+    }
+    assert_eq!(
+        ErrorCode::Cancelled,
+        Diagnostic::new(&RspError::Cancelled).code
+    );
+}
+
+#[test]
+fn test_render_position() {
+    // 末尾缺少右括号，位置长度为 0，标记一个字符
+    let src = "x = (1 +\r\n  2 *\r\n  3";
+    let err = Parser::new(src).parse().err().unwrap();
+    let text = Diagnostic::new(&err).render(src);
+    assert!(
+        text.starts_with("error[E0001]: syntax error\n --> line 3, column 4\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("\n3 |   3\n  |    ^ Expected ')'"),
+        "{}",
+        text
+    );
+
+    // 中文按两列宽对齐，制表符原样保留
+    let src = "\t合计 = 单价 * 数量 $";
+    let err = Parser::new(src).parse().err().unwrap();
+    let text = Diagnostic::new(&err).render(src);
+    assert!(
+        text.contains("1 | \t合计 = 单价 * 数量 $\n  | \t                   ^ "),
+        "{}",
+        text
+    );
+
+    // 没有位置或者位置超出源码时只显示错误信息
+    let text = Diagnostic::new(&RspError::Cancelled).render("");
+    assert_eq!(
+        "error[E0405]: cancelled\n  = Execution cancelled\n".to_string()
+            + &note(ErrorCode::Cancelled),
+        text
+    );
+    let text = Diagnostic::new(&vm_error("c = totl")).render("");
+    assert!(
+        text.contains(" --> line 1, column 5\n  = Undefined variable"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn test_render_json() {
+    let src = "c = \"a\tb\" + totl";
+    let err = vm_error(src);
+    let diagnostic =
+        Diagnostic::new(&err).with_suggestions(&get_env(), &FunctionManager::new(), &[src]);
+    let expected = format!(
+        "{{\"code\":\"E0102\",\"title\":\"undefined variable\",\
+         \"message\":\"Undefined variable: totl, order: 0\",\"explanation\":\"{}\",\
+         \"span\":{{\"offset\":12,\"len\":4,\"line\":1,\"column\":13}},\
+         \"source_line\":\"c = \\\"a\\tb\\\" + totl\",\"suggestions\":[\"total\"]}}",
+        ErrorCode::UndefinedVariable.explanation()
+    );
+    assert_eq!(expected, diagnostic.render_json(src));
+
+    let json = Diagnostic::new(&RspError::Cancelled).render_json("");
+    assert!(
+        json.contains("\"span\":null,\"source_line\":null,\"suggestions\":[]"),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn test_suggest() {
+    let names = [
+        "price", "prices", "Total", "total", "count", "amount", "价格",
+    ];
+    assert_eq!(vec!["price"], suggest("pirce", names));
+    assert_eq!(vec!["price", "prices"], suggest("prics", names));
+    assert_eq!(vec!["Total", "total"], suggest("TOTAL", names));
+    // 与已有名称完全相同时不给出建议
+    assert_eq!(vec!["prices"], suggest("price", names));
+    assert_eq!(vec!["count"], suggest("cont", names));
+    assert_eq!(vec!["价格"], suggest("价钱", names));
+    assert!(suggest("x", names).is_empty());
+    assert!(suggest("quantity", names).is_empty());
+}
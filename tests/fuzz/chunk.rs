@@ -0,0 +1,62 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use rspression::vm::VM;
+use rspression::{Chunk, ExecuteMode, RspRunner};
+
+use crate::{corpus, get_env, iterations, mutate_bytes, rng, run_case};
+
+/// 读取字节码并直接交给虚拟机执行，不经过校验。读取和执行都可以出错，但不能 panic
+fn check(bytes: &[u8]) {
+    let Ok(chunk) = Chunk::from_bytes(bytes) else {
+        return;
+    };
+    let _ = VM::new().execute_with_env(&chunk, &mut get_env());
+    let mut runner = RspRunner::new();
+    let _ = runner.run_chunk(&chunk, &mut get_env());
+    runner.set_verify_chunk(false);
+    let _ = runner.run_chunk(&chunk, &mut get_env());
+    if let Ok(program) = runner.load_chunk(&chunk) {
+        let _ = runner.run_program(&program, &mut get_env());
+    }
+}
+
+/// 编译种子表达式得到的字节码，作为变异的起点
+fn seed_chunks() -> Vec<Chunk> {
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::ChunkVM);
+    corpus()
+        .iter()
+        .filter_map(|src| runner.compile_source(&[src]).ok())
+        .collect()
+}
+
+/// 修改字节码中的某一段再重新打包，文件头的校验和随之更新，变异后的内容能进入执行阶段
+fn mutate_section(rng: &mut StdRng, chunk: &Chunk) -> Vec<u8> {
+    let mut chunk = chunk.clone();
+    match rng.random_range(0..5) {
+        0 | 1 => chunk.codes = mutate_bytes(rng, &chunk.codes),
+        2 => chunk.constants = mutate_bytes(rng, &chunk.constants),
+        3 => chunk.vars = mutate_bytes(rng, &chunk.vars),
+        _ => chunk.debug = mutate_bytes(rng, &chunk.debug),
+    }
+    chunk.to_bytes()
+}
+
+#[test]
+fn fuzz_chunk() {
+    let chunks = seed_chunks();
+    assert!(!chunks.is_empty());
+    let (seed, mut rng) = rng("chunk");
+    for iter in 0..iterations() {
+        let chunk = &chunks[rng.random_range(0..chunks.len())];
+        let bytes = match rng.random_range(0..4) {
+            0 => mutate_bytes(&mut rng, &chunk.to_bytes()),
+            1 => {
+                let len = rng.random_range(0..64);
+                (0..len).map(|_| rng.random()).collect()
+            }
+            _ => mutate_section(&mut rng, chunk),
+        };
+        run_case("chunk", seed, iter, &bytes, || check(&bytes));
+    }
+}
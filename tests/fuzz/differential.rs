@@ -0,0 +1,66 @@
+use rand::Rng;
+use rspression::{DefaultEnvironment, Environment, ExecuteMode, RspResult, RspRunner, Value};
+
+use crate::generator::Generator;
+use crate::{get_env, iterations, rng, run_case};
+
+fn run(mode: ExecuteMode, srcs: &[&str]) -> (RspResult<Vec<Value>>, DefaultEnvironment) {
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(mode);
+    let mut env = get_env();
+    let result = runner.execute_multiple_with_env(srcs, &mut env);
+    (result, env)
+}
+
+/// 两个 NaN 也视为相同
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Double(x), Value::Double(y)) => x == y || x.is_nan() && y.is_nan(),
+        _ => a == b,
+    }
+}
+
+/// 语法树解释执行、闭包执行与字节码执行要么都出错，要么得到相同的结果和相同的变量值
+fn check(srcs: &[&str]) {
+    let (chunk, chunk_env) = run(ExecuteMode::ChunkVM, srcs);
+    for mode in [ExecuteMode::SyntaxTree, ExecuteMode::Closure] {
+        let (other, other_env) = run(mode, srcs);
+        match (&other, &chunk) {
+            (Ok(a), Ok(b)) => {
+                assert_eq!(a.len(), b.len(), "{:?}", mode);
+                for (i, (x, y)) in a.iter().zip(b).enumerate() {
+                    assert!(
+                        same(x, y),
+                        "formula {}: {:?} {:?}, chunk {:?}",
+                        i,
+                        mode,
+                        x,
+                        y
+                    );
+                }
+                for i in 0..srcs.len() {
+                    let name = format!("v{}", i);
+                    let (x, y) = (other_env.get(&name), chunk_env.get(&name));
+                    let equal = match (x, y) {
+                        (Some(x), Some(y)) => same(x, y),
+                        (x, y) => x.is_none() && y.is_none(),
+                    };
+                    assert!(equal, "{}: {:?} {:?}, chunk {:?}", name, mode, x, y);
+                }
+            }
+            (Err(_), Err(_)) => {}
+            _ => panic!("{:?} {:?}, chunk {:?}", mode, other, chunk),
+        }
+    }
+}
+
+#[test]
+fn fuzz_differential() {
+    let (seed, mut rng) = rng("differential");
+    for iter in 0..iterations() {
+        let count = rng.random_range(1..=4);
+        let srcs = Generator::new(&mut rng).formulas(count);
+        let srcs: Vec<&str> = srcs.iter().map(String::as_str).collect();
+        run_case("differential", seed, iter, &srcs, || check(&srcs));
+    }
+}
@@ -0,0 +1,133 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+
+/// 生成表达式时期望的结果类型
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Number,
+    Boolean,
+    Any,
+}
+
+const INT_VARS: [&str; 4] = ["a", "b", "c", "m"];
+const DOUBLE_VARS: [&str; 2] = ["x", "y"];
+const LITERALS: [&str; 7] = ["0", "1", "2", "7", "2147483647", "2.5", "0.0"];
+
+/// 按语法生成能通过解析的表达式，子表达式都加括号，不依赖优先级。
+///
+/// 逻辑运算的操作数可以是任意类型，用来比较各种执行方式短路时的结果。
+/// 函数调用只生成参数个数正确的 `abs`：字节码和闭包在编译时检查函数名和参数个数，
+/// 语法树在执行到调用时才检查，不会执行的分支中的错误调用会让两者的结果不同。
+/// 少量数值操作数故意取任意类型，少量变量没有值，用来比较各种执行方式对错误的处理
+pub struct Generator<'r> {
+    rng: &'r mut StdRng,
+    /// 可以读取的赋值目标
+    assigned: Vec<String>,
+}
+
+impl<'r> Generator<'r> {
+    pub fn new(rng: &'r mut StdRng) -> Self {
+        Self {
+            rng,
+            assigned: Vec::new(),
+        }
+    }
+
+    /// 生成一组公式，部分公式给 `v0`、`v1` 等变量赋值，后面的公式可能读取这些变量
+    pub fn formulas(&mut self, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| {
+                let expr = self.expr(Kind::Any, 3);
+                if self.rng.random_bool(0.6) {
+                    let name = format!("v{}", i);
+                    self.assigned.push(name.clone());
+                    format!("{} = {}", name, expr)
+                } else {
+                    expr
+                }
+            })
+            .collect()
+    }
+
+    fn expr(&mut self, kind: Kind, depth: usize) -> String {
+        // 偶尔把数值换成任意类型，制造类型错误
+        let kind = if kind == Kind::Number && self.rng.random_bool(0.05) {
+            Kind::Any
+        } else {
+            kind
+        };
+        if depth == 0 || self.rng.random_bool(0.25) {
+            return self.leaf(kind);
+        }
+        let kind = match kind {
+            Kind::Any if self.rng.random_bool(0.5) => Kind::Number,
+            Kind::Any => Kind::Boolean,
+            kind => kind,
+        };
+        match (kind, self.rng.random_range(0..5)) {
+            (Kind::Number, 0) => format!("(-{})", self.expr(Kind::Number, depth - 1)),
+            (Kind::Number, 1) => self.if_expr(Kind::Number, depth),
+            (Kind::Number, 2) => format!("abs({})", self.expr(Kind::Number, depth - 1)),
+            (Kind::Number, _) => {
+                let op = self.pick(&["+", "-", "*", "/", "%", "**"]);
+                self.binary(Kind::Number, op, Kind::Number, depth)
+            }
+            (_, 0) => format!("(!{})", self.expr(Kind::Any, depth - 1)),
+            (_, 1) => {
+                let op = self.pick(&["<", "<=", ">", ">="]);
+                self.binary(Kind::Number, op, Kind::Number, depth)
+            }
+            (_, 2) => {
+                let op = self.pick(&["==", "!="]);
+                self.binary(Kind::Any, op, Kind::Any, depth)
+            }
+            _ => {
+                let op = self.pick(&["&&", "||"]);
+                self.binary(Kind::Any, op, Kind::Any, depth)
+            }
+        }
+    }
+
+    fn binary(&mut self, left: Kind, op: &str, right: Kind, depth: usize) -> String {
+        let l = self.expr(left, depth - 1);
+        let r = self.expr(right, depth - 1);
+        format!("({} {} {})", l, op, r)
+    }
+
+    fn if_expr(&mut self, kind: Kind, depth: usize) -> String {
+        let c = self.expr(Kind::Boolean, depth - 1);
+        let t = self.expr(kind, depth - 1);
+        let e = self.expr(kind, depth - 1);
+        format!("if({}, {}, {})", c, t, e)
+    }
+
+    fn leaf(&mut self, kind: Kind) -> String {
+        let kind = match kind {
+            Kind::Any => match self.rng.random_range(0..8) {
+                0 => return "s".to_string(),
+                1 => return self.pick(&["\"q\"", "\"\"", "null"]).to_string(),
+                2 if !self.assigned.is_empty() => {
+                    let i = self.rng.random_range(0..self.assigned.len());
+                    return self.assigned[i].clone();
+                }
+                // 没有值的变量
+                4 if self.rng.random_bool(0.3) => return "u".to_string(),
+                3 => Kind::Boolean,
+                _ => Kind::Number,
+            },
+            kind => kind,
+        };
+        if kind == Kind::Boolean {
+            return self.pick(&["true", "false", "t"]).to_string();
+        }
+        match self.rng.random_range(0..3) {
+            0 => self.pick(&INT_VARS).to_string(),
+            1 => self.pick(&DOUBLE_VARS).to_string(),
+            _ => self.pick(&LITERALS).to_string(),
+        }
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.rng.random_range(0..items.len())]
+    }
+}
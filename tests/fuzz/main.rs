@@ -0,0 +1,166 @@
+//! 模糊测试目标，作为普通的测试程序运行，不依赖网络和外部工具：
+//!
+//! ```text
+//! cargo test --test fuzz
+//! RSP_FUZZ_ITERS=200000 RSP_FUZZ_SEED=7 cargo test --release --test fuzz
+//! ```
+//!
+//! 每个目标从 `corpus.txt` 中的种子表达式出发，用固定种子的随机数生成器变异输入，
+//! 失败时输出种子、迭代序号和输入，按相同的种子重新运行即可复现。
+
+mod chunk;
+mod differential;
+mod generator;
+mod parser;
+mod pipeline;
+
+use std::fmt::Debug;
+use std::panic::{self, AssertUnwindSafe};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rspression::{DefaultEnvironment, Environment, Value};
+
+/// 从现有测试中收集的种子表达式，每行一条
+const CORPUS: &str = include_str!("corpus.txt");
+
+const DEFAULT_ITERS: usize = 2000;
+const DEFAULT_SEED: u64 = 0x5eed;
+
+/// 随机变异时插入的片段，拼接后大部分能通过词法分析
+const PIECES: [&str; 32] = [
+    "a",
+    "b",
+    "s",
+    "o.p",
+    "1",
+    "0",
+    "2.5",
+    "2147483647",
+    "-",
+    "+",
+    "*",
+    "/",
+    "%",
+    "**",
+    "(",
+    ")",
+    ",",
+    "=",
+    "==",
+    "!=",
+    "!",
+    "<",
+    ">=",
+    "&&",
+    "||",
+    "if(",
+    "abs(",
+    "\"",
+    ".",
+    "true",
+    "null",
+    " ",
+];
+
+pub fn corpus() -> Vec<&'static str> {
+    CORPUS.lines().collect()
+}
+
+pub fn iterations() -> usize {
+    std::env::var("RSP_FUZZ_ITERS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_ITERS)
+}
+
+/// 每个目标使用不同的随机序列，同一个目标在相同的种子下总是得到相同的输入
+pub fn rng(target: &str) -> (u64, StdRng) {
+    let seed = std::env::var("RSP_FUZZ_SEED")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SEED);
+    let salt = target
+        .bytes()
+        .fold(0u64, |h, b| h.wrapping_mul(31).wrapping_add(b as u64));
+    (seed, StdRng::seed_from_u64(seed ^ salt))
+}
+
+/// 执行一次迭代，panic 时报告复现所需的种子、迭代序号和输入
+pub fn run_case<I: Debug, F: FnOnce()>(target: &str, seed: u64, iter: usize, input: &I, f: F) {
+    if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+        panic!(
+            "fuzz target {} failed, RSP_FUZZ_SEED={}, iteration {}, input: {:?}",
+            target, seed, iter, input
+        );
+    }
+}
+
+pub fn get_env() -> DefaultEnvironment {
+    let mut env = DefaultEnvironment::new();
+    for (name, value) in [
+        ("a", Value::Integer(7)),
+        ("b", Value::Integer(-3)),
+        ("c", Value::Integer(0)),
+        ("m", Value::Integer(i32::MIN)),
+        ("x", Value::Double(2.5)),
+        ("y", Value::Double(-0.0)),
+        ("t", Value::Boolean(true)),
+        ("s", Value::String("str".to_string())),
+    ] {
+        env.put(name.to_string(), value);
+    }
+    env
+}
+
+/// 在种子表达式上做一到三次随机修改：插入片段或任意字符、删除、复制或拼接另一条种子
+pub fn mutate_source(rng: &mut StdRng, corpus: &[&str]) -> String {
+    let mut chars: Vec<char> = corpus[rng.random_range(0..corpus.len())].chars().collect();
+    for _ in 0..rng.random_range(1..=3) {
+        let at = rng.random_range(0..=chars.len());
+        match rng.random_range(0..6) {
+            0 => {
+                let piece = PIECES[rng.random_range(0..PIECES.len())];
+                chars.splice(at..at, piece.chars());
+            }
+            1 => chars.insert(at, rng.random()),
+            2 => {
+                let end = rng.random_range(at..=chars.len());
+                chars.drain(at..end);
+            }
+            3 => {
+                let end = rng.random_range(at..=chars.len().min(at + 8));
+                let copy: Vec<char> = chars[at..end].to_vec();
+                chars.splice(at..at, copy);
+            }
+            4 => {
+                let other = corpus[rng.random_range(0..corpus.len())];
+                chars.splice(at..at, other.chars());
+            }
+            _ => {
+                let end = rng.random_range(at..=chars.len());
+                chars.truncate(end);
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// 修改字节序列中的一到三处：改写、翻转位、插入、删除或截断
+pub fn mutate_bytes(rng: &mut StdRng, bytes: &[u8]) -> Vec<u8> {
+    let mut out = bytes.to_vec();
+    for _ in 0..rng.random_range(1..=3) {
+        let at = rng.random_range(0..=out.len());
+        match rng.random_range(0..5) {
+            0 if at < out.len() => out[at] = rng.random(),
+            1 if at < out.len() => out[at] ^= 1 << rng.random_range(0..8),
+            2 => out.insert(at, rng.random()),
+            3 if at < out.len() => {
+                out.remove(at);
+            }
+            4 => out.truncate(at),
+            _ => {}
+        }
+    }
+    out
+}
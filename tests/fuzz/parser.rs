@@ -0,0 +1,98 @@
+use rand::Rng;
+use rspression::{Diagnostic, Parser, RspError, Scanner, TokenType};
+
+use crate::{corpus, iterations, mutate_source, rng, run_case};
+
+/// 词法分析总能结束，以 `Eof` 结尾，行号不减且不超过源码的行数，位置对应的文本就是 token 的文本
+fn check_scanner(src: &str) {
+    let Ok(tokens) = Scanner::new(src).scan_tokens() else {
+        return;
+    };
+    let lines = src.matches('\n').count() + 1;
+    assert_eq!(Some(&TokenType::Eof), tokens.last().map(|t| &t.token_type));
+    let mut line = 1;
+    for token in &tokens {
+        assert!(token.line >= line && token.line <= lines, "{:?}", token);
+        line = token.line;
+        if token.token_type != TokenType::Eof && token.token_type != TokenType::String {
+            assert!(src.contains(token.lexeme.as_str()), "{:?}", token);
+        }
+        assert_eq!(Some(&*token.lexeme), token.span.slice(src), "{:?}", token);
+    }
+}
+
+fn check(src: &str) {
+    check_scanner(src);
+    // 成功与否都可以，同一段源码的解析结果必须确定，位置不超出源码
+    match Parser::new(src).parse() {
+        Ok(expr) => assert!(expr.span().slice(src).is_some()),
+        Err(err) => assert!(err.span().and_then(|s| s.slice(src)).is_some(), "{}", err),
+    }
+    check_recover(src);
+    let first = Parser::new(src).parse().map_err(|e| e.to_string());
+    let second = Parser::new(src).parse().map_err(|e| e.to_string());
+    assert_eq!(first.is_ok(), second.is_ok());
+    if let (Err(a), Err(b)) = (first, second) {
+        assert_eq!(a, b);
+    }
+}
+
+/// 容错解析没有错误时与正常解析的结果相同，有错误时包含正常解析报告的那个错误
+fn check_recover(src: &str) {
+    let partial = Parser::new(src).parse_recover();
+    assert!(partial.expr.span().slice(src).is_some());
+    for err in &partial.errors {
+        assert!(matches!(err, RspError::ParseError { .. }), "{}", err);
+        assert!(err.span().and_then(|s| s.slice(src)).is_some(), "{}", err);
+        // 诊断信息总能带上出错的源码行
+        let diagnostic = Diagnostic::new(err);
+        assert!(diagnostic.render(src).contains(" | "), "{}", err);
+        assert!(!diagnostic.render_json(src).contains("\"source_line\":null"));
+    }
+    match Parser::new(src).parse() {
+        Ok(expr) => {
+            assert!(partial.is_ok(), "{:?}", partial.errors);
+            assert!(!partial.expr.has_error());
+            assert_eq!(expr.span(), partial.expr.span());
+        }
+        Err(err) => {
+            let err = err.to_string();
+            assert!(
+                partial.errors.iter().any(|e| e.to_string() == err),
+                "{} not in {:?}",
+                err,
+                partial.errors
+            );
+        }
+    }
+}
+
+#[test]
+fn fuzz_parser_mutated_corpus() {
+    let corpus = corpus();
+    let (seed, mut rng) = rng("parser_corpus");
+    for src in &corpus {
+        run_case("parser_corpus", seed, 0, src, || check(src));
+    }
+    for iter in 0..iterations() {
+        let src = mutate_source(&mut rng, &corpus);
+        run_case("parser_corpus", seed, iter, &src, || check(&src));
+    }
+}
+
+#[test]
+fn fuzz_parser_arbitrary_utf8() {
+    let (seed, mut rng) = rng("parser_utf8");
+    for iter in 0..iterations() {
+        let len = rng.random_range(0..32);
+        // 一半只取 ASCII，更容易组成合法的词法单元
+        let src: String = if rng.random_bool(0.5) {
+            (0..len)
+                .map(|_| rng.random_range(0x20u8..0x7f) as char)
+                .collect()
+        } else {
+            (0..len).map(|_| rng.random::<char>()).collect()
+        };
+        run_case("parser_utf8", seed, iter, &src, || check(&src));
+    }
+}
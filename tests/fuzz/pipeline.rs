@@ -0,0 +1,41 @@
+use rspression::vm::{VM, Verifier};
+use rspression::{Chunk, ExecuteMode, RspRunner};
+
+use crate::{corpus, get_env, iterations, mutate_source, rng, run_case};
+
+/// 解析 -> 编译 -> 执行。编译成功的字节码必须通过校验、能原样序列化和读回，执行可以出错但不能 panic
+fn check(srcs: &[&str]) {
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::ChunkVM);
+    let Ok(chunk) = runner.compile_source(srcs) else {
+        return;
+    };
+    if let Err(e) = Verifier::verify(&chunk) {
+        panic!("compiled chunk rejected by verifier: {}", e);
+    }
+    let decoded = Chunk::from_bytes(&chunk.to_bytes()).unwrap();
+    assert_eq!(chunk.codes, decoded.codes);
+    assert_eq!(chunk.constants, decoded.constants);
+    assert_eq!(chunk.vars, decoded.vars);
+
+    let _ = VM::new().execute_with_env(&decoded, &mut get_env());
+    let _ = runner.run_chunk(&decoded, &mut get_env());
+    let _ = runner.run_chunk_parallel(&decoded, &mut get_env());
+}
+
+#[test]
+fn fuzz_pipeline() {
+    let corpus = corpus();
+    let (seed, mut rng) = rng("pipeline");
+    for src in &corpus {
+        run_case("pipeline", seed, 0, src, || check(&[src]));
+    }
+    for iter in 0..iterations() {
+        let srcs = [
+            mutate_source(&mut rng, &corpus),
+            mutate_source(&mut rng, &corpus),
+        ];
+        let srcs = [srcs[0].as_str(), srcs[1].as_str()];
+        run_case("pipeline", seed, iter, &srcs, || check(&srcs));
+    }
+}
@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use rspression::chunk::assemble;
+use rspression::{
+    Batch, CancelToken, DefaultEnvironment, Environment, ExecuteMode, Limits, RspError, RspRunner,
+    Value,
+};
+
+const MODES: [ExecuteMode; 3] = [
+    ExecuteMode::SyntaxTree,
+    ExecuteMode::ChunkVM,
+    ExecuteMode::Closure,
+];
+
+fn runner(mode: ExecuteMode, limits: Limits) -> RspRunner {
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(mode);
+    runner.set_limits(limits);
+    runner
+}
+
+fn get_env() -> DefaultEnvironment {
+    let mut env = DefaultEnvironment::new();
+    env.put("a".to_string(), Value::Integer(1));
+    env.put("s".to_string(), Value::String("abcd".to_string()));
+    env
+}
+
+/// `(a + ... + a) + (a + ... + a) + ...`，共 `n` 项。每 100 项加一层括号，语法树不超过嵌套层数的限制
+fn long_sum(n: usize) -> String {
+    let terms = vec!["a"; n];
+    let groups: Vec<String> = terms
+        .chunks(100)
+        .map(|group| format!("({})", group.join(" + ")))
+        .collect();
+    groups.join(" + ")
+}
+
+#[test]
+fn test_instruction_limit() {
+    let src = long_sum(200);
+    for mode in MODES {
+        let limits = Limits {
+            max_instructions: Some(100),
+            ..Limits::default()
+        };
+        let err = runner(mode, limits)
+            .execute_with_env(&src, &mut get_env())
+            .unwrap_err();
+        assert!(
+            matches!(err, RspError::InstructionLimit { limit: 100 }),
+            "{:?}",
+            err
+        );
+
+        let limits = Limits {
+            max_instructions: Some(10_000),
+            ..Limits::default()
+        };
+        let v = runner(mode, limits)
+            .execute_with_env(&src, &mut get_env())
+            .unwrap();
+        assert_eq!(Value::Integer(200), v);
+    }
+
+    // 并行执行时每个工作线程分别计数
+    let srcs: Vec<String> = (0..4)
+        .map(|i| format!("x{} = {}", i, long_sum(50)))
+        .collect();
+    let srcs: Vec<&str> = srcs.iter().map(String::as_str).collect();
+    for mode in MODES {
+        let limits = Limits {
+            max_instructions: Some(20),
+            ..Limits::default()
+        };
+        let err = runner(mode, limits)
+            .execute_multiple_parallel_with_env(&srcs, &mut get_env())
+            .unwrap_err();
+        assert!(
+            matches!(err, RspError::InstructionLimit { .. }),
+            "{:?}",
+            err
+        );
+    }
+}
+
+#[test]
+fn test_stack_limit() {
+    let nested = format!("{}a{}", "(a + ".repeat(20), ")".repeat(20));
+    for mode in MODES {
+        let limits = Limits {
+            max_stack: 8,
+            ..Limits::default()
+        };
+        let err = runner(mode, limits)
+            .execute_with_env(&nested, &mut get_env())
+            .unwrap_err();
+        assert!(
+            matches!(err, RspError::StackOverflow { limit: 8 }),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            Value::Integer(21),
+            runner(mode, Limits::default())
+                .execute_with_env(&nested, &mut get_env())
+                .unwrap()
+        );
+    }
+
+    // 未校验的字节码超出默认栈深度时报错而不是 panic
+    let source = format!("Begin 0\n{}End\nExit", "True\n".repeat(300));
+    let chunk = assemble(&source).unwrap();
+    let mut runner = runner(ExecuteMode::ChunkVM, Limits::default());
+    runner.set_verify_chunk(false);
+    let err = runner.run_chunk(&chunk, &mut get_env()).unwrap_err();
+    assert!(
+        matches!(err, RspError::StackOverflow { limit: 256 }),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn test_string_limit() {
+    for mode in MODES {
+        let limits = Limits {
+            max_string_len: Some(10),
+            ..Limits::default()
+        };
+        let mut runner = runner(mode, limits);
+        assert_eq!(
+            Value::String("abcdabcd".to_string()),
+            runner.execute_with_env("s + s", &mut get_env()).unwrap()
+        );
+        let err = runner
+            .execute_with_env("s + s + s", &mut get_env())
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                RspError::StringTooLong {
+                    length: 12,
+                    limit: 10
+                }
+            ),
+            "{:?}",
+            err
+        );
+    }
+}
+
+#[test]
+fn test_timeout_and_cancel() {
+    let src = long_sum(400);
+    for mode in MODES {
+        let limits = Limits {
+            timeout: Some(Duration::ZERO),
+            ..Limits::default()
+        };
+        let err = runner(mode, limits)
+            .execute_with_env(&src, &mut get_env())
+            .unwrap_err();
+        assert!(matches!(err, RspError::Timeout { .. }), "{:?}", err);
+
+        let token = CancelToken::new();
+        let limits = Limits {
+            cancel_token: Some(token.clone()),
+            timeout: Some(Duration::from_secs(60)),
+            ..Limits::default()
+        };
+        let mut runner = runner(mode, limits);
+        assert!(runner.execute_with_env(&src, &mut get_env()).is_ok());
+        token.cancel();
+        let err = runner.execute_with_env(&src, &mut get_env()).unwrap_err();
+        assert!(matches!(err, RspError::Cancelled), "{:?}", err);
+    }
+}
+
+#[test]
+fn test_batch_and_closure_limits() {
+    let mut batch = Batch::new(100);
+    batch.set_column("a", vec![1; 100]).unwrap();
+    let limits = Limits {
+        max_instructions: Some(400),
+        ..Limits::default()
+    };
+    let mut runner = runner(ExecuteMode::ChunkVM, limits);
+    // 按列执行时每条指令按行数计数
+    let err = runner
+        .execute_batch(&["a + a * 2"], &mut batch)
+        .unwrap_err();
+    assert!(
+        matches!(err, RspError::InstructionLimit { .. }),
+        "{:?}",
+        err
+    );
+    let err = runner
+        .execute_batch(&["if(a > 0, a, 0)"], &mut batch)
+        .unwrap_err();
+    assert!(
+        matches!(err, RspError::InstructionLimit { .. }),
+        "{:?}",
+        err
+    );
+
+    // 闭包按节点计数，与语法树解释执行相同
+    runner.set_execute_mode(ExecuteMode::Closure);
+    assert_eq!(
+        Value::Integer(2),
+        runner.execute_with_env("a + 1", &mut get_env()).unwrap()
+    );
+    let program = runner.compile_closures(&[long_sum(300)]).unwrap();
+    let mut env = get_env();
+    let err = runner.run_closures(&program, &mut env).unwrap_err();
+    assert!(
+        matches!(err, RspError::InstructionLimit { limit: 400 }),
+        "{:?}",
+        err
+    );
+    let err = runner
+        .run_closures_parallel(&program, &mut env)
+        .unwrap_err();
+    assert!(
+        matches!(err, RspError::InstructionLimit { limit: 400 }),
+        "{:?}",
+        err
+    );
+    runner.set_limits(Limits::default());
+    assert_eq!(
+        Value::Integer(300),
+        runner.run_closures(&program, &mut env).unwrap()[0]
+    );
+}
@@ -0,0 +1,125 @@
+use rspression::expr::Expr;
+use rspression::ir::Analyzer;
+use rspression::{DefaultEnvironment, Parser, RspError, RspRunner};
+
+/// 容错解析得到的所有错误在源码中对应的文本
+fn error_texts(src: &str) -> Vec<String> {
+    Parser::new(src)
+        .parse_recover()
+        .errors
+        .iter()
+        .map(|e| e.span().unwrap().slice(src).unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn test_multiple_errors() {
+    let cases: [(&str, &[&str]); 8] = [
+        ("abs(1 +, 2 *, 3)", &[",", ","]),
+        ("if(a > 1 2, 3, )", &["2", ")"]),
+        ("f(1, (2 3), 4) + x", &["3"]),
+        ("(1 +) * (2 -)", &[")", ")"]),
+        ("a = $ + b # c", &["$", "#"]),
+        ("o. + 1", &["+"]),
+        ("(1 +) 2", &[")", "2"]),
+        // 同一个错误不会引起一连串的报错
+        ("((((1 +", &[""]),
+    ];
+    for (src, texts) in cases {
+        assert_eq!(texts, error_texts(src), "{}", src);
+    }
+}
+
+#[test]
+fn test_partial_ast() {
+    let src = "x = max(a, b +) + c.";
+    let partial = Parser::new(src).parse_recover();
+    assert!(!partial.is_ok());
+    assert!(partial.expr.has_error());
+    let Expr::Assign(assign) = &partial.expr else {
+        panic!("expected assignment");
+    };
+    let Expr::Id(id) = &*assign.left else {
+        panic!("expected id");
+    };
+    assert_eq!("x", &*id.name.lexeme);
+    // 出错的部分之外仍然保留原来的结构，参数中出错的部分由 `Expr::Error` 代替
+    let Expr::Binary(sum) = &*assign.right else {
+        panic!("expected binary");
+    };
+    let Expr::Call(call) = &*sum.left else {
+        panic!("expected call");
+    };
+    assert_eq!(2, call.arguments.len());
+    assert!(matches!(call.arguments[0], Expr::Id(_)));
+    let Expr::Binary(arg) = &call.arguments[1] else {
+        panic!("expected binary");
+    };
+    assert!(matches!(*arg.right, Expr::Error(_)));
+    assert!(matches!(*sum.right, Expr::Error(_)));
+    assert_eq!(Some("c."), sum.right.span().slice(src));
+    assert_eq!(2, partial.errors.len());
+}
+
+#[test]
+fn test_same_as_parse() {
+    for src in ["a + b * 2", "if(a, b, c)", "x = o.p.q", "abs(-1)"] {
+        let partial = Parser::new(src).parse_recover();
+        assert!(partial.is_ok(), "{}", src);
+        assert!(!partial.expr.has_error());
+        assert_eq!(
+            Parser::new(src).parse().unwrap().span(),
+            partial.expr.span()
+        );
+    }
+    // 正常解析只返回第一个错误
+    let src = "abs(1 +, 2 *, 3)";
+    let err = Parser::new(src).parse().err().unwrap();
+    let partial = Parser::new(src).parse_recover();
+    assert_eq!(err.to_string(), partial.errors[0].to_string());
+}
+
+#[test]
+fn test_error_node_execution() {
+    // 含有出错节点的语法树不能执行或编译，报告出错节点的位置
+    let src = "x = 1 + (2 *)";
+    let partial = Parser::new(src).parse_recover();
+    let ana = Analyzer::new(vec![partial.expr], true);
+    let infos = ana.analyze().unwrap();
+    let mut runner = RspRunner::new();
+    let err = runner
+        .run_ir(&infos, &mut DefaultEnvironment::new())
+        .unwrap_err();
+    assert!(matches!(err, RspError::ParseError { .. }), "{}", err);
+    // `*` 后面缺少操作数，出错节点是右括号处长度为 0 的一段
+    let span = err.span().unwrap();
+    assert_eq!((src.len() - 1, 0), (span.start(), span.len as usize));
+    let err = runner.compile_ir(&infos).unwrap_err();
+    assert!(matches!(err, RspError::ParseError { .. }), "{}", err);
+}
+
+#[test]
+fn test_runner_parse_recover() {
+    let srcs = ["x = a + 1", "y = (b * ) + c.", "z = x + y", "w = $ 1"];
+    let errors = RspRunner::new().parse_recover(&srcs);
+    let found: Vec<(usize, Vec<&str>)> = errors
+        .iter()
+        .map(|(index, errors)| {
+            let texts = errors
+                .iter()
+                .map(|e| e.span().unwrap().slice(srcs[*index]).unwrap())
+                .collect();
+            (*index, texts)
+        })
+        .collect();
+    assert_eq!(vec![(1, vec![")", ""]), (3, vec!["$"])], found);
+    assert!(
+        errors
+            .iter()
+            .flat_map(|(_, errors)| errors)
+            .all(|e| matches!(e, RspError::ParseError { .. }))
+    );
+    // 解析遇到第一个错误就返回
+    assert!(RspRunner::new().parse(&srcs).is_err());
+    assert!(RspRunner::new().parse_recover(&["a + 1", "b"]).is_empty());
+}
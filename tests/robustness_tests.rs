@@ -0,0 +1,292 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rspression::chunk::disassemble;
+use rspression::vm::{VM, Verifier};
+use rspression::{
+    Batch, Chunk, DefaultEnvironment, Environment, ExecuteMode, Parser, Program, RspRunner, Value,
+};
+
+const SEED: u64 = 0x5eed_2024;
+const ROUNDS: usize = 3000;
+
+/// 随机表达式使用的片段，拼接后大部分能通过词法分析，少部分能通过语法分析
+const PIECES: [&str; 40] = [
+    "a",
+    "b",
+    "x",
+    "s",
+    "o",
+    "o.p",
+    "1",
+    "0",
+    "2.5",
+    "2147483647",
+    "-",
+    "+",
+    "*",
+    "/",
+    "%",
+    "**",
+    "(",
+    ")",
+    ",",
+    "=",
+    "==",
+    "!=",
+    "!",
+    "<",
+    ">=",
+    "&&",
+    "||",
+    "if",
+    "abs",
+    "clock",
+    "\"t\"",
+    "\"",
+    ".",
+    "true",
+    "null",
+    " ",
+    "\n",
+    "//",
+    "#",
+    "é",
+];
+
+/// 执行 `f`，panic 时报告导致 panic 的输入
+fn no_panic<F: FnOnce()>(input: &dyn std::fmt::Debug, f: F) {
+    if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+        panic!("panicked on input: {:?}", input);
+    }
+}
+
+fn get_env() -> DefaultEnvironment {
+    let mut env = DefaultEnvironment::new();
+    env.put("a".to_string(), Value::Integer(i32::MIN));
+    env.put("b".to_string(), Value::Integer(-1));
+    env.put("x".to_string(), Value::Double(0.0));
+    env.put("s".to_string(), Value::String("str".to_string()));
+    env
+}
+
+fn random_source(rng: &mut StdRng) -> String {
+    let n = rng.random_range(1..16);
+    (0..n)
+        .map(|_| PIECES[rng.random_range(0..PIECES.len())])
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+fn run_all_modes(srcs: &[&str]) {
+    for mode in [
+        ExecuteMode::SyntaxTree,
+        ExecuteMode::ChunkVM,
+        ExecuteMode::Closure,
+    ] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        let _ = runner.execute_multiple_with_env(srcs, &mut get_env());
+        let _ = runner.execute_multiple_parallel_with_env(srcs, &mut get_env());
+    }
+}
+
+#[test]
+fn test_random_sources() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    for _ in 0..ROUNDS {
+        let src = random_source(&mut rng);
+        no_panic(&src, || {
+            let _ = Parser::new(&src).parse();
+            run_all_modes(&[&src]);
+        });
+    }
+    // 任意字符，包括多字节字符和控制字符
+    for _ in 0..ROUNDS {
+        let len = rng.random_range(0..24);
+        let src: String = (0..len).map(|_| rng.random::<char>()).collect();
+        no_panic(&src, || {
+            let _ = Parser::new(&src).parse();
+        });
+    }
+}
+
+#[test]
+fn test_arithmetic_edge_cases() {
+    let srcs = [
+        "a - 1",
+        "a * b",
+        "a / b",
+        "a % b",
+        "-a",
+        "abs(a)",
+        "1 % 0",
+        "a % 0",
+        "2147483647 + 1",
+        "x % 0",
+        "1 / 0.0",
+        "s * 2",
+        "o.p",
+        "y = o.p = 1",
+    ];
+    for src in srcs {
+        no_panic(&src, || run_all_modes(&[src]));
+    }
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::ChunkVM);
+    let err = runner.execute("1 % 0").unwrap_err();
+    assert!(err.to_string().contains("Division by zero"), "{}", err);
+    // 整数溢出时按补码回绕
+    assert_eq!(
+        Value::Integer(i32::MIN),
+        runner.execute_with_env("a / b", &mut get_env()).unwrap()
+    );
+    assert_eq!(
+        Value::Integer(i32::MAX),
+        runner.execute_with_env("a - 1", &mut get_env()).unwrap()
+    );
+    assert_eq!(
+        Value::Integer(i32::MIN),
+        runner.execute("2147483647 + 1").unwrap()
+    );
+}
+
+#[test]
+fn test_deep_nesting() {
+    // 直接在测试线程中执行，测试线程默认只有 2MB 栈。
+    // 接近上限的嵌套在各种执行方式下都能正常处理，超过上限时返回解析错误，而不是耗尽线程栈
+    let nested =
+        |open: &str, close: &str, n: usize| format!("{}a{}", open.repeat(n), close.repeat(n));
+    for (n, ok) in [(127, true), (100_000, false)] {
+        let srcs = [
+            nested("(", ")", n * 2),
+            nested("(a + ", ")", n),
+            nested("-", "", n * 2),
+            nested("!", "", n * 2),
+            nested("x = ", "", n * 2),
+            nested("a ** ", "", n * 2),
+            nested("abs(", ")", n * 2),
+            nested("if(a, ", ", a)", n * 2),
+            // 不递归解析的长链也会生成很高的语法树
+            nested("a + ", "", n * 2),
+            nested("a && ", "", n * 2),
+            nested("", ".b", n * 2),
+        ];
+        for src in &srcs {
+            no_panic(&n, || run_all_modes(&[src]));
+            let parsed = Parser::new(src).parse();
+            assert_eq!(ok, parsed.is_ok(), "{}", &src[..20]);
+            if let Err(err) = parsed {
+                assert!(err.to_string().contains("nested too deeply"), "{}", err);
+            }
+            // 容错解析也只报告一次，外层缺少的右括号不会引起一连串的报错
+            let partial = Parser::new(src).parse_recover();
+            assert_eq!(usize::from(!ok), partial.errors.len(), "{}", &src[..20]);
+            if ok {
+                no_panic(&n, || run_on_workers(src));
+            }
+        }
+    }
+}
+
+/// 同一层有足够多的公式，并行执行时分配到默认栈大小的工作线程上
+fn run_on_workers(src: &str) {
+    let srcs: Vec<String> = (0..130).map(|i| format!("y{} = {}", i, src)).collect();
+    let srcs: Vec<&str> = srcs.iter().map(String::as_str).collect();
+    for mode in [
+        ExecuteMode::SyntaxTree,
+        ExecuteMode::ChunkVM,
+        ExecuteMode::Closure,
+    ] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        runner.set_threads(2);
+        let mut env = DefaultEnvironment::new();
+        env.put("a".to_string(), Value::Integer(1));
+        env.put("x".to_string(), Value::Integer(1));
+        let _ = runner.execute_multiple_parallel_with_env(&srcs, &mut env);
+    }
+}
+
+/// 把随机选取的字节改成随机值，有时再截断或追加
+fn mutate(rng: &mut StdRng, bytes: &[u8]) -> Vec<u8> {
+    let mut out = bytes.to_vec();
+    for _ in 0..rng.random_range(1..4) {
+        if out.is_empty() {
+            break;
+        }
+        let i = rng.random_range(0..out.len());
+        out[i] = rng.random();
+    }
+    match rng.random_range(0..8) {
+        0 => out.truncate(rng.random_range(0..=out.len())),
+        1 => out.push(rng.random()),
+        _ => {}
+    }
+    out
+}
+
+/// 不经过校验直接加载和执行，任何错误都可以，但不能 panic
+fn exercise_chunk(chunk: &Chunk) {
+    let _ = Verifier::verify(chunk);
+    let _ = disassemble(chunk);
+    let _ = chunk.variables();
+    let mut vm = VM::new();
+    let _ = vm.execute_with_env(chunk, &mut get_env());
+    let _ = VM::execute_parallel_with_env(chunk, &mut get_env(), 2);
+    let mut runner = RspRunner::new();
+    runner.set_verify_chunk(false);
+    let _ = runner.run_chunk_for_targets(chunk, &["y"], &mut get_env());
+    if let Ok(program) = Program::new(chunk) {
+        let mut batch = Batch::new(3);
+        let _ = batch.set_column("a", vec![i32::MIN, 0, 7]);
+        batch.set_scalar("b", Value::Integer(-1));
+        let _ = runner.run_program_batch(&program, &mut batch);
+    }
+}
+
+#[test]
+fn test_random_chunks() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut runner = RspRunner::new();
+    let chunk = runner
+        .compile_source(&[
+            "y = a + b * 2",
+            "z = if(y > 1, s + \"!\", abs(x))",
+            "w = y / b % 3 && z || !a",
+        ])
+        .unwrap();
+    let bytes = chunk.to_bytes();
+
+    for _ in 0..ROUNDS {
+        // 完全随机的字节，有一半带上合法的文件头
+        let len = rng.random_range(0..64);
+        let mut random: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+        if rng.random_bool(0.5) {
+            random.splice(0..0, bytes[..8].iter().copied());
+        }
+        no_panic(&random, || {
+            if let Ok(chunk) = Chunk::from_bytes(&random) {
+                exercise_chunk(&chunk);
+            }
+        });
+
+        // 在合法字节码上做少量修改。文件头带有校验和，直接修改各段内容才能进入执行阶段
+        let mutated = mutate(&mut rng, &bytes);
+        no_panic(&mutated, || {
+            let _ = Chunk::from_bytes(&mutated);
+        });
+        let mutated = Chunk {
+            codes: mutate(&mut rng, &chunk.codes),
+            constants: if rng.random_bool(0.3) {
+                mutate(&mut rng, &chunk.constants)
+            } else {
+                chunk.constants.clone()
+            },
+            vars: chunk.vars.clone(),
+            debug: chunk.debug.clone(),
+        };
+        no_panic(&mutated, || exercise_chunk(&mutated));
+    }
+}
@@ -24,6 +24,41 @@ fn test_ir() {
     println!("==========");
 }
 
+#[test]
+fn test_ir_parallel() {
+    println!("批量运算测试(解析执行，分层并行)");
+    let lines = get_expressions();
+    let srcs: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let start = Instant::now();
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::SyntaxTree);
+    runner.set_threads(4);
+    let mut env = get_env();
+    runner
+        .execute_multiple_parallel_with_env(&srcs, &mut env)
+        .unwrap();
+    check_values(&env);
+    println!("用时: {:?}", start.elapsed());
+    println!("==========");
+}
+
+#[test]
+fn test_chunk_parallel() {
+    println!("批量运算测试(编译+字节码分层并行执行)");
+    let lines = get_expressions();
+    let srcs: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let mut runner = RspRunner::new();
+    runner.set_threads(4);
+    let chunk = runner.compile_source(&srcs).unwrap();
+
+    let start = std::time::Instant::now();
+    let mut env = get_env();
+    runner.run_chunk_parallel(&chunk, &mut env).unwrap();
+    check_values(&env);
+    println!("字节码并行执行用时：{:?}", start.elapsed());
+    println!("==========");
+}
+
 #[test]
 fn test_compile_chunk() {
     println!("批量运算测试(编译+字节码执行)");
@@ -42,6 +77,30 @@ fn test_compile_chunk() {
     println!("==========");
 }
 
+#[test]
+fn test_compile_closures() {
+    println!("批量运算测试(编译为闭包+执行)");
+    let lines = get_expressions();
+    let srcs: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let mut runner = RspRunner::new();
+    let start = std::time::Instant::now();
+    let program = runner.compile_closures(&srcs).unwrap();
+    println!("编译用时: {:?}", start.elapsed());
+
+    let start = std::time::Instant::now();
+    let mut env = get_env();
+    runner.run_closures(&program, &mut env).unwrap();
+    check_values(&env);
+    println!("闭包执行用时：{:?}", start.elapsed());
+
+    let start = std::time::Instant::now();
+    let mut env = get_env();
+    runner.run_closures_parallel(&program, &mut env).unwrap();
+    check_values(&env);
+    println!("闭包并行执行用时：{:?}", start.elapsed());
+    println!("==========");
+}
+
 #[test]
 fn test_file_chunk() {
     println!("字节码编译到文件再从文件读取执行");
@@ -71,6 +130,7 @@ fn test_file_chunk() {
     println!("==========");
 }
 
+#[allow(clippy::ptr_arg)]
 fn create_and_get_chunk(path: &PathBuf) -> Chunk {
     let lines = get_expressions();
     let srcs: Vec<&str> = lines.iter().map(String::as_str).collect();
@@ -92,6 +152,7 @@ fn create_and_get_chunk(path: &PathBuf) -> Chunk {
     chunk
 }
 
+#[allow(clippy::extra_unused_lifetimes)]
 fn get_expressions<'a>() -> Vec<String> {
     let mut lines = Vec::new();
     let fml = "A! = 1 + 2 * 3 - 6 - 1 + B! + C! * (D! - E! + 10 ** 2 / 5 - (12 + 8)) - F! * G! +  100 / 5 ** 2 ** 1";
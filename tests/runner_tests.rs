@@ -1,4 +1,12 @@
-use rspression::{DefaultEnvironment, Environment, RspRunner, Value};
+use std::sync::Arc;
+use std::thread;
+
+use rspression::expr::Expr;
+use rspression::ir::Analyzer;
+use rspression::{
+    DefaultEnvironment, DefaultSlotEnvironment, Environment, ExecuteMode, Field, OptLevel, Parser,
+    RspError, RspRunner, SlotEnvironment, Value,
+};
 
 #[test]
 fn test_basic_arithmetic() {
@@ -105,6 +113,71 @@ fn test_logical_operations() {
 }
 
 #[test]
+fn test_mode_semantics() {
+    let modes = [
+        ExecuteMode::SyntaxTree,
+        ExecuteMode::ChunkVM,
+        ExecuteMode::Closure,
+    ];
+    let get_env = || {
+        let mut env = DefaultEnvironment::new();
+        env.put("s".to_string(), Value::String(String::new()));
+        env.put("n".to_string(), Value::Integer(-3));
+        env
+    };
+    for mode in modes {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        // `&&` 和 `||` 返回决定结果的操作数，只有 `null`、`false` 和空字符串为假
+        let cases = [
+            ("s || \"none\"", Value::String("none".to_string())),
+            ("1 && 0", Value::Integer(0)),
+            ("0 || 2", Value::Integer(0)),
+            ("null && 1", Value::Null),
+            ("abs(n) + 1", Value::Integer(4)),
+        ];
+        for (src, expected) in cases {
+            let result = runner.execute_with_env(src, &mut get_env()).unwrap();
+            assert_eq!(expected, result, "{:?}: {}", mode, src);
+        }
+        // 读取没有值的变量时报错
+        let err = runner
+            .execute_with_env("u + 1", &mut get_env())
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Undefined variable: u"),
+            "{:?}: {}",
+            mode,
+            err
+        );
+        let err = runner
+            .execute_with_env("abs(1, 2)", &mut get_env())
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Expected 1 arguments"),
+            "{:?}: {}",
+            mode,
+            err
+        );
+    }
+
+    // 唯一的差别：字节码和闭包在编译时检查函数调用，语法树只检查执行到的调用
+    let src = "if(true, 1, nofn(2))";
+    for mode in modes {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        let result = runner.execute_with_env(src, &mut get_env());
+        assert_eq!(
+            mode == ExecuteMode::SyntaxTree,
+            result.is_ok(),
+            "{:?}",
+            mode
+        );
+    }
+}
+
+#[test]
+#[allow(clippy::vec_init_then_push)]
 fn test_muilti_evaluate() {
     let mut env = DefaultEnvironment::new();
 
@@ -134,6 +207,7 @@ fn test_muilti_evaluate() {
 }
 
 #[test]
+#[allow(clippy::vec_init_then_push)]
 fn test_calculation() {
     let mut srcs = Vec::new();
     srcs.push("x = a + b * c");
@@ -157,3 +231,612 @@ fn test_calculation() {
     assert_eq!(12, results[2].as_integer());
     assert_eq!(10, results[3].as_integer());
 }
+
+#[test]
+fn test_call_non_function() {
+    // 被调用的不是函数名时编译报错，而不是静默地不生成任何指令
+    let mut runner = RspRunner::new();
+    for src in ["1(2)", "(a + 1)(2)", "o.f(1)"] {
+        match runner.compile_source(&[src]) {
+            Err(err @ RspError::CompileError { .. }) => {
+                assert!(
+                    err.to_string()
+                        .contains("Only named functions can be called")
+                )
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected compile error: {}", src),
+        }
+    }
+}
+
+#[test]
+fn test_parallel_calculation() {
+    let srcs = vec![
+        "x = a + b * c",
+        "a = m + n",
+        "b = a * 2",
+        "c = n + w",
+        "a + c",
+    ];
+
+    for mode in [ExecuteMode::SyntaxTree, ExecuteMode::ChunkVM] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        runner.set_threads(2);
+        let mut env = DefaultEnvironment::new();
+        env.put("m".to_string(), Value::Integer(2));
+        env.put("n".to_string(), Value::Integer(4));
+        env.put("w".to_string(), Value::Integer(6));
+        let results = runner
+            .execute_multiple_parallel_with_env(&srcs, &mut env)
+            .unwrap();
+        assert_eq!(126, env.get("x").unwrap().as_integer());
+        assert_eq!(6, env.get("a").unwrap().as_integer());
+        assert_eq!(12, env.get("b").unwrap().as_integer());
+        assert_eq!(10, env.get("c").unwrap().as_integer());
+
+        assert_eq!(126, results[0].as_integer());
+        assert_eq!(6, results[1].as_integer());
+        assert_eq!(12, results[2].as_integer());
+        assert_eq!(10, results[3].as_integer());
+        assert_eq!(16, results[4].as_integer());
+    }
+}
+
+#[test]
+fn test_parallel_workers() {
+    // 同一层的公式超过一批，并行执行时会分配到多个工作线程上
+    let srcs: Vec<String> = (0..200)
+        .map(|i| format!("y{} = m * {} + n", i, i))
+        .chain(["s = y0 + y199".to_string(), "s * 2".to_string()])
+        .collect();
+    let srcs: Vec<&str> = srcs.iter().map(String::as_str).collect();
+    let get_env = || {
+        let mut env = DefaultEnvironment::new();
+        env.put("m".to_string(), Value::Integer(2));
+        env.put("n".to_string(), Value::Integer(4));
+        env
+    };
+
+    for mode in [
+        ExecuteMode::SyntaxTree,
+        ExecuteMode::ChunkVM,
+        ExecuteMode::Closure,
+    ] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        runner.set_threads(4);
+        let mut serial_env = get_env();
+        let serial = runner
+            .execute_multiple_with_env(&srcs, &mut serial_env)
+            .unwrap();
+        let mut env = get_env();
+        let results = runner
+            .execute_multiple_parallel_with_env(&srcs, &mut env)
+            .unwrap();
+        assert_eq!(serial, results, "{:?}", mode);
+        assert_eq!(Value::Integer(812), results[201]);
+        for i in 0..200 {
+            let name = format!("y{}", i);
+            assert_eq!(serial_env.get(&name), env.get(&name), "{:?}", mode);
+        }
+    }
+}
+
+#[test]
+fn test_run_ir_parallel_subset() {
+    let srcs = ["a = m + n", "b = a * 2", "c = n + w"];
+    let mut runner = RspRunner::new();
+    let plan = runner.analyze(&srcs).unwrap();
+    let levels = plan.levels();
+    // 只执行最后一层，结果按公式序号存放
+    let mut env = DefaultEnvironment::new();
+    env.put("a".to_string(), Value::Integer(3));
+    let results = runner
+        .run_ir_parallel(&levels[levels.len() - 1..], &mut env)
+        .unwrap();
+    assert_eq!(vec![Value::Null, Value::Integer(6)], results);
+}
+
+#[test]
+fn test_targets() {
+    let srcs = vec![
+        "x = a + b * c",
+        "a = m + n",
+        "b = a * 2",
+        "c = n + w",
+        "y = c * 2",
+        "z = m / 0",
+    ];
+
+    for mode in [ExecuteMode::SyntaxTree, ExecuteMode::ChunkVM] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        let mut env = DefaultEnvironment::new();
+        env.put("m".to_string(), Value::Integer(2));
+        env.put("n".to_string(), Value::Integer(4));
+        env.put("w".to_string(), Value::Integer(6));
+        let results = runner.execute_for_targets(&srcs, &["b"], &mut env).unwrap();
+        assert_eq!(6, results.len());
+        assert_eq!(Value::Null, results[0]);
+        assert_eq!(6, results[1].as_integer());
+        assert_eq!(12, results[2].as_integer());
+        assert_eq!(Value::Null, results[3]);
+        assert!(env.get("x").is_none());
+        assert!(env.get("c").is_none());
+        assert_eq!(12, env.get("b").unwrap().as_integer());
+
+        let results = runner.execute_for_targets(&srcs, &["x"], &mut env).unwrap();
+        assert_eq!(126, results[0].as_integer());
+        assert_eq!(10, results[3].as_integer());
+        assert_eq!(Value::Null, results[4]);
+        assert!(env.get("y").is_none());
+    }
+}
+
+#[test]
+fn test_chunk_targets() {
+    let srcs = vec![
+        "x = a + b",
+        "a = m + n",
+        "b = a * 2",
+        "y = m * 0",
+        "z = m / 0",
+    ];
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&srcs).unwrap();
+
+    let mut env = DefaultEnvironment::new();
+    env.put("m".to_string(), Value::Integer(2));
+    env.put("n".to_string(), Value::Integer(4));
+    let results = runner
+        .run_chunk_for_targets(&chunk, &["x"], &mut env)
+        .unwrap();
+    // 与 `execute_for_targets` 相同，结果的长度等于公式个数
+    assert_eq!(srcs.len(), results.len());
+    assert_eq!(Value::Null, results[4]);
+    assert_eq!(18, results[0].as_integer());
+    assert_eq!(6, results[1].as_integer());
+    assert_eq!(12, results[2].as_integer());
+    assert_eq!(18, env.get("x").unwrap().as_integer());
+    assert!(env.get("y").is_none());
+    assert!(env.get("z").is_none());
+}
+
+#[test]
+fn test_program() {
+    let srcs = vec!["x = a + b", "a = m + n", "b = a * 2"];
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&srcs).unwrap();
+    // 加载一次，在多个线程中分别用不同的环境反复执行
+    let program = Arc::new(runner.load_chunk(&chunk).unwrap());
+    assert_eq!(3, program.blocks().len());
+
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let program = Arc::clone(&program);
+            thread::spawn(move || {
+                let mut runner = RspRunner::new();
+                for m in 0..100 {
+                    let mut env = DefaultEnvironment::new();
+                    env.put("m".to_string(), Value::Integer(m));
+                    env.put("n".to_string(), Value::Integer(t));
+                    let results = runner.run_program(&program, &mut env).unwrap();
+                    assert_eq!((m + t) * 3, results[0].as_integer());
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_slot_environment() {
+    let srcs = vec!["x = a + b", "a = m + n", "b = a * 2"];
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&srcs).unwrap();
+    let program = runner.load_chunk(&chunk).unwrap();
+    assert_eq!(5, program.slot_count());
+    assert!(program.slot("y").is_none());
+
+    // 槽位只需查询一次
+    let m = program.slot("m").unwrap();
+    let n = program.slot("n").unwrap();
+    let x = program.slot("x").unwrap();
+    let mut env = DefaultSlotEnvironment::new(program.slot_count());
+    for i in 0..10 {
+        env.clear();
+        env.set(m, Value::Integer(i));
+        env.set(n, Value::Integer(1));
+        let results = runner.run_program_slots(&program, &mut env).unwrap();
+        assert_eq!((i + 1) * 3, results[0].as_integer());
+        assert_eq!((i + 1) * 3, env.get_slot(x).unwrap().as_integer());
+    }
+
+    env.clear();
+    env.set(m, Value::Integer(1));
+    assert!(runner.run_program_slots(&program, &mut env).is_err());
+    // 超出范围的槽位不会被设置
+    assert!(!env.set(program.slot_count(), Value::Integer(1)));
+    assert_eq!(5, env.size());
+}
+
+/// 记录 `before_execute` 收到的变量，并模拟一次性从数据库中加载数据
+struct PrefetchEnvironment {
+    inner: DefaultEnvironment,
+    requested: Vec<String>,
+    fields: Vec<String>,
+    allow: bool,
+}
+
+impl PrefetchEnvironment {
+    fn new(allow: bool) -> Self {
+        Self {
+            inner: DefaultEnvironment::new(),
+            requested: Vec::new(),
+            fields: Vec::new(),
+            allow,
+        }
+    }
+}
+
+impl Environment for PrefetchEnvironment {
+    fn before_execute(&mut self, vars: &[String], fields: &[Arc<Field>]) -> bool {
+        self.requested = vars.to_vec();
+        self.fields = fields.iter().map(|f| f.to_string()).collect();
+        for name in vars {
+            if name == "m" || name == "n" {
+                self.inner.put(name.clone(), Value::Integer(2));
+            }
+        }
+        self.allow
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.inner.get(name)
+    }
+
+    fn put(&mut self, name: String, value: Value) -> bool {
+        self.inner.put(name, value)
+    }
+
+    fn extend<T: IntoIterator<Item = (String, Value)>>(&mut self, iter: T) {
+        self.inner.extend(iter)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+}
+
+#[test]
+fn test_chunk_variables() {
+    let srcs = vec!["x = a + b", "a = m + n", "b = a * 2", "y = w * 2"];
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&srcs).unwrap();
+    let variables = chunk.variables().unwrap();
+    assert_eq!(vec!["a", "b", "m", "n", "w"], variables.reads);
+    assert_eq!(vec!["a", "b", "x", "y"], variables.writes);
+
+    let mut env = PrefetchEnvironment::new(true);
+    let results = runner
+        .run_chunk_for_targets(&chunk, &["x"], &mut env)
+        .unwrap();
+    assert_eq!(vec!["a", "b", "m", "n", "x"], env.requested);
+    assert_eq!(12, results[0].as_integer());
+
+    env.allow = false;
+    assert!(runner.run_chunk(&chunk, &mut env).is_err());
+    assert_eq!(vec!["a", "b", "m", "n", "w", "x", "y"], env.requested);
+}
+
+#[test]
+fn test_before_execute_fields() {
+    let srcs = vec!["x = order.customer.tier * 2 + n", "y = x + order.amount"];
+    let expected = vec!["n", "order.amount", "order.customer.tier", "x", "y"];
+    let modes = [
+        ExecuteMode::SyntaxTree,
+        ExecuteMode::ChunkVM,
+        ExecuteMode::Closure,
+    ];
+    for mode in modes {
+        for parallel in [false, true] {
+            let mut runner = RspRunner::new();
+            runner.set_execute_mode(mode);
+            let mut env = PrefetchEnvironment::new(false);
+            let result = if parallel {
+                runner.execute_multiple_parallel_with_env(&srcs, &mut env)
+            } else {
+                runner.execute_multiple_with_env(&srcs, &mut env)
+            };
+            assert!(
+                matches!(result, Err(RspError::Aborted { .. })),
+                "{:?}",
+                mode
+            );
+            // 各种执行方式收到的变量名和访问路径完全相同
+            assert_eq!(vec!["n", "order", "x", "y"], env.requested, "{:?}", mode);
+            assert_eq!(expected, env.fields, "{:?}", mode);
+            assert!(env.get("x").is_none());
+        }
+    }
+
+    // 给属性赋值时同样给出完整路径
+    let assigns = vec!["order.total = order.amount + fee"];
+    for mode in modes {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        let mut env = PrefetchEnvironment::new(false);
+        assert!(
+            runner
+                .execute_multiple_with_env(&assigns, &mut env)
+                .is_err()
+        );
+        assert_eq!(vec!["fee", "order"], env.requested, "{:?}", mode);
+        assert_eq!(
+            vec!["fee", "order.amount", "order.total"],
+            env.fields,
+            "{:?}",
+            mode
+        );
+    }
+
+    let mut runner = RspRunner::new();
+    let mut env = PrefetchEnvironment::new(false);
+    let levels = {
+        let exprs = runner.parse(&srcs).unwrap();
+        let plan = Analyzer::new(exprs, true).into_plan().unwrap();
+        let result = runner.run_plan_parallel(&plan, &mut env);
+        assert!(matches!(result, Err(RspError::Aborted { .. })));
+        plan.level_count()
+    };
+    assert_eq!(2, levels);
+    assert_eq!(expected, env.fields);
+}
+
+#[test]
+fn test_execution_plan() {
+    let mut runner = RspRunner::new();
+    let plan = {
+        // 源字符串在生成执行计划后即被释放
+        let lines: Vec<String> = [
+            "x = a + b * c",
+            "a = m + n",
+            "b = a * 2",
+            "c = n + w",
+            "a + c",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let srcs: Vec<&str> = lines.iter().map(String::as_str).collect();
+        runner.analyze(&srcs).unwrap()
+    };
+    assert_eq!(5, plan.size());
+    assert_eq!(3, plan.level_count());
+    assert_eq!(vec!["m", "n", "w"], plan.inputs());
+    assert_eq!(vec!["a", "b", "c", "x"], plan.outputs());
+
+    let cached = plan.clone();
+    let handle = std::thread::spawn(move || {
+        let mut runner = RspRunner::new();
+        let mut env = DefaultEnvironment::new();
+        env.put("m".to_string(), Value::Integer(2));
+        env.put("n".to_string(), Value::Integer(4));
+        env.put("w".to_string(), Value::Integer(6));
+        runner.run_plan(&cached, &mut env).unwrap()
+    });
+    let results = handle.join().unwrap();
+    assert_eq!(126, results[0].as_integer());
+    assert_eq!(16, results[4].as_integer());
+
+    let chunk = runner.compile_plan(&plan).unwrap();
+    let mut env = DefaultEnvironment::new();
+    env.put("m".to_string(), Value::Integer(2));
+    env.put("n".to_string(), Value::Integer(4));
+    env.put("w".to_string(), Value::Integer(6));
+    let results = runner.run_chunk(&chunk, &mut env).unwrap();
+    assert_eq!(126, results[0].as_integer());
+    assert_eq!(16, results[4].as_integer());
+
+    let mut env = DefaultEnvironment::new();
+    env.put("m".to_string(), Value::Integer(2));
+    env.put("n".to_string(), Value::Integer(4));
+    env.put("w".to_string(), Value::Integer(6));
+    let results = runner.run_plan_parallel(&plan, &mut env).unwrap();
+    assert_eq!(126, results[0].as_integer());
+    assert_eq!(10, results[3].as_integer());
+}
+
+#[test]
+fn test_owned_ast() {
+    fn assert_send_sync<T: Send + Sync + 'static>(_: &T) {}
+
+    let mut runner = RspRunner::new();
+    let exprs = {
+        let lines = vec!["x = a + b".to_string(), "a = m * 2".to_string()];
+        runner.parse_owned(&lines).unwrap()
+    };
+    assert_send_sync(&exprs);
+
+    let handle = std::thread::spawn(move || {
+        let plan = Analyzer::new(exprs, true).into_plan().unwrap();
+        let mut env = DefaultEnvironment::new();
+        env.put("m".to_string(), Value::Integer(3));
+        env.put("b".to_string(), Value::Integer(1));
+        RspRunner::new().run_plan(&plan, &mut env).unwrap()
+    });
+    let results = handle.join().unwrap();
+    assert_eq!(7, results[0].as_integer());
+    assert_eq!(6, results[1].as_integer());
+
+    // 一次性解析仍然不拷贝源字符串
+    let src = "a + b";
+    let expr = Parser::new(src).parse().unwrap();
+    if let Expr::Binary(binary) = &expr {
+        assert!(binary.operator.lexeme.is_borrowed());
+    } else {
+        panic!("expected binary expression");
+    }
+    let owned = expr.clone().into_owned();
+    if let Expr::Binary(binary) = &owned {
+        assert!(!binary.operator.lexeme.is_borrowed());
+        assert_eq!("+", &*binary.operator.lexeme);
+    }
+}
+
+#[test]
+fn test_opt_level() {
+    let srcs = [
+        "a = 100 / 5 ** 2 ** 1 + x",
+        "b = if(2 > 1, a * 2, y) - -3",
+        "c = false && y || x - 10 % 4",
+        "d = x * 1 + 0",
+    ];
+    let mut expected = None;
+    for level in [OptLevel::None, OptLevel::Basic, OptLevel::Full] {
+        for mode in [
+            ExecuteMode::SyntaxTree,
+            ExecuteMode::ChunkVM,
+            ExecuteMode::Closure,
+        ] {
+            let mut runner = RspRunner::new();
+            runner.set_opt_level(level);
+            runner.set_execute_mode(mode);
+            let mut env = DefaultEnvironment::new();
+            env.put("x".to_string(), Value::Integer(6));
+            let results = runner.execute_multiple_with_env(&srcs, &mut env).unwrap();
+            match &expected {
+                None => expected = Some(results),
+                Some(e) => assert_eq!(e, &results, "{:?} {:?}", level, mode),
+            }
+        }
+    }
+
+    // 默认不做优化，仍会读取 `y`
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&["b = if(2 > 1, 3, y) * 2"]).unwrap();
+    assert_eq!(2, chunk.variables().unwrap().names().len());
+    // 折叠后不再读取 `y`，常量也不再进入字节码
+    runner.set_opt_level(OptLevel::Basic);
+    let chunk = runner.compile_source(&["b = if(2 > 1, 3, y) * 2"]).unwrap();
+    assert_eq!(vec!["b".to_string()], chunk.variables().unwrap().names());
+}
+
+#[test]
+fn test_fused_instructions() {
+    let srcs = [
+        "a = i + j",
+        "b = d * e - i",
+        "c = i > 2 && d <= 1.5",
+        "f = s + t",
+        "g = (i + d) * 2 / 4",
+        "h = i == j || d != e",
+        "k = j % i + e ** 2",
+    ];
+    let mut expected = None;
+    for mode in [
+        ExecuteMode::SyntaxTree,
+        ExecuteMode::ChunkVM,
+        ExecuteMode::Closure,
+    ] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        let mut env = DefaultEnvironment::new();
+        env.put("i".to_string(), Value::Integer(3));
+        env.put("j".to_string(), Value::Integer(7));
+        env.put("d".to_string(), Value::Double(1.5));
+        env.put("e".to_string(), Value::Double(-2.0));
+        env.put("s".to_string(), Value::String("s".to_string()));
+        env.put("t".to_string(), Value::String("t".to_string()));
+        let results = runner.execute_multiple_with_env(&srcs, &mut env).unwrap();
+        match &expected {
+            None => expected = Some(results),
+            Some(e) => assert_eq!(e, &results),
+        }
+    }
+
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::ChunkVM);
+    let mut env = DefaultEnvironment::new();
+    env.put("i".to_string(), Value::Integer(3));
+    match runner.execute_with_env("i / 0", &mut env) {
+        Err(RspError::RuntimeError { message, .. }) => assert_eq!("Division by zero", message),
+        other => panic!("unexpected: {:?}", other),
+    }
+    match runner.execute_with_env("i + x", &mut env) {
+        Err(RspError::RuntimeError { message, .. }) => {
+            assert!(message.contains("Undefined variable: x"))
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
+#[test]
+fn test_closure_mode() {
+    let srcs = [
+        "total = price * qty - discount",
+        "discount = if(qty > 10, abs(-5), 0)",
+        "flag = qty > 3 && price || 0",
+        "label = \"n=\" + qty",
+    ];
+    let mut expected = None;
+    for mode in [ExecuteMode::ChunkVM, ExecuteMode::Closure] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        let mut env = DefaultEnvironment::new();
+        env.put("price".to_string(), Value::Double(2.5));
+        env.put("qty".to_string(), Value::Integer(12));
+        let results = runner.execute_multiple_with_env(&srcs, &mut env).unwrap();
+        assert_eq!(Value::Double(25.0), *env.get("total").unwrap());
+        match &expected {
+            None => expected = Some(results),
+            Some(e) => assert_eq!(e, &results),
+        }
+    }
+
+    // 编译一次，按槽位在多个线程上反复执行
+    let mut runner = RspRunner::new();
+    let program = Arc::new(runner.compile_closures(&srcs).unwrap());
+    let price = program.slot("price").unwrap();
+    let qty = program.slot("qty").unwrap();
+    let total = program.slot("total").unwrap();
+    let handles: Vec<_> = (0..4)
+        .map(|n| {
+            let program = Arc::clone(&program);
+            thread::spawn(move || {
+                let mut runner = RspRunner::new();
+                let mut env = DefaultSlotEnvironment::new(program.slot_count());
+                let mut totals = Vec::new();
+                for q in 0..100 {
+                    env.clear();
+                    env.set(price, Value::Double(n as f64));
+                    env.set(qty, Value::Integer(q));
+                    runner.run_closures_slots(&program, &mut env).unwrap();
+                    totals.push(env.get_slot(total).unwrap().clone());
+                }
+                totals
+            })
+        })
+        .collect();
+    for (n, handle) in handles.into_iter().enumerate() {
+        let totals = handle.join().unwrap();
+        assert_eq!(Value::Double(n as f64 * 20.0 - 5.0), totals[20]);
+    }
+
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::Closure);
+    match runner.execute("y + 1") {
+        Err(RspError::RuntimeError { message, .. }) => assert_eq!("Undefined variable: y", message),
+        other => panic!("unexpected: {:?}", other),
+    }
+    match runner.execute("nope(1)") {
+        Err(RspError::CompileError { message, .. }) => {
+            assert_eq!("Undefined function: nope", message)
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+}
@@ -3,9 +3,9 @@ mod common;
 use common::TestHelper;
 use rand::Rng;
 use rspression::Chunk;
-use rspression::RspRunner;
 use rspression::ir::{Analyzer, ExprInfo};
-use rspression::{DefaultEnvironment, Environment};
+use rspression::{DefaultEnvironment, Environment, Value};
+use rspression::{RspError, RspRunner};
 
 const FORMULA_BATCHES: usize = 10000;
 const DIRECTORY: &str = "SerializeTest";
@@ -118,3 +118,82 @@ fn check_result(env: &DefaultEnvironment) {
         assert_eq!(9.0, env.get(&format!("G{}", index)).unwrap().as_double());
     }
 }
+
+#[test]
+fn test_chunk_format() {
+    let srcs = vec!["x = a + b * 2", "y = x * \"s\""];
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&srcs).unwrap();
+    let bytes = chunk.to_bytes();
+    assert_eq!(&Chunk::MAGIC, &bytes[0..4]);
+
+    let loaded = Chunk::from_bytes(&bytes).unwrap();
+    assert_eq!(chunk.codes, loaded.codes);
+    assert_eq!(chunk.constants, loaded.constants);
+    assert_eq!(chunk.vars, loaded.vars);
+
+    // 数据不完整
+    for len in [0, 3, 10, bytes.len() - 1] {
+        assert!(matches!(
+            Chunk::from_bytes(&bytes[..len]),
+            Err(RspError::ChunkError { .. })
+        ));
+    }
+
+    // 数据损坏
+    let mut corrupted = bytes.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0xFF;
+    assert!(matches!(
+        Chunk::from_bytes(&corrupted),
+        Err(RspError::ChunkError { .. })
+    ));
+
+    // 更高的格式版本和指令集版本
+    let mut newer = bytes.clone();
+    newer[4..6].copy_from_slice(&(Chunk::FORMAT_VERSION + 1).to_be_bytes());
+    assert!(Chunk::from_bytes(&newer).is_err());
+    let mut newer = bytes.clone();
+    newer[6..8].copy_from_slice(&(Chunk::OPCODE_VERSION + 1).to_be_bytes());
+    assert!(Chunk::from_bytes(&newer).is_err());
+}
+
+#[test]
+fn test_legacy_chunk_format() {
+    let srcs = vec!["x = a + b * 2"];
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&srcs).unwrap();
+
+    // 没有文件头的旧格式
+    let mut legacy = Vec::new();
+    for part in [&chunk.codes, &chunk.constants, &chunk.vars] {
+        legacy.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        legacy.extend_from_slice(part);
+    }
+    let loaded = Chunk::from_bytes(&legacy).unwrap();
+    let mut env = DefaultEnvironment::new();
+    env.put("a".to_string(), 1.into());
+    env.put("b".to_string(), 2.into());
+    runner.run_chunk(&loaded, &mut env).unwrap();
+    assert_eq!(5, env.get("x").unwrap().as_integer());
+
+    assert!(Chunk::from_bytes(&legacy[..legacy.len() - 1]).is_err());
+}
+
+#[test]
+fn test_constant_kinds() {
+    // 超过 u16::MAX 字节的长文本，以及值相同但类型不同的常量
+    let template = "模板".repeat(40000);
+    let src = format!("x = \"{}\" + a", template);
+    let srcs = vec![src.as_str(), "y = 1 + \"1\"", "z = \"1\" + 1"];
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&srcs).unwrap();
+    let loaded = Chunk::from_bytes(&chunk.to_bytes()).unwrap();
+
+    let mut env = DefaultEnvironment::new();
+    env.put("a".to_string(), Value::String("尾".to_string()));
+    runner.run_chunk(&loaded, &mut env).unwrap();
+    assert_eq!(format!("{}尾", template), env.get("x").unwrap().to_string());
+    assert_eq!(runner.execute("1 + \"1\"").unwrap(), *env.get("y").unwrap());
+    assert_eq!(runner.execute("\"1\" + 1").unwrap(), *env.get("z").unwrap());
+}
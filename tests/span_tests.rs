@@ -0,0 +1,285 @@
+use rspression::expr::Expr;
+use rspression::vm::VM;
+use rspression::{
+    Batch, Chunk, DefaultEnvironment, Environment, ExecuteMode, Parser, RspError, RspRunner,
+    Scanner, Span, TokenType, Value,
+};
+
+fn get_env() -> DefaultEnvironment {
+    let mut env = DefaultEnvironment::new();
+    env.put("a".to_string(), Value::Integer(6));
+    env.put("b".to_string(), Value::Integer(0));
+    env.put("s".to_string(), Value::String("str".to_string()));
+    env
+}
+
+/// 出错位置在源码中对应的文本
+fn error_text(src: &str, err: &RspError) -> String {
+    let span = err.span().unwrap_or_else(|| panic!("no span: {:?}", err));
+    span.slice(src).unwrap().to_string()
+}
+
+#[test]
+fn test_token_spans() {
+    let src = "a1 + \"x\"\n  >= 变量";
+    let tokens = Scanner::new(src).scan_tokens().unwrap();
+    let spans: Vec<(TokenType, Span)> = tokens
+        .iter()
+        .map(|t| (t.token_type.clone(), t.span))
+        .collect();
+    assert_eq!(
+        vec![
+            (TokenType::Identifier, Span::new(0, 2, 1, 1)),
+            (TokenType::Plus, Span::new(3, 1, 1, 4)),
+            (TokenType::String, Span::new(5, 3, 1, 6)),
+            (TokenType::GreaterEqual, Span::new(11, 2, 2, 3)),
+            // 列号按字符计算，偏移和长度按字节计算
+            (TokenType::Identifier, Span::new(14, 6, 2, 6)),
+            (TokenType::Eof, Span::new(20, 0, 2, 8)),
+        ],
+        spans
+    );
+    for token in &tokens {
+        assert_eq!(Some(&*token.lexeme), token.span.slice(src));
+        assert_eq!(token.line, token.span.line as usize);
+    }
+}
+
+#[test]
+fn test_expr_spans() {
+    let src = "x = if(a > 1, -a, abs(b)) + o.p * 2";
+    let expr = Parser::new(src).parse().unwrap();
+    let text = |expr: &Expr| expr.span().slice(src).unwrap().to_string();
+    assert_eq!(src, text(&expr));
+    let Expr::Assign(assign) = &expr else {
+        panic!("expected assignment");
+    };
+    assert_eq!("x", text(&assign.left));
+    let Expr::Binary(sum) = &*assign.right else {
+        panic!("expected binary");
+    };
+    assert_eq!("if(a > 1, -a, abs(b)) + o.p * 2", text(&assign.right));
+    assert_eq!("o.p * 2", text(&sum.right));
+    let Expr::If(if_expr) = &*sum.left else {
+        panic!("expected if");
+    };
+    assert_eq!("if(a > 1, -a, abs(b))", text(&sum.left));
+    assert_eq!("a > 1", text(&if_expr.condition));
+    assert_eq!("-a", text(&if_expr.then_branch));
+    assert_eq!("abs(b)", text(if_expr.else_branch.as_ref().unwrap()));
+    let Expr::Binary(product) = &*sum.right else {
+        panic!("expected binary");
+    };
+    assert_eq!("o.p", text(&product.left));
+    assert_eq!("2", text(&product.right));
+
+    // 括号不计入子表达式的位置，转换为不借用源字符串的语法树后位置不变
+    let expr = Parser::new("(1 + 2) * 3").parse().unwrap();
+    let Expr::Binary(binary) = &expr else {
+        panic!("expected binary");
+    };
+    assert_eq!(Span::new(1, 5, 1, 2), binary.left.span());
+    assert_eq!(Span::new(1, 10, 1, 2), expr.span());
+    assert_eq!(expr.span(), expr.into_owned().span());
+}
+
+#[test]
+fn test_parse_error_spans() {
+    let cases = [
+        ("1 + $", "$", 1, 5),
+        ("a = \"open", "\"open", 1, 5),
+        ("1 +\n  * 2", "*", 2, 3),
+        ("if(a > 1, 2 3)", "3", 1, 13),
+        ("a 1", "1", 1, 3),
+        ("12.", "12.", 1, 1),
+    ];
+    for (src, text, line, column) in cases {
+        let err = Parser::new(src).parse().err().unwrap();
+        let RspError::ParseError { span, .. } = &err else {
+            panic!("{}: {:?}", src, err);
+        };
+        assert_eq!((line, column), (span.line, span.column), "{}", src);
+        assert_eq!(text, error_text(src, &err), "{}", src);
+        assert!(
+            err.to_string()
+                .starts_with(&format!("Parse error at line {}, column {}", line, column)),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn test_runtime_error_spans() {
+    let cases = [
+        ("c = a / b", "b"),
+        ("c = (a + 1) % (b * 2)", "b * 2"),
+        ("c = a + (s - 1)", "s - 1"),
+        ("c = -s", "-s"),
+        ("c = a.p", "a.p"),
+        // 优化时折叠的常量保留原来的位置
+        ("c = a / (2 - 2)", "2 - 2"),
+    ];
+    for mode in [ExecuteMode::SyntaxTree, ExecuteMode::Closure] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        for (src, text) in cases {
+            let err = runner.execute_with_env(src, &mut get_env()).unwrap_err();
+            assert!(
+                matches!(err, RspError::RuntimeError { .. }),
+                "{:?}: {}",
+                mode,
+                err
+            );
+            assert_eq!(text, error_text(src, &err), "{:?}: {}", mode, src);
+        }
+    }
+
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::Closure);
+    let src = "c = a + missing";
+    let err = runner.execute_with_env(src, &mut get_env()).unwrap_err();
+    assert_eq!("missing", error_text(src, &err));
+}
+
+#[test]
+fn test_compile_error_spans() {
+    for mode in [ExecuteMode::ChunkVM, ExecuteMode::Closure] {
+        let mut runner = RspRunner::new();
+        runner.set_execute_mode(mode);
+        for (src, text) in [
+            ("1 + nope(a)", "nope"),
+            ("1 + abs(a, b)", "abs(a, b)"),
+            ("o.f(1)", "o.f"),
+        ] {
+            let err = runner.execute_with_env(src, &mut get_env()).unwrap_err();
+            assert!(
+                matches!(err, RspError::CompileError { .. }),
+                "{:?}: {}",
+                mode,
+                err
+            );
+            assert_eq!(text, error_text(src, &err), "{:?}: {}", mode, src);
+        }
+    }
+}
+
+#[test]
+fn test_vm_error_spans() {
+    let cases = [
+        ("c = a / b", "b"),
+        ("c = (a + 1) % (b * 2)", "b * 2"),
+        ("c = a + (s - 1)", "s - 1"),
+        ("c = s * 2", "s * 2"),
+        ("c = -s", "-s"),
+        ("c = a.p", "a.p"),
+        ("c = a + missing", "missing"),
+        ("c = missing * 2", "missing"),
+    ];
+    let mut runner = RspRunner::new();
+    runner.set_execute_mode(ExecuteMode::ChunkVM);
+    for (src, text) in cases {
+        // 直接交给虚拟机执行，不经过运行器对未定义变量的处理
+        let chunk = runner.compile_source(&[src]).unwrap();
+        let Err(err) = VM::new().execute_with_env(&chunk, &mut get_env()) else {
+            panic!("expected error: {}", src);
+        };
+        assert!(matches!(err, RspError::RuntimeError { .. }), "{}", err);
+        assert_eq!(text, error_text(src, &err), "{}", src);
+    }
+
+    // 多条公式时指向出错的那一条
+    let srcs = ["x = a + 1", "y = x / b"];
+    let chunk = runner.compile_source(&srcs).unwrap();
+    let map = chunk.source_map().unwrap().unwrap();
+    let err = runner.run_chunk(&chunk, &mut get_env()).unwrap_err();
+    assert!(map.entries().iter().any(|e| {
+        e.formula == 1
+            && e.operands
+                .is_some_and(|(_, right)| Some(right) == err.span())
+    }));
+    assert_eq!("b", err.span().unwrap().slice(srcs[1]).unwrap());
+}
+
+#[test]
+fn test_batch_error_spans() {
+    let mut batch = Batch::new(3);
+    batch.set_column("a", vec![1, 2, 3]).unwrap();
+    batch.set_column("b", vec![1, 0, 1]).unwrap();
+    let mut runner = RspRunner::new();
+    for (src, text) in [
+        ("a / b", "b"),
+        ("a + missing", "missing"),
+        ("a * (b - 1) / (a - 2)", "a - 2"),
+    ] {
+        let err = runner.execute_batch(&[src], &mut batch).unwrap_err();
+        assert_eq!(text, error_text(src, &err), "{}", src);
+    }
+}
+
+#[test]
+fn test_strip_debug_info() {
+    let src = "c = a / b";
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&[src]).unwrap();
+    assert!(chunk.has_debug_info());
+    assert!(!chunk.source_map().unwrap().unwrap().is_empty());
+
+    // 调试信息随字节码一起序列化
+    let bytes = chunk.to_bytes();
+    let loaded = Chunk::from_bytes(&bytes).unwrap();
+    assert_eq!(chunk.debug, loaded.debug);
+    assert_eq!(
+        Chunk::FLAG_DEBUG,
+        u32::from_be_bytes(bytes[8..12].try_into().unwrap())
+    );
+    let err = runner.run_chunk(&loaded, &mut get_env()).unwrap_err();
+    assert_eq!("b", error_text(src, &err));
+
+    // 去掉调试信息后字节码更小，执行结果不变，错误只带有公式序号
+    let mut stripped = loaded.clone();
+    stripped.strip_debug_info();
+    let stripped_bytes = stripped.to_bytes();
+    assert!(stripped_bytes.len() < bytes.len());
+    assert_eq!(
+        0,
+        u32::from_be_bytes(stripped_bytes[8..12].try_into().unwrap())
+    );
+    let stripped = Chunk::from_bytes(&stripped_bytes).unwrap();
+    assert!(!stripped.has_debug_info());
+    assert_eq!(None, stripped.source_map().unwrap());
+    let stripped_err = runner.run_chunk(&stripped, &mut get_env()).unwrap_err();
+    assert_eq!(None, stripped_err.span());
+    assert_eq!(err.to_string(), stripped_err.to_string());
+    let mut env = get_env();
+    env.put("b".to_string(), Value::Integer(2));
+    assert_eq!(
+        runner.run_chunk(&loaded, &mut env.clone()).unwrap(),
+        runner.run_chunk(&stripped, &mut env).unwrap()
+    );
+
+    // 编译时关闭调试信息，与去掉调试信息的结果相同
+    runner.set_debug_info(false);
+    let chunk = runner.compile_source(&[src]).unwrap();
+    assert_eq!(stripped_bytes, chunk.to_bytes());
+}
+
+#[test]
+fn test_invalid_source_map() {
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&["c = a / b", "d = c + 1"]).unwrap();
+    let mut corrupted = chunk.clone();
+    // 条目数比实际多
+    corrupted.debug[3] += 1;
+    let err = runner.load_chunk(&corrupted).unwrap_err();
+    assert!(matches!(err, RspError::ChunkError { .. }), "{}", err);
+    // 偏移超出字节码
+    let mut corrupted = chunk.clone();
+    corrupted.debug[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+    let err = runner.load_chunk(&corrupted).unwrap_err();
+    assert!(err.to_string().contains("Invalid source map"), "{}", err);
+    // 不加载直接执行时同样会检查调试信息
+    let mut env = DefaultEnvironment::new();
+    let err = runner.run_chunk(&corrupted, &mut env).unwrap_err();
+    assert!(err.to_string().contains("Invalid source map"), "{}", err);
+}
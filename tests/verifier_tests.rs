@@ -0,0 +1,252 @@
+use rspression::chunk::ChunkWriter;
+use rspression::vm::{OpCode, Verifier};
+use rspression::{Chunk, DefaultEnvironment, Program, RspError, RspRunner, Value};
+
+fn build<F: FnOnce(&mut ChunkWriter)>(f: F) -> Chunk {
+    let mut writer = ChunkWriter::new();
+    f(&mut writer);
+    writer.flush()
+}
+
+fn assert_rejected(chunk: &Chunk, fragment: &str) {
+    match Verifier::verify(chunk) {
+        Err(RspError::VerifyError { message, .. }) => {
+            assert!(
+                message.contains(fragment),
+                "unexpected message: {}",
+                message
+            )
+        }
+        other => panic!(
+            "expected verify error containing {:?}, got {:?}",
+            fragment, other
+        ),
+    }
+}
+
+#[test]
+fn test_compiled_chunks_pass() {
+    let srcs = [
+        "a = m + n",
+        "b = a * 2 > 10 && m < 3 || !(n == 1)",
+        "c = abs(a - b) + abs(-m)",
+        "x = y = a + b * c",
+        "\"s\" + 1",
+    ];
+    let mut runner = RspRunner::new();
+    let chunk = runner.compile_source(&srcs).unwrap();
+    Verifier::verify(&chunk).unwrap();
+}
+
+#[test]
+fn test_invalid_instruction() {
+    let chunk = build(|w| {
+        w.write_byte(200);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "Invalid instruction");
+}
+
+#[test]
+fn test_truncated_operand() {
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_short(0);
+    });
+    assert_rejected(&chunk, "Truncated operand");
+}
+
+#[test]
+fn test_constant_out_of_range() {
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::Constant);
+        w.write_int(3);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "out of range");
+}
+
+#[test]
+fn test_global_name_not_string() {
+    let chunk = build(|w| {
+        let index = w.add_constant(Value::Integer(1)).unwrap();
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::GetGlobal);
+        w.write_int(index as i32);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "must be a string constant");
+}
+
+#[test]
+fn test_fused_operands() {
+    let fused = |op: u8| {
+        build(|w| {
+            let x = w.add_constant(Value::String("x".to_string())).unwrap();
+            let k = w.add_constant(Value::Integer(10)).unwrap();
+            w.write_code(OpCode::Begin);
+            w.write_int(0);
+            w.write_code(OpCode::GlobalConstOp);
+            w.write_int(x as i32);
+            w.write_int(k as i32);
+            w.write_byte(op);
+            w.write_code(OpCode::ConstOp);
+            w.write_int(k as i32);
+            w.write_code(OpCode::Add);
+            w.write_code(OpCode::End);
+            w.write_code(OpCode::Exit);
+        })
+    };
+    Verifier::verify(&fused(OpCode::Greater as u8)).unwrap();
+    assert_rejected(&fused(OpCode::Not as u8), "Invalid binary operator");
+
+    let chunk = build(|w| {
+        let k = w.add_constant(Value::Integer(10)).unwrap();
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::GlobalGlobalOp);
+        w.write_int(k as i32);
+        w.write_int(k as i32);
+        w.write_code(OpCode::Add);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "must be a string constant");
+}
+
+#[test]
+fn test_jump_target() {
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::Jump);
+        // 跳到 End 的操作数中间
+        w.write_int(2);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "Invalid jump target");
+
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::Jump);
+        w.write_int(-6);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "Negative jump offset");
+}
+
+#[test]
+fn test_stack_balance() {
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::Add);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "Stack underflow");
+
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "Stack depth at End");
+
+    // 条件为真时多压入一个值，两条路径在 Add 处的栈深度不一致
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::JumpIfFalse);
+        w.write_int(1);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::Add);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "Inconsistent stack depth");
+}
+
+#[test]
+fn test_block_structure() {
+    let chunk = build(|w| {
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "outside a formula block");
+
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::End);
+    });
+    assert_rejected(&chunk, "Missing Exit");
+}
+
+#[test]
+fn test_formula_index() {
+    let block = |w: &mut ChunkWriter, index: i32| {
+        w.write_code(OpCode::Begin);
+        w.write_int(index);
+        w.write_code(OpCode::True);
+        w.write_code(OpCode::End);
+    };
+    let chunk = build(|w| {
+        block(w, -1);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "Formula index -1 out of range");
+
+    let chunk = build(|w| {
+        block(w, Program::MAX_FORMULAS as i32);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "out of range");
+
+    let chunk = build(|w| {
+        block(w, 1);
+        block(w, 0);
+        block(w, 1);
+        w.write_code(OpCode::Exit);
+    });
+    assert_rejected(&chunk, "Duplicate formula index: 1");
+
+    // 按目标变量编译时序号可以不连续
+    let chunk = build(|w| {
+        block(w, 3);
+        block(w, 0);
+        w.write_code(OpCode::Exit);
+    });
+    Verifier::verify(&chunk).unwrap();
+}
+
+#[test]
+fn test_runner_verifies_chunk() {
+    let chunk = build(|w| {
+        w.write_code(OpCode::Begin);
+        w.write_int(0);
+        w.write_code(OpCode::Add);
+        w.write_code(OpCode::End);
+        w.write_code(OpCode::Exit);
+    });
+    let mut runner = RspRunner::new();
+    let mut env = DefaultEnvironment::new();
+    let result = runner.run_chunk(&chunk, &mut env);
+    assert!(matches!(result, Err(RspError::VerifyError { .. })));
+}